@@ -0,0 +1,661 @@
+//! Components attached to the entities in the world.
+
+use std::f32::consts::PI;
+use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
+use std::time::Duration;
+
+use derive_more::Sub;
+use quicksilver::geom::Vector;
+use quicksilver::graphics::Color;
+use quicksilver::lifecycle::Key;
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
+use specs::prelude::*;
+use specs::Component;
+use specs_hierarchy::Parent;
+
+use crate::input::{KeyMap, Keys};
+#[cfg(feature = "serialize")]
+use crate::serialize::{vectors, ColorDef, VectorDef};
+
+/// How far a ship without a `Hull` reaches from its middle.
+pub const LINE_EXTENT: f32 = 10.0;
+/// Points for landing in a landing area of the usual size.
+const LANDING_POINTS: f32 = 1_000.0;
+
+/// A landing area.
+///
+/// The smaller ones are harder to get into, so they are worth more points.
+#[derive(Copy, Clone, Component, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Deserialize, Serialize))]
+#[storage(HashMapStorage)]
+pub struct Landing {
+    /// Multiplies the landing distance of the rules.
+    pub size: f32,
+    pub points: u32,
+}
+
+impl Landing {
+    /// A landing area of the given size, worth the points that go with the size.
+    pub fn new(size: f32) -> Self {
+        Landing {
+            size,
+            points: (LANDING_POINTS / size).round() as u32,
+        }
+    }
+
+    /// How close a ship needs to get to land here, with the landing distance of the rules.
+    pub fn radius(&self, land_distance: f32) -> f32 {
+        land_distance * self.size
+    }
+}
+
+impl Default for Landing {
+    fn default() -> Self {
+        Landing::new(1.0)
+    }
+}
+
+/// A ship holding still in a landing area, to land there.
+#[derive(Copy, Clone, Component, Debug)]
+#[storage(HashMapStorage)]
+pub struct Capture {
+    pub pad: Entity,
+    /// How long the ship has been holding there.
+    pub held: Duration,
+}
+
+#[derive(Copy, Clone, Component, Debug)]
+#[cfg_attr(feature = "serialize", derive(Deserialize, Serialize))]
+#[storage(HashMapStorage)]
+pub struct Ship {
+    pub keys: KeyMap,
+    pub temperature: f32,
+    pub max_temp: f32,
+    pub temp_dec: f32,
+    /// Fuel left, in seconds of a single thruster firing.
+    pub fuel: f32,
+    pub max_fuel: f32,
+    /// Each repair of a thruster uses one.
+    pub spare_parts: u32,
+    /// Multiplies the G limit of the rules.
+    pub strength: f32,
+}
+
+impl Ship {
+    /// The thrusters fire only while there's some fuel left.
+    pub fn has_fuel(&self) -> bool {
+        self.fuel > 0.0
+    }
+
+    /// Is the ship's thruster firing with these keys pressed?
+    pub fn fires(
+        &self,
+        thruster: &Thruster,
+        interference: Option<&Interference>,
+        keys: &Keys,
+    ) -> bool {
+        let key = interference.map_or(thruster.key, |i| i.wiring(&self.keys, thruster.key));
+        self.has_fuel() && thruster.condition != Condition::Dead && keys.contains(&key)
+    }
+}
+
+/// The outline of a ship's hull, relative to its center.
+///
+/// Ships without it are drawn as a simple line, `LINE_EXTENT` to each side.
+#[derive(Clone, Component, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Deserialize, Serialize))]
+#[storage(HashMapStorage)]
+pub struct Hull(#[cfg_attr(feature = "serialize", serde(with = "vectors"))] pub Vec<Vector>);
+
+impl Hull {
+    /// How far the hull reaches from the middle of the ship.
+    pub fn extent(&self) -> f32 {
+        self.0.iter().map(|point| point.len()).fold(0.0, f32::max)
+    }
+}
+
+/// How the magnetic anomalies a ship is in disturb its controls.
+///
+/// Sits between the keys and the thrusters, missing when nothing disturbs the ship.
+#[derive(Copy, Clone, Component, Debug, PartialEq)]
+#[storage(HashMapStorage)]
+pub struct Interference {
+    /// Multiplies the push of the thrusters.
+    pub response: f32,
+    /// The thrusters are wired the other way around, left to right and forward to back.
+    pub reversed: bool,
+    /// The ship turns by itself, in degrees per second squared.
+    pub drift: f32,
+}
+
+impl Interference {
+    /// The key firing the thruster normally fired by the given one.
+    pub fn wiring(&self, keys: &KeyMap, key: Key) -> Key {
+        if !self.reversed {
+            return key;
+        }
+        let swapped = [
+            (keys.left, keys.right),
+            (keys.right, keys.left),
+            (keys.forward, keys.back),
+            (keys.back, keys.forward),
+        ];
+        swapped
+            .iter()
+            .find(|(from, _)| *from == key)
+            .map_or(key, |(_, to)| *to)
+    }
+}
+
+impl Display for Interference {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        let mut disturbed = Vec::new();
+        if self.response < 1.0 {
+            disturbed.push("thrusters weakened");
+        }
+        if self.reversed {
+            disturbed.push("controls reversed");
+        }
+        if self.drift != 0.0 {
+            disturbed.push("rotation drifting");
+        }
+        write!(fmt, "{}", disturbed.join(", "))
+    }
+}
+
+impl Default for Interference {
+    fn default() -> Self {
+        Interference {
+            response: 1.0,
+            reversed: false,
+            drift: 0.0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Component, Debug)]
+#[cfg_attr(feature = "serialize", derive(Deserialize, Serialize))]
+#[storage(HashMapStorage)]
+pub struct Rotation(pub f32);
+
+#[derive(Copy, Clone, Component, Debug)]
+#[cfg_attr(feature = "serialize", derive(Deserialize, Serialize))]
+#[storage(HashMapStorage)]
+pub struct RotationSpeed(pub f32);
+
+/// How well a thruster works, worsened by impacts and overheating.
+#[derive(Copy, Clone, Debug, Default, Eq, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serialize", derive(Deserialize, Serialize))]
+pub enum Condition {
+    #[default]
+    Intact,
+    /// Pushes only half as much.
+    Degraded,
+    /// Doesn't fire at all.
+    Dead,
+}
+
+impl Condition {
+    /// The condition after taking another hit.
+    pub fn worse(self) -> Self {
+        match self {
+            Condition::Intact => Condition::Degraded,
+            Condition::Degraded | Condition::Dead => Condition::Dead,
+        }
+    }
+
+    /// The condition after a repair.
+    pub fn better(self) -> Self {
+        match self {
+            Condition::Intact | Condition::Degraded => Condition::Intact,
+            Condition::Dead => Condition::Degraded,
+        }
+    }
+
+    /// Multiplies the push of the thruster.
+    pub fn push(self) -> f32 {
+        match self {
+            Condition::Intact => 1.0,
+            Condition::Degraded => 0.5,
+            Condition::Dead => 0.0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Thruster {
+    pub ship: Entity,
+    pub position: Vector,
+    pub direction: f32,
+    pub len: f32,
+    // Add force and rotation force, the latter computed from the other info
+    pub key: Key,
+    pub push_direction: f32,
+    pub push: f32,
+    pub rotation: f32,
+    pub heating: f32,
+    pub condition: Condition,
+}
+
+impl Thruster {
+    /// The change of speed per second the thruster causes when firing, for the ship's rotation.
+    ///
+    /// It pushes the ship the opposite way.
+    pub fn thrust(&self, ship_rotation: f32) -> Vector {
+        Vector::from_angle(ship_rotation + self.push_direction) * self.push * self.condition.push()
+    }
+
+    /// Where the end of the nozzle is, relative to the ship's center.
+    pub fn nozzle(&self, ship_rotation: f32) -> Vector {
+        let tip = self.position + Vector::from_angle(self.direction) * self.len;
+        Vector::from_angle(ship_rotation + tip.angle()) * tip.len()
+    }
+}
+
+impl Component for Thruster {
+    type Storage = FlaggedStorage<Self, HashMapStorage<Self>>;
+}
+
+impl Parent for Thruster {
+    fn parent_entity(&self) -> Entity {
+        self.ship
+    }
+}
+
+#[derive(Copy, Clone, Component, Debug)]
+#[cfg_attr(feature = "serialize", derive(Deserialize, Serialize))]
+#[storage(VecStorage)]
+pub struct Star {
+    #[cfg_attr(feature = "serialize", serde(with = "ColorDef"))]
+    pub color: Color,
+    pub size: f32,
+}
+
+/// A small body with a tail pointing away from the nearest star.
+///
+/// It moves by the same gravity as everything else (it needs the usual `Position`, `Speed` and
+/// `Mass` for that), this only adds the looks.
+#[derive(Copy, Clone, Component, Debug)]
+#[cfg_attr(feature = "serialize", derive(Deserialize, Serialize))]
+#[storage(HashMapStorage)]
+pub struct Comet {
+    pub size: f32,
+    /// Tail length multiplier, the tail is this long divided by the distance to the nearest star.
+    pub tail: f32,
+    /// Don't let the tail grow over this, no matter how close the star is.
+    pub max_tail: f32,
+}
+
+/// A solid body a ship can sit on.
+///
+/// To make it spin, give it `Rotation` and `RotationSpeed`.
+#[derive(Copy, Clone, Component, Debug)]
+#[cfg_attr(feature = "serialize", derive(Deserialize, Serialize))]
+#[storage(HashMapStorage)]
+pub struct Planet {
+    #[cfg_attr(feature = "serialize", serde(with = "ColorDef"))]
+    pub color: Color,
+    pub radius: f32,
+}
+
+/// The ship sits on a planet and moves together with its surface.
+///
+/// The place is remembered in the planet's (rotating) coordinates.
+#[derive(Copy, Clone, Component, Debug)]
+#[storage(HashMapStorage)]
+pub struct Landed {
+    pub planet: Entity,
+    /// Angle of the ship's position, relative to the planet's rotation.
+    pub angle: f32,
+    pub distance: f32,
+    /// The ship's own rotation, relative to the planet's rotation.
+    pub rotation: f32,
+}
+
+/// The osculating orbit of a ship around the body that pulls it the most.
+///
+/// Recomputed every frame, it's what the orbit would be if nothing else interfered from now on.
+#[derive(Copy, Clone, Component, Debug)]
+#[storage(HashMapStorage)]
+pub struct Orbit {
+    pub body: Entity,
+    pub eccentricity: f32,
+    /// Distance of the closest point from the body's center.
+    pub periapsis: f32,
+    /// Distance of the farthest point, if the orbit is closed at all.
+    pub apoapsis: Option<f32>,
+    /// Direction from the body to the periapsis, in degrees.
+    pub periapsis_angle: f32,
+}
+
+impl Orbit {
+    /// Computes the orbit from the relative position and speed.
+    ///
+    /// The `mu` is the gravitational parameter (the gravity force multiplied by both masses, the
+    /// way our gravity works).
+    pub fn new(body: Entity, mu: f32, pos: Vector, speed: Vector) -> Option<Orbit> {
+        let dist = pos.len();
+        if dist <= 0.0 || mu <= 0.0 {
+            return None;
+        }
+        let speed_sq = speed.len2();
+        let radial = pos.x * speed.x + pos.y * speed.y;
+        let angular_momentum = pos.x * speed.y - pos.y * speed.x;
+        let ecc_vec = (pos * (speed_sq - mu / dist) - speed * radial) / mu;
+        let eccentricity = ecc_vec.len();
+        let semi_latus = angular_momentum * angular_momentum / mu;
+        let apoapsis = if eccentricity < 1.0 {
+            Some(semi_latus / (1.0 - eccentricity))
+        } else {
+            None
+        };
+
+        Some(Orbit {
+            body,
+            eccentricity,
+            periapsis: semi_latus / (1.0 + eccentricity),
+            apoapsis,
+            periapsis_angle: ecc_vec.angle(),
+        })
+    }
+
+    /// A point on the orbit, relative to the body.
+    ///
+    /// The angle is in degrees, measured from the periapsis. Returns `None` if the orbit doesn't
+    /// go in that direction at all (the open ones don't).
+    pub fn point(&self, angle: f32) -> Option<Vector> {
+        let semi_latus = self.periapsis * (1.0 + self.eccentricity);
+        let denominator = 1.0 + self.eccentricity * angle.to_radians().cos();
+        if denominator <= 0.0 {
+            return None;
+        }
+        Some(Vector::from_angle(self.periapsis_angle + angle) * (semi_latus / denominator))
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Deserialize, Serialize))]
+pub enum LagrangePoint {
+    L1,
+    L2,
+    L3,
+    L4,
+    L5,
+}
+
+impl LagrangePoint {
+    pub const ALL: [LagrangePoint; 5] = [
+        LagrangePoint::L1,
+        LagrangePoint::L2,
+        LagrangePoint::L3,
+        LagrangePoint::L4,
+        LagrangePoint::L5,
+    ];
+
+    /// Computes where the point is for the given pair of bodies.
+    ///
+    /// Uses the usual approximations valid when the secondary is much lighter than the primary.
+    /// The speed is the one of the secondary relative to the primary, it decides which of the
+    /// triangular points is the leading one (L4).
+    pub fn position(
+        self,
+        primary: (Vector, f32),
+        secondary: (Vector, f32),
+        speed: Vector,
+    ) -> Vector {
+        let (primary_pos, primary_mass) = primary;
+        let (secondary_pos, secondary_mass) = secondary;
+        let dist = secondary_pos - primary_pos;
+        let dir = dist.normalize();
+        let mass_ratio = secondary_mass / (primary_mass + secondary_mass);
+        let hill = dist.len() * (mass_ratio / 3.0).cbrt();
+        let leading = if dist.x * speed.y - dist.y * speed.x >= 0.0 {
+            60.0
+        } else {
+            -60.0
+        };
+        let triangle =
+            |angle: f32| primary_pos + Vector::from_angle(dist.angle() + angle) * dist.len();
+        match self {
+            LagrangePoint::L1 => secondary_pos - dir * hill,
+            LagrangePoint::L2 => secondary_pos + dir * hill,
+            LagrangePoint::L3 => primary_pos - dist * (1.0 + 5.0 * mass_ratio / 12.0),
+            LagrangePoint::L4 => triangle(leading),
+            LagrangePoint::L5 => triangle(-leading),
+        }
+    }
+}
+
+impl Display for LagrangePoint {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        write!(fmt, "{:?}", self)
+    }
+}
+
+/// Marks a secondary body (eg. a planet) as having Lagrange points with the primary.
+#[derive(Copy, Clone, Component, Debug)]
+#[storage(HashMapStorage)]
+pub struct Lagrange {
+    pub primary: Entity,
+}
+
+/// Keeps the entity (eg. a landing area) sitting in a Lagrange point.
+///
+/// The secondary needs to have the `Lagrange` component.
+#[derive(Copy, Clone, Component, Debug)]
+#[storage(HashMapStorage)]
+pub struct AtLagrange {
+    pub secondary: Entity,
+    pub point: LagrangePoint,
+}
+
+/// Where a `Zone` reaches, around the entity's `Position`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Deserialize, Serialize))]
+pub enum ZoneShape {
+    /// A rectangle of this size, centered on the position.
+    Rect {
+        #[cfg_attr(feature = "serialize", serde(with = "VectorDef"))]
+        size: Vector,
+    },
+    Circle { radius: f32 },
+}
+
+impl ZoneShape {
+    pub fn contains(&self, center: Vector, point: Vector) -> bool {
+        let rel = point - center;
+        match *self {
+            ZoneShape::Rect { size } => rel.x.abs() <= size.x / 2.0 && rel.y.abs() <= size.y / 2.0,
+            ZoneShape::Circle { radius } => rel.len2() <= radius * radius,
+        }
+    }
+}
+
+/// What a `Zone` does to the things inside.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Deserialize, Serialize))]
+pub enum ZoneEffect {
+    /// A solar wind or a similar force, accelerating everything inside.
+    ///
+    /// Works in the same units as the gravity.
+    Force {
+        #[cfg_attr(feature = "serialize", serde(with = "VectorDef"))]
+        accel: Vector,
+        /// Swing the acceleration back and forth with this period (in seconds), instead of
+        /// keeping it constant.
+        period: Option<f32>,
+    },
+    /// A magnetic anomaly, disturbing the controls of the ships inside.
+    Magnetic(Anomaly),
+}
+
+/// How a magnetic anomaly disturbs the controls.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Deserialize, Serialize))]
+pub enum Anomaly {
+    /// The thrusters push only this much of their usual force.
+    Weak(f32),
+    /// Left is right and forward is back.
+    Reversed,
+    /// The ship turns by itself, in degrees per second squared.
+    Drift(f32),
+}
+
+impl Anomaly {
+    /// Adds the anomaly to the interference of other ones.
+    pub fn disturb(self, interference: &mut Interference) {
+        match self {
+            Anomaly::Weak(response) => interference.response *= response,
+            Anomaly::Reversed => interference.reversed = !interference.reversed,
+            Anomaly::Drift(drift) => interference.drift += drift,
+        }
+    }
+}
+
+impl ZoneEffect {
+    /// The acceleration of the things inside at the given level time.
+    pub fn force(&self, time: Duration) -> Vector {
+        match *self {
+            ZoneEffect::Force { accel, period: None } => accel,
+            ZoneEffect::Force { accel, period: Some(period) } => {
+                accel * (time.as_secs_f32() / period * 2.0 * PI).sin()
+            }
+            ZoneEffect::Magnetic(_) => Vector::ZERO,
+        }
+    }
+}
+
+/// An area of the space affecting whatever flies through it.
+#[derive(Copy, Clone, Component, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Deserialize, Serialize))]
+#[storage(HashMapStorage)]
+pub struct Zone {
+    pub shape: ZoneShape,
+    pub effect: ZoneEffect,
+}
+
+/// What a `PowerUp` does to the ship that picks it up.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Deserialize, Serialize))]
+pub enum Boost {
+    /// Halves the gravity pulling the ship.
+    GravityDampener,
+    /// Doubles the push of the thrusters.
+    Booster,
+}
+
+impl Display for Boost {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match self {
+            Boost::GravityDampener => write!(fmt, "Gravity dampener"),
+            Boost::Booster => write!(fmt, "Booster"),
+        }
+    }
+}
+
+/// Something for the ships to pick up in flight, boosting them for a while.
+#[derive(Copy, Clone, Component, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Deserialize, Serialize))]
+#[storage(HashMapStorage)]
+pub struct PowerUp {
+    pub boost: Boost,
+    /// How long the boost lasts, in seconds.
+    pub duration: f32,
+}
+
+/// The boosts a ship picked up, with the time each has left.
+#[derive(Clone, Component, Debug, Default, PartialEq)]
+#[storage(HashMapStorage)]
+pub struct Effects(pub Vec<(Boost, Duration)>);
+
+impl Effects {
+    pub fn has(&self, boost: Boost) -> bool {
+        self.0.iter().any(|(active, _)| *active == boost)
+    }
+
+    /// Starts the boost, or makes it last longer if it's already active.
+    pub fn add(&mut self, boost: Boost, duration: Duration) {
+        match self.0.iter_mut().find(|(active, _)| *active == boost) {
+            Some((_, left)) => *left = (*left).max(duration),
+            None => self.0.push((boost, duration)),
+        }
+    }
+
+    /// Multiplies the gravity pulling the ship.
+    pub fn gravity(&self) -> f32 {
+        if self.has(Boost::GravityDampener) {
+            0.5
+        } else {
+            1.0
+        }
+    }
+
+    /// Multiplies the push of the thrusters.
+    pub fn push(&self) -> f32 {
+        if self.has(Boost::Booster) {
+            2.0
+        } else {
+            1.0
+        }
+    }
+}
+
+/// A place refilling the fuel of the ships hovering nearby.
+#[derive(Copy, Clone, Component, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Deserialize, Serialize))]
+#[storage(HashMapStorage)]
+pub struct FuelStation {
+    /// How close the ships need to hover.
+    pub range: f32,
+    /// How much fuel it gives, in seconds of a thruster firing per second.
+    pub rate: f32,
+}
+
+/// A ship being repaired, while its pilot holds the repair key.
+#[derive(Copy, Clone, Component, Debug, Default)]
+#[storage(HashMapStorage)]
+pub struct Repair {
+    /// How long the current repair step has been going.
+    pub progress: Duration,
+}
+
+/// What the structure of a ship goes through.
+#[derive(Copy, Clone, Component, Debug, Default, PartialEq)]
+#[storage(HashMapStorage)]
+pub struct Strain {
+    /// The acceleration the ship feels, in G.
+    pub g: f32,
+    /// How long the ship has been over the limit of the rules.
+    pub over: Duration,
+}
+
+/// A ship taking fuel from a station.
+#[derive(Copy, Clone, Component, Debug)]
+#[storage(HashMapStorage)]
+pub struct Refueling {
+    pub station: Entity,
+}
+
+/// Human readable name of a star, planet, landing area...
+#[derive(Clone, Component, Debug)]
+#[cfg_attr(feature = "serialize", derive(Deserialize, Serialize))]
+#[storage(HashMapStorage)]
+pub struct Name(pub String);
+
+#[derive(Copy, Clone, Component, Debug, Sub)]
+#[cfg_attr(feature = "serialize", derive(Deserialize, Serialize))]
+#[storage(VecStorage)]
+pub struct Position(#[cfg_attr(feature = "serialize", serde(with = "VectorDef"))] pub Vector);
+
+// Note: while we might have several things that can't move (therefore don't have speed), the
+// vector is small and the overhead for omitting empty ones is not worth it.
+#[derive(Copy, Clone, Component, Debug)]
+#[cfg_attr(feature = "serialize", derive(Deserialize, Serialize))]
+#[storage(VecStorage)]
+pub struct Speed(#[cfg_attr(feature = "serialize", serde(with = "VectorDef"))] pub Vector);
+
+#[derive(Copy, Clone, Component, Debug)]
+#[cfg_attr(feature = "serialize", derive(Deserialize, Serialize))]
+#[storage(VecStorage)]
+pub struct Mass(pub f32);
@@ -0,0 +1,624 @@
+//! Keyboard and gamepad input, and replays of it.
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt::Debug;
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs::{self, File};
+#[cfg(not(target_arch = "wasm32"))]
+use std::io::BufWriter;
+use std::io::Write as _;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use quicksilver::lifecycle::{GamepadAxis, GamepadButton, GamepadId, Key, PointerId};
+use specs::prelude::*;
+use specs::SystemData;
+
+use log::{error, info};
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
+
+use crate::physics::FrameDuration;
+use crate::ships::ShipDesign;
+use crate::state::{level, Difficulty, GameState, Players};
+
+pub type Keys = HashSet<Key>;
+
+/// Which keys control a ship.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Deserialize, Serialize))]
+pub struct KeyMap {
+    #[cfg_attr(feature = "serialize", serde(with = "crate::serialize::key"))]
+    pub forward: Key,
+    #[cfg_attr(feature = "serialize", serde(with = "crate::serialize::key"))]
+    pub back: Key,
+    #[cfg_attr(feature = "serialize", serde(with = "crate::serialize::key"))]
+    pub left: Key,
+    #[cfg_attr(feature = "serialize", serde(with = "crate::serialize::key"))]
+    pub right: Key,
+    #[cfg_attr(feature = "serialize", serde(with = "crate::serialize::key"))]
+    pub homing: Key,
+    #[cfg_attr(feature = "serialize", serde(with = "crate::serialize::key"))]
+    pub repair: Key,
+}
+
+impl KeyMap {
+    /// Puts the map together from its keys, in the order of [`keys`][KeyMap::keys].
+    pub fn from_keys([forward, back, left, right, homing, repair]: [Key; 6]) -> Self {
+        KeyMap { forward, back, left, right, homing, repair }
+    }
+
+    pub fn keys(&self) -> [Key; 6] {
+        [self.forward, self.back, self.left, self.right, self.homing, self.repair]
+    }
+
+    pub fn contains(&self, key: Key) -> bool {
+        self.keys().contains(&key)
+    }
+}
+
+const ARROW_KEYS: KeyMap = KeyMap {
+    forward: Key::Up,
+    back: Key::Down,
+    left: Key::Left,
+    right: Key::Right,
+    homing: Key::Home,
+    repair: Key::PageDown,
+};
+
+const WASD_KEYS: KeyMap = KeyMap {
+    forward: Key::W,
+    back: Key::S,
+    left: Key::A,
+    right: Key::D,
+    homing: Key::Q,
+    repair: Key::E,
+};
+
+/// The keys of each player, in order.
+pub const PLAYER_KEYS: [KeyMap; 2] = [ARROW_KEYS, WASD_KEYS];
+
+/// How far the stick needs to be tilted to count as pressed.
+pub const STICK_DEAD_ZONE: f32 = 0.5;
+
+/// Which gamepad controls which player's ship.
+///
+/// The gamepad input is turned into the keys of the player's `KeyMap`, so it works just like
+/// the keyboard and ends up in the replays too.
+#[derive(Clone, Debug, Default)]
+pub struct Gamepads {
+    pub players: [Option<GamepadId>; 2],
+}
+
+impl Gamepads {
+    pub fn player(&self, id: &GamepadId) -> Option<usize> {
+        self.players.iter().position(|pad| pad.as_ref() == Some(id))
+    }
+
+    /// Pairs the gamepad with the first player without one.
+    pub fn pair(&mut self, id: GamepadId) -> Option<usize> {
+        let player = self.players.iter().position(Option::is_none)?;
+        self.players[player] = Some(id);
+        Some(player)
+    }
+
+    pub fn unpair(&mut self, id: &GamepadId) {
+        if let Some(player) = self.player(id) {
+            self.players[player] = None;
+        }
+    }
+}
+
+/// An input that can hold a key down.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Source {
+    /// A key of the keyboard, as pressed (before the rebinding).
+    Keyboard(Key),
+    Button(GamepadId, GamepadButton),
+    Axis(GamepadId, GamepadAxis),
+    /// A finger on the on-screen controls.
+    Touch(PointerId),
+}
+
+/// The keys held by each of the inputs.
+///
+/// Several inputs may hold the same key, eg. the keyboard and a gamepad button of the same
+/// action. The key is let go once all of them let go of it. The `Keys` are made from these.
+#[derive(Clone, Debug, Default)]
+pub struct HeldKeys(HashMap<Source, Key>);
+
+impl HeldKeys {
+    /// The input now holds the key, or nothing if `None`.
+    pub fn set(&mut self, source: Source, key: Option<Key>) {
+        match key {
+            Some(key) => self.0.insert(source, key),
+            None => self.0.remove(&source),
+        };
+    }
+
+    /// The key the input holds.
+    pub fn get(&self, source: Source) -> Option<Key> {
+        self.0.get(&source).copied()
+    }
+
+    /// Does any of the inputs hold the key?
+    pub fn contains(&self, key: Key) -> bool {
+        self.0.values().any(|held| *held == key)
+    }
+
+    /// The input lets go of whatever key it held.
+    pub fn release(&mut self, source: Source) {
+        self.0.remove(&source);
+    }
+
+    /// Keeps only the inputs for which the condition holds.
+    pub fn retain<F: FnMut(&Source) -> bool>(&mut self, mut condition: F) {
+        self.0.retain(|source, _| condition(source));
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    /// The keys held by any of the inputs.
+    pub fn keys(&self) -> Keys {
+        self.0.values().copied().collect()
+    }
+}
+
+/// What the buttons and sticks of the gamepads do, the same for all of them.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PadMap {
+    /// The buttons of each action, in the order of `KeyMap::keys`.
+    pub buttons: [Vec<GamepadButton>; 6],
+    /// The stick axis turning the ship.
+    pub turn: Option<GamepadAxis>,
+    /// The stick axis firing the forward (when pushed up) and back thrusters.
+    pub thrust: Option<GamepadAxis>,
+}
+
+impl Default for PadMap {
+    fn default() -> Self {
+        PadMap {
+            buttons: [
+                vec![GamepadButton::DPadUp, GamepadButton::South, GamepadButton::RightTrigger],
+                vec![GamepadButton::DPadDown, GamepadButton::LeftTrigger],
+                vec![GamepadButton::DPadLeft, GamepadButton::LeftShoulder],
+                vec![GamepadButton::DPadRight, GamepadButton::RightShoulder],
+                vec![GamepadButton::North],
+                vec![GamepadButton::West],
+            ],
+            turn: Some(GamepadAxis::LeftStickX),
+            thrust: None,
+        }
+    }
+}
+
+impl PadMap {
+    /// The key a gamepad button stands for.
+    pub fn key(&self, keys: &KeyMap, button: GamepadButton) -> Option<Key> {
+        let action = self.buttons.iter().position(|buttons| buttons.contains(&button))?;
+        Some(keys.keys()[action])
+    }
+
+    /// The keys a stick axis stands for, the negative direction first.
+    pub fn axis_keys(&self, keys: &KeyMap, axis: GamepadAxis) -> Option<(Key, Key)> {
+        if self.turn == Some(axis) {
+            Some((keys.left, keys.right))
+        } else if self.thrust == Some(axis) {
+            // Pushing the stick up is positive
+            Some((keys.back, keys.forward))
+        } else {
+            None
+        }
+    }
+
+    /// Makes the button the only one of the action.
+    pub fn bind(&mut self, action: usize, button: GamepadButton) {
+        for buttons in &mut self.buttons {
+            buttons.retain(|bound| *bound != button);
+        }
+        self.buttons[action] = vec![button];
+    }
+}
+
+/// Keys that may influence the simulation, so they are stored in replays.
+///
+/// The replay stores a bit mask indexed by this table, so only append to it.
+pub const REPLAY_KEYS: [Key; 48] = [
+    Key::Up, Key::Down, Key::Left, Key::Right, Key::Home, Key::End, Key::PageUp, Key::PageDown,
+    Key::Insert, Key::Delete, Key::Space, Key::Return,
+    Key::LShift, Key::RShift, Key::LControl, Key::RControl,
+    Key::A, Key::B, Key::C, Key::D, Key::E, Key::F, Key::G, Key::H, Key::I, Key::J, Key::K,
+    Key::L, Key::M, Key::N, Key::O, Key::P, Key::Q, Key::R, Key::S, Key::T, Key::U, Key::V,
+    Key::W, Key::X, Key::Y, Key::Z,
+    Key::Key1, Key::Key2, Key::Key3, Key::Key4, Key::Key5, Key::Key6,
+];
+
+#[cfg(not(target_arch = "wasm32"))]
+pub const REPLAY_DIR: &str = "replays";
+
+const REPLAY_HEADER: &str = "thrust-replay 6";
+
+/// Every this many frames, the replay holds a checksum of the world, to notice when the playback
+/// goes somewhere else than the recording did.
+pub const CHECKSUM_INTERVAL: usize = 60;
+
+/// The demo flight bundled with the game, relative to the static directory.
+const DEMO_FILE: &str = "demo.replay";
+
+#[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+const STATIC_DIR: &str = "static";
+
+/// One simulated frame of a replay.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ReplayFrame {
+    duration: Duration,
+    keys: u64,
+    /// The checksum of the world after the frame, in every `CHECKSUM_INTERVAL`-th one.
+    pub checksum: Option<u64>,
+}
+
+impl ReplayFrame {
+    fn new(duration: Duration, keys: &Keys) -> Self {
+        let keys = REPLAY_KEYS
+            .iter()
+            .enumerate()
+            .filter(|(_, key)| keys.contains(key))
+            .fold(0, |mask, (i, _)| mask | 1 << i);
+        ReplayFrame {
+            duration,
+            keys,
+            checksum: None,
+        }
+    }
+
+    fn keys(&self) -> Keys {
+        REPLAY_KEYS
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| self.keys & 1 << i != 0)
+            .map(|(_, key)| *key)
+            .collect()
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ReplayMode {
+    /// Recording what the player does.
+    Recording,
+    /// Feeding the recorded frames back into the simulation.
+    Playing,
+    /// All the recorded frames were played.
+    Finished,
+}
+
+/// The inputs and frame timings of a run, enough to simulate it again.
+///
+/// Only the frames in which the physics runs are stored, pauses are skipped.
+#[derive(Clone, Debug)]
+pub struct Replay {
+    pub mode: ReplayMode,
+    players: Players,
+    difficulty: Difficulty,
+    assists: u32,
+    /// The ships might have been designed by the player, so the whole design is stored.
+    ship: ShipDesign,
+    pub frames: Vec<ReplayFrame>,
+    pub position: usize,
+    saved: bool,
+    /// The first frame whose checksum came out different in the playback.
+    pub diverged: Option<usize>,
+}
+
+impl Default for Replay {
+    fn default() -> Self {
+        Replay {
+            mode: ReplayMode::Recording,
+            players: Players::default(),
+            difficulty: Difficulty::default(),
+            assists: 0,
+            ship: ShipDesign::default(),
+            frames: Vec::new(),
+            position: 0,
+            saved: false,
+            diverged: None,
+        }
+    }
+}
+
+impl Replay {
+    /// Starts over, on a level (re)start.
+    ///
+    /// Returns who plays the level, how hard it is, how many assists help and what ships fly,
+    /// which is decided by the replay when playing one.
+    pub fn restart(
+        &mut self,
+        players: Players,
+        difficulty: Difficulty,
+        assists: u32,
+        ship: ShipDesign,
+    ) -> (Players, Difficulty, u32, ShipDesign) {
+        match self.mode {
+            ReplayMode::Recording => {
+                self.frames.clear();
+                self.saved = false;
+                self.players = players;
+                self.difficulty = difficulty;
+                self.assists = assists;
+                self.ship = ship;
+            }
+            ReplayMode::Playing | ReplayMode::Finished => {
+                self.mode = ReplayMode::Playing;
+                self.position = 0;
+                self.diverged = None;
+            }
+        }
+        (self.players, self.difficulty, self.assists, self.ship.clone())
+    }
+
+    /// The frame recorded or played last.
+    pub fn last_frame(&self) -> Option<usize> {
+        match self.mode {
+            ReplayMode::Recording => self.frames.len().checked_sub(1),
+            ReplayMode::Playing | ReplayMode::Finished => self.position.checked_sub(1),
+        }
+    }
+
+    /// Stores the checksum of the world after the frame when recording, compares it to the
+    /// stored one when playing.
+    pub fn check(&mut self, frame: usize, checksum: u64) {
+        let recorded = &mut self.frames[frame].checksum;
+        if self.mode == ReplayMode::Recording {
+            *recorded = Some(checksum);
+        } else if recorded.is_some_and(|recorded| recorded != checksum)
+            && self.diverged.is_none()
+        {
+            error!("The replay diverged from the recording at frame {}", frame);
+            self.diverged = Some(frame);
+        }
+    }
+
+    /// Records the rest of a level loaded from a save.
+    ///
+    /// Without the start of the level, the recording can't be replayed, so it is never saved.
+    #[cfg(all(feature = "serialize", not(target_arch = "wasm32")))]
+    pub fn resume(&mut self) {
+        self.mode = ReplayMode::Recording;
+        self.frames.clear();
+        self.position = 0;
+        self.saved = true;
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn save(&self) -> Result<PathBuf, Box<dyn Error>> {
+        fs::create_dir_all(REPLAY_DIR)?;
+        let stamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        let path = Path::new(REPLAY_DIR).join(format!("thrust-{}.replay", stamp));
+        self.save_to(&path)?;
+        Ok(path)
+    }
+
+    /// Stores the recording as the demo flight bundled with the game.
+    ///
+    /// A developer command, the result is meant to be committed.
+    #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+    pub fn save_demo(&self) -> Result<PathBuf, Box<dyn Error>> {
+        let path = Path::new(STATIC_DIR).join(DEMO_FILE);
+        self.save_to(&path)?;
+        Ok(path)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn save_to(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(file, "{}", REPLAY_HEADER)?;
+        writeln!(file, "players {:?}", self.players)?;
+        writeln!(file, "difficulty {:?}", self.difficulty)?;
+        writeln!(file, "assists {}", self.assists)?;
+        let ship = self.ship.to_string();
+        writeln!(file, "ship {}", ship.lines().count())?;
+        write!(file, "{}", ship)?;
+        for frame in &self.frames {
+            write!(file, "{} {:x}", frame.duration.as_micros(), frame.keys)?;
+            if let Some(checksum) = frame.checksum {
+                write!(file, " {:x}", checksum)?;
+            }
+            writeln!(file)?;
+        }
+        file.flush()?;
+        Ok(())
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Self::parse(&fs::read_to_string(path)?)
+            .map_err(|e| format!("{}: {}", path.display(), e).into())
+    }
+
+    /// Parses a replay, ready to be played.
+    fn parse(content: &str) -> Result<Self, Box<dyn Error>> {
+        let mut lines = content.lines();
+        if lines.next() != Some(REPLAY_HEADER) {
+            return Err("Not a replay".into());
+        }
+        let players = lines
+            .next()
+            .and_then(|line| line.strip_prefix("players "))
+            .and_then(Players::parse)
+            .ok_or("Missing players")?;
+        let difficulty = lines
+            .next()
+            .and_then(|line| line.strip_prefix("difficulty "))
+            .and_then(Difficulty::parse)
+            .ok_or("Missing difficulty")?;
+        let assists = lines
+            .next()
+            .and_then(|line| line.strip_prefix("assists "))
+            .ok_or("Missing assists")?
+            .parse()?;
+        let ship_lines = lines
+            .next()
+            .and_then(|line| line.strip_prefix("ship "))
+            .ok_or("Missing ship")?
+            .parse()?;
+        let ship = lines.by_ref().take(ship_lines).collect::<Vec<_>>().join("\n");
+        let ship = ShipDesign::parse(&ship)?;
+        let frames = lines
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let mut fields = line.split_whitespace();
+                let duration = fields.next().ok_or("Missing frame duration")?.parse()?;
+                let keys = u64::from_str_radix(fields.next().ok_or("Missing keys")?, 16)?;
+                let checksum = fields
+                    .next()
+                    .map(|checksum| u64::from_str_radix(checksum, 16))
+                    .transpose()?;
+                Ok(ReplayFrame {
+                    duration: Duration::from_micros(duration),
+                    keys,
+                    checksum,
+                })
+            })
+            .collect::<Result<_, Box<dyn Error>>>()?;
+        Ok(Replay {
+            mode: ReplayMode::Playing,
+            players,
+            difficulty,
+            assists,
+            ship,
+            frames,
+            position: 0,
+            saved: true,
+            diverged: None,
+        })
+    }
+}
+
+/// How long the player needs to idle on the start screen before the demo flight is shown.
+pub const ATTRACT_DELAY: Duration = Duration::from_secs(30);
+
+/// The demo flight is being played to attract the player.
+///
+/// Holds the players and difficulty chosen before the demo, the demo may have its own.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Attract(pub Option<(Players, Difficulty)>);
+
+pub async fn load_demo() -> Option<Replay> {
+    let content = match quicksilver::load_file(DEMO_FILE).await {
+        Ok(content) => content,
+        Err(e) => {
+            info!("No demo flight available: {}", e);
+            return None;
+        }
+    };
+    match Replay::parse(&String::from_utf8_lossy(&content)) {
+        Ok(demo) => Some(demo),
+        Err(e) => {
+            error!("Broken demo flight: {}", e);
+            None
+        }
+    }
+}
+
+pub fn start_attract(world: &mut World, demo: &Replay) {
+    info!("Playing the demo flight");
+    let chosen = (*world.fetch::<Players>(), *world.fetch::<Difficulty>());
+    world.insert(demo.clone());
+    world.fetch_mut::<Attract>().0 = Some(chosen);
+    level(world);
+}
+
+pub fn stop_attract(world: &mut World) {
+    info!("Demo flight over");
+    world.insert(Replay::default());
+    let chosen = world.fetch_mut::<Attract>().0.take();
+    if let Some((players, difficulty)) = chosen {
+        *world.fetch_mut::<Players>() = players;
+        world.insert(difficulty);
+    }
+    // The demo pressed some keys, the player didn't
+    world.fetch_mut::<Keys>().clear();
+    level(world);
+}
+
+/// Records the inputs of the running game, or replaces them with the recorded ones.
+///
+/// Needs to run before the physics, so the physics sees the replayed frame.
+#[derive(Debug)]
+pub struct ReplayInputs;
+
+#[derive(SystemData)]
+pub struct ReplayInputsData<'a> {
+    replay: Write<'a, Replay>,
+    duration: Write<'a, FrameDuration>,
+    keys: WriteExpect<'a, Keys>,
+    state: WriteExpect<'a, GameState>,
+}
+
+impl<'a> System<'a> for ReplayInputs {
+    type SystemData = ReplayInputsData<'a>;
+
+    fn run(&mut self, mut d: Self::SystemData) {
+        let replay = &mut *d.replay;
+        match replay.mode {
+            ReplayMode::Recording => match *d.state {
+                GameState::Running => {
+                    // The file keeps whole microseconds, the recording must simulate the same
+                    // frames the playback does
+                    let micros = d.duration.0.as_micros() as u64;
+                    d.duration.0 = Duration::from_micros(micros);
+                    replay.frames.push(ReplayFrame::new(d.duration.0, &d.keys));
+                }
+                GameState::Won | GameState::Lost(_) if !replay.saved => {
+                    replay.saved = true;
+                    #[cfg(not(target_arch = "wasm32"))]
+                    match replay.save() {
+                        Ok(path) => info!("Replay saved to {}", path.display()),
+                        Err(e) => error!("Can't save replay: {}", e),
+                    }
+                }
+                _ => (),
+            },
+            ReplayMode::Playing => {
+                if let GameState::Won | GameState::Lost(_) = *d.state {
+                    replay.mode = ReplayMode::Finished;
+                } else if let Some(frame) = replay.frames.get(replay.position) {
+                    d.duration.0 = frame.duration;
+                    *d.keys = frame.keys();
+                    *d.state = GameState::Running;
+                    replay.position += 1;
+                } else {
+                    replay.mode = ReplayMode::Finished;
+                    *d.state = GameState::Paused;
+                }
+            }
+            ReplayMode::Finished => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_held_by_several_inputs() {
+        let mut held = HeldKeys::default();
+        // Both bound to the same action
+        held.set(Source::Keyboard(Key::Left), Some(Key::Left));
+        held.set(Source::Keyboard(Key::J), Some(Key::Left));
+        held.set(Source::Keyboard(Key::Up), Some(Key::Up));
+        held.release(Source::Keyboard(Key::J));
+        assert!(held.contains(Key::Left));
+        // Letting go of something else doesn't matter
+        held.set(Source::Keyboard(Key::K), None);
+        assert_eq!(held.keys(), [Key::Left, Key::Up].iter().copied().collect());
+        held.release(Source::Keyboard(Key::Left));
+        assert_eq!(held.keys(), [Key::Up].iter().copied().collect());
+    }
+}
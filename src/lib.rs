@@ -0,0 +1,2003 @@
+//! A game about flying a spaceship between planets.
+//!
+//! The [`Game`] is the simulation itself and can run without any window. The [`run`] drives it
+//! in a window, drawing it and feeding it the player input.
+//!
+//! The game is put together from [`Plugin`]s, see the [`GameBuilder`].
+
+use std::cell::RefCell;
+#[cfg(not(target_arch = "wasm32"))]
+use std::collections::VecDeque;
+use std::env;
+use std::error::Error;
+use std::fmt::Debug;
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs::{self, File};
+#[cfg(not(target_arch = "wasm32"))]
+use std::io::BufWriter;
+use std::io::Write as _;
+#[cfg(not(target_arch = "wasm32"))]
+use std::panic;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::{Path, PathBuf};
+#[cfg(all(feature = "leaderboard", not(target_arch = "wasm32")))]
+use std::sync::mpsc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::{Mutex, TryLockError};
+#[cfg(not(target_arch = "wasm32"))]
+use std::thread;
+use std::time::{Duration, Instant};
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use quicksilver::QuicksilverError as QError;
+use quicksilver::geom::Vector;
+use quicksilver::graphics::{Color, Graphics, ResizeHandler, VectorFont};
+#[cfg(not(target_arch = "wasm32"))]
+use quicksilver::graphics::PixelFormat;
+use quicksilver::lifecycle::{
+    Event, EventStream, GamepadButton, Key, MouseButton, PointerId, ScrollDelta, Settings, Window,
+};
+use specs::prelude::*;
+use specs_hierarchy::HierarchySystem;
+
+use log::{debug, error, info, trace};
+#[cfg(not(target_arch = "wasm32"))]
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+mod components;
+mod input;
+mod loadout;
+mod options;
+mod physics;
+mod plugin;
+mod powerups;
+mod profile;
+mod progress;
+mod render;
+mod repair;
+#[cfg(all(feature = "serialize", not(target_arch = "wasm32")))]
+mod saves;
+#[cfg(feature = "serialize")]
+pub mod serialize;
+mod ships;
+mod state;
+mod stations;
+#[cfg(test)]
+mod test_support;
+mod touch;
+mod tutorial;
+mod zones;
+
+use crate::components::{
+    Comet, Hull, Landing, Name, Position, Rotation, Ship, Speed, Star, Thruster,
+};
+use crate::input::{
+    load_demo, start_attract, stop_attract, Attract, Gamepads, HeldKeys, Keys, Replay,
+    ReplayInputs, ReplayMode, Source, ATTRACT_DELAY, CHECKSUM_INTERVAL, PLAYER_KEYS, REPLAY_DIR,
+    STICK_DEAD_ZONE,
+};
+use crate::loadout::{Designer, Loadout};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::options::OPTIONS_FILE;
+use crate::options::{Options, OptionsKey, OptionsMenu, Setting};
+use crate::physics::{
+    motion, DifficultyTimeMod, FrameStep, PhysicsSystems, UpdateDurations, FRAME_STEP,
+};
+use crate::profile::{apply_profile, Profiles, RecordProfile};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::profile::PROFILE_DIR;
+use crate::render::{
+    FitCamera, FitView, FreeCamera, PanCamera, ShowGravityField, ShowGrid, ShowLagrange, Spectate,
+    Spectator, Viewport, Waypoint, DESIGN_SIZE, ZOOM_FACTOR,
+};
+#[cfg(all(feature = "serialize", not(target_arch = "wasm32")))]
+use crate::saves::{MenuKey, MenuMode, SaveMenu, SAVE_DIR};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::ships::SHIP_DIR;
+use crate::ships::Shipyard;
+#[cfg(all(feature = "leaderboard", not(target_arch = "wasm32")))]
+use crate::state::submit_score;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::state::LEVEL_ID;
+use crate::state::{
+    level, Assists, CollectStats, CountFailures, EventLog, Leaderboard, LevelClock, LevelShip,
+    LevelTime, LogStateChanges, Players, Rules, TargetPad, TrackTarget, VictoryDetector,
+};
+use crate::touch::Touches;
+use crate::tutorial::Tutorial;
+
+pub use crate::physics::{Gravity, GravityPlugin, ThrusterPlugin};
+pub use crate::plugin::Plugin;
+pub use crate::powerups::PowerUpPlugin;
+pub use crate::render::RenderPlugin;
+pub use crate::repair::RepairPlugin;
+pub use crate::state::{Difficulty, GameState, LostReason};
+pub use crate::stations::StationPlugin;
+pub use crate::tutorial::TutorialPlugin;
+pub use crate::zones::ZonePlugin;
+
+/// Startup configuration.
+///
+/// The options come from the options file (`thrust.cfg`, desktop only), which the options menu
+/// writes. Environment variables override them:
+/// * `THRUST_VSYNC=0` turns vsync off.
+/// * `THRUST_FRAME_CAP=<fps>` limits the frame rate when vsync is off.
+/// * `THRUST_LETTERBOX=1` keeps the design aspect ratio, with bars around.
+/// * `THRUST_LEADERBOARD=<url>` submits won levels to an online leaderboard (needs the
+///   `leaderboard` feature, desktop only).
+/// * `THRUST_PLAYER=<name>` is the name of the first player profile, shown on the leaderboard.
+///
+/// And from the command line:
+/// * `--export-replay <file>` renders the replay into a sequence of PNG frames and exits.
+/// * `--verify-replay <file>` plays the replay twice without any window and checks both runs
+///   went the same way (see [`verify_replay`]).
+#[derive(Clone, Debug)]
+pub struct Config {
+    options: Options,
+    #[cfg(not(target_arch = "wasm32"))]
+    export_replay: Option<PathBuf>,
+    #[cfg(not(target_arch = "wasm32"))]
+    verify_replay: Option<PathBuf>,
+    #[cfg(all(feature = "leaderboard", not(target_arch = "wasm32")))]
+    leaderboard: Option<String>,
+    #[cfg(not(target_arch = "wasm32"))]
+    player_name: String,
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        let var = |name| env::var(name).ok();
+        #[cfg(not(target_arch = "wasm32"))]
+        let mut options = Options::load(Path::new(OPTIONS_FILE));
+        #[cfg(target_arch = "wasm32")]
+        let mut options = Options::default();
+        if let Some(vsync) = var("THRUST_VSYNC") {
+            options.vsync = vsync != "0";
+        }
+        if let Some(cap) = var("THRUST_FRAME_CAP") {
+            match cap.parse() {
+                Ok(0) => options.frame_cap = None,
+                Ok(cap) => options.frame_cap = Some(cap),
+                Err(e) => error!("Invalid frame cap {}: {}", cap, e),
+            }
+        }
+        if let Some(letterbox) = var("THRUST_LETTERBOX") {
+            options.letterbox = letterbox != "0";
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        let arg = |name| env::args().skip_while(|arg| arg != name).nth(1).map(PathBuf::from);
+        Config {
+            options,
+            #[cfg(not(target_arch = "wasm32"))]
+            export_replay: arg("--export-replay"),
+            #[cfg(not(target_arch = "wasm32"))]
+            verify_replay: arg("--verify-replay"),
+            #[cfg(all(feature = "leaderboard", not(target_arch = "wasm32")))]
+            leaderboard: var("THRUST_LEADERBOARD"),
+            #[cfg(not(target_arch = "wasm32"))]
+            player_name: var("THRUST_PLAYER").unwrap_or_else(|| "Player 1".to_owned()),
+        }
+    }
+
+    /// The replay to check instead of playing, if asked for on the command line.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn replay_to_verify(&self) -> Option<&Path> {
+        self.verify_replay.as_deref()
+    }
+
+    /// The window settings to start the game with.
+    pub fn settings(&self) -> Settings {
+        Settings {
+            size: self.options.window,
+            fullscreen: self.options.fullscreen,
+            resizable: true,
+            vsync: self.options.vsync,
+            title: "Thrust",
+            ..Settings::default()
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+const SCREENSHOT_DIR: &str = "screenshots";
+
+/// How many pixels of smooth scrolling make one zoom step, like one line of a mouse wheel.
+const SCROLL_STEP: f32 = 20.0;
+
+/// Saves the current content of the screen as a PNG.
+///
+/// Needs to be called before presenting the frame.
+#[cfg(not(target_arch = "wasm32"))]
+fn screenshot(gfx: &mut Graphics, window: &Window) -> Result<PathBuf, Box<dyn Error>> {
+    fs::create_dir_all(SCREENSHOT_DIR)?;
+    let stamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+    let path = Path::new(SCREENSHOT_DIR).join(format!("thrust-{}.png", stamp));
+    save_png(gfx, window, &path)?;
+    Ok(path)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn save_png(gfx: &mut Graphics, window: &Window, path: &Path) -> Result<(), Box<dyn Error>> {
+    let size = Vector::from(window.size()) * window.scale_factor();
+    let (width, height) = (size.x as u32, size.y as u32);
+    let pixels = gfx.screenshot(window, PixelFormat::RGBA);
+    // OpenGL gives us the rows bottom up
+    let flipped = pixels
+        .chunks(width as usize * 4)
+        .rev()
+        .flatten()
+        .copied()
+        .collect::<Vec<u8>>();
+
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, width, height);
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&flipped)?;
+    Ok(())
+}
+
+/// Where the frames of a replay being exported go.
+#[cfg(not(target_arch = "wasm32"))]
+struct Export {
+    dir: PathBuf,
+    frame: usize,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Export {
+    /// Loads the replay into the world and prepares the output directory.
+    fn start(world: &mut World, replay: &Path) -> Result<Self, Box<dyn Error>> {
+        let replay = Replay::load(replay)?;
+        let stamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        let dir = Path::new(REPLAY_DIR).join(format!("export-{}", stamp));
+        fs::create_dir_all(&dir)?;
+        info!("Exporting {} frames into {}", replay.frames.len(), dir.display());
+        world.insert(replay);
+        level(world);
+        Ok(Export { dir, frame: 0 })
+    }
+
+    /// Stores the frame if the replay moved on since the last one.
+    fn frame(&mut self, gfx: &mut Graphics, window: &Window, replay: &Replay) {
+        if replay.position == self.frame {
+            return;
+        }
+        self.frame = replay.position;
+        let path = self.dir.join(format!("frame-{:05}.png", self.frame));
+        if let Err(e) = save_png(gfx, window, &path) {
+            error!("Can't export frame {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Plays the replay from the start, without any window.
+///
+/// Returns the [checksum][Game::checksum] of the world after each frame.
+#[cfg(not(target_arch = "wasm32"))]
+fn replay_checksums(replay: &Replay) -> Vec<u64> {
+    let mut game = Game::new();
+    let mut replay = replay.clone();
+    replay.mode = ReplayMode::Playing;
+    game.world.insert(replay);
+    game.restart();
+    let mut checksums = Vec::new();
+    loop {
+        game.step();
+        // The last step only notices there are no more frames
+        if game.world.fetch::<Replay>().mode != ReplayMode::Playing {
+            return checksums;
+        }
+        checksums.push(game.checksum());
+    }
+}
+
+/// Plays the replay twice and compares the runs, frame by frame.
+///
+/// The ghosts, the leaderboards and the replays themselves all need the simulation to come out
+/// the same each time it is fed the same inputs. Returns how many frames were checked, or the
+/// first one where the runs went apart.
+#[cfg(not(target_arch = "wasm32"))]
+fn check_replay(replay: &Replay) -> Result<usize, Box<dyn Error>> {
+    let first = replay_checksums(replay);
+    let second = replay_checksums(replay);
+    if let Some(frame) = first.iter().zip(&second).position(|(a, b)| a != b) {
+        return Err(format!("The runs diverge at frame {}", frame).into());
+    }
+    let recorded = replay.frames.iter().map(|frame| frame.checksum);
+    if let Some(frame) = recorded
+        .zip(&first)
+        .position(|(recorded, checksum)| recorded.is_some_and(|recorded| recorded != *checksum))
+    {
+        return Err(format!("The run diverges from the recording at frame {}", frame).into());
+    }
+    if first.len() != second.len() {
+        let frames = first.len().min(second.len());
+        return Err(format!("One of the runs ended after {} frames", frames).into());
+    }
+    Ok(first.len())
+}
+
+/// Checks the replay in the file plays the same way each time.
+///
+/// Returns how many frames were checked.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn verify_replay(path: &Path) -> Result<usize, Box<dyn Error>> {
+    let replay = Replay::load(path)?;
+    info!("Verifying {} frames of {}", replay.frames.len(), path.display());
+    check_replay(&replay)
+}
+
+fn set_fullscreen(world: &World, gfx: &Graphics, window: &Window, fullscreen: bool) {
+    info!("Fullscreen: {}", fullscreen);
+    window.set_fullscreen(fullscreen);
+    world.fetch_mut::<Viewport>().adjust_to_window_size(gfx, window);
+}
+
+fn toggle_fullscreen(world: &mut World, gfx: &Graphics, window: &Window) {
+    let options = world.get_mut::<Options>().expect("Options are always present");
+    options.fullscreen = !options.fullscreen;
+    options.unsaved = true;
+    let fullscreen = options.fullscreen;
+    set_fullscreen(world, gfx, window, fullscreen);
+}
+
+fn set_letterbox(world: &World, gfx: &mut Graphics, window: &Window, letterbox: bool) {
+    info!("Letterbox: {}", letterbox);
+    let handler = if letterbox {
+        ResizeHandler::Fit {
+            aspect_width: DESIGN_SIZE.x,
+            aspect_height: DESIGN_SIZE.y,
+        }
+    } else {
+        ResizeHandler::Stretch
+    };
+    gfx.set_resize_handler(handler);
+    let mut viewport = world.fetch_mut::<Viewport>();
+    viewport.letterbox = letterbox;
+    viewport.adjust_to_window_size(gfx, window);
+}
+
+/// Puts a changed option into effect, if it can change while the game runs.
+fn apply_option(world: &World, gfx: &mut Graphics, window: &Window, setting: Setting) {
+    let options = world.fetch::<Options>().clone();
+    match setting {
+        Setting::Fullscreen => set_fullscreen(world, gfx, window, options.fullscreen),
+        Setting::Window => {
+            info!("Window size: {:?}", options.window);
+            window.set_size(options.window.into());
+            world.fetch_mut::<Viewport>().adjust_to_window_size(gfx, window);
+        }
+        Setting::Letterbox => set_letterbox(world, gfx, window, options.letterbox),
+        // The held keys might be gone
+        Setting::Key { .. } | Setting::Button { .. } | Setting::PadTurn | Setting::PadThrust => {
+            world.fetch_mut::<HeldKeys>().clear();
+        }
+        // Let go of the buttons that are gone
+        Setting::Touch => {
+            world.fetch_mut::<Touches>().release();
+            world.fetch_mut::<HeldKeys>().retain(|source| !matches!(source, Source::Touch(_)));
+        }
+        Setting::TextScale => world.fetch_mut::<Viewport>().text_scale = options.text_scale,
+        // Read when needed
+        Setting::Vsync | Setting::FrameCap | Setting::Assists | Setting::ReducedMotion => (),
+    }
+}
+
+/// Lets go of the key, after a menu took the press for itself.
+///
+/// The key went down as what the ships know it as, which is whatever the keyboard key holds.
+fn release_key(world: &World, key: Key) {
+    world.fetch_mut::<HeldKeys>().release(Source::Keyboard(key));
+}
+
+/// Presses and releases the keys of a finger, for the on-screen buttons.
+fn press_keys(world: &World, pointer: PointerId, changes: Vec<(Key, bool)>) {
+    let mut held = world.fetch_mut::<HeldKeys>();
+    let source = Source::Touch(pointer);
+    for (key, down) in changes {
+        if down {
+            held.set(source, Some(key));
+        } else if held.get(source) == Some(key) {
+            held.release(source);
+        }
+    }
+}
+
+/// The simulation of the game, without any window or graphics.
+///
+/// It holds the world with the current level and the systems that move it forward. Whoever drives
+/// it feeds it with the input (through the [`Keys`] and other resources in the world) and calls
+/// [`step`][Game::step] once per frame. Drawing the world is up to the caller.
+pub struct Game {
+    world: World,
+    dispatcher: Dispatcher<'static, 'static>,
+}
+
+/// The 64-bit FNV-1a hash.
+///
+/// The checksums end up in the replay files, so they can't use the hasher of the standard
+/// library, which may change between Rust releases.
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+impl Game {
+    /// The game with all the built-in plugins and the first level.
+    pub fn new() -> Self {
+        let mut game = GameBuilder::new()
+            .with_plugin(GravityPlugin::default())
+            .with_plugin(ZonePlugin)
+            .with_plugin(PowerUpPlugin)
+            .with_plugin(StationPlugin)
+            .with_plugin(RepairPlugin)
+            .with_plugin(ThrusterPlugin)
+            .with_plugin(TutorialPlugin)
+            .build();
+        game.restart();
+        game
+    }
+
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    pub fn world_mut(&mut self) -> &mut World {
+        &mut self.world
+    }
+
+    pub fn state(&self) -> GameState {
+        *self.world.fetch::<GameState>()
+    }
+
+    /// Starts the level from scratch.
+    pub fn restart(&mut self) {
+        level(&mut self.world);
+    }
+
+    /// Runs one frame of the simulation.
+    ///
+    /// Every `CHECKSUM_INTERVAL`-th frame of the replay gets the checksum of the world, either
+    /// stored or compared to the stored one.
+    pub fn step(&mut self) {
+        let before = self.world.fetch::<Replay>().last_frame();
+        self.dispatcher.dispatch(&self.world);
+        self.world.maintain();
+        let frame = self.world.fetch::<Replay>().last_frame();
+        if let Some(frame) = frame.filter(|frame| Some(*frame) != before) {
+            if (frame + 1) % CHECKSUM_INTERVAL == 0 {
+                let checksum = self.checksum();
+                self.world.fetch_mut::<Replay>().check(frame, checksum);
+            }
+        }
+    }
+
+    /// A fingerprint of the simulated state: where everything is, how it moves, how the ships
+    /// are doing and how far the level got.
+    ///
+    /// Two worlds with the same checksum are the same for all practical purposes.
+    pub fn checksum(&self) -> u64 {
+        let (entities, positions, speeds, rotations, ships, state, time) = self.world
+            .system_data::<(
+                Entities,
+                ReadStorage<Position>,
+                ReadStorage<Speed>,
+                ReadStorage<Rotation>,
+                ReadStorage<Ship>,
+                ReadExpect<GameState>,
+                Read<LevelTime>,
+            )>();
+        // The entities get different ids depending on what existed before, so the order they
+        // come in doesn't matter
+        let mut things = (&entities, positions.maybe(), speeds.maybe(), rotations.maybe())
+            .join()
+            .map(|(ent, pos, speed, rotation)| {
+                let mut numbers = Vec::new();
+                for vector in pos.map(|pos| pos.0).iter().chain(speed.map(|speed| &speed.0)) {
+                    numbers.extend_from_slice(&[vector.x, vector.y]);
+                }
+                numbers.extend(rotation.map(|rotation| rotation.0));
+                if let Some(ship) = ships.get(ent) {
+                    numbers.extend_from_slice(&[ship.fuel, ship.temperature]);
+                }
+                let mut hasher = Fnv::new();
+                for number in numbers {
+                    hasher.write(&number.to_bits().to_le_bytes());
+                }
+                hasher.0
+            })
+            .collect::<Vec<_>>();
+        things.sort_unstable();
+        let mut hasher = Fnv::new();
+        for thing in things {
+            hasher.write(&thing.to_le_bytes());
+        }
+        hasher.write(format!("{:?}", *state).as_bytes());
+        hasher.write(&time.0.as_secs().to_le_bytes());
+        hasher.write(&time.0.subsec_nanos().to_le_bytes());
+        hasher.0
+    }
+
+    /// Runs one frame of a paused game, [`FRAME_STEP`] long, and pauses it again.
+    ///
+    /// The frame gets into the replay like any other. Does nothing if the game isn't paused.
+    pub fn step_paused(&mut self) {
+        if self.state() != GameState::Paused {
+            return;
+        }
+        *self.world.fetch_mut::<GameState>() = GameState::Running;
+        self.world.insert(FrameStep(true));
+        self.step();
+        self.world.insert(FrameStep(false));
+        let state = self.world.get_mut::<GameState>().expect("The game state is always present");
+        // Unless the frame ended the level
+        if *state == GameState::Running {
+            *state = GameState::Paused;
+        }
+        debug!("Stepped a frame of {:?}", FRAME_STEP);
+    }
+}
+
+impl Default for Game {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Puts together a [`Game`] from [`Plugin`]s.
+///
+/// The built game has an empty world, call [`restart`][Game::restart] to get a level.
+pub struct GameBuilder {
+    plugins: Vec<Box<dyn Plugin<'static, 'static>>>,
+    step: Option<Duration>,
+}
+
+impl GameBuilder {
+    pub fn new() -> Self {
+        GameBuilder {
+            plugins: Vec::new(),
+            step: None,
+        }
+    }
+
+    pub fn with_plugin<P: Plugin<'static, 'static> + 'static>(mut self, plugin: P) -> Self {
+        self.plugins.push(Box::new(plugin));
+        self
+    }
+
+    /// Makes each frame take exactly this long, no matter how much real time passed.
+    pub fn with_fixed_step(mut self, step: Duration) -> Self {
+        self.step = Some(step);
+        self
+    }
+
+    pub fn build(mut self) -> Game {
+        let mut world = World::new();
+        for plugin in &mut self.plugins {
+            plugin.setup(&mut world);
+        }
+
+        let mut physics = DispatcherBuilder::new();
+        for plugin in &mut self.plugins {
+            physics = plugin.physics(physics);
+        }
+        let physics = motion(physics.with_barrier()).with(LevelClock, "level-clock", &[]);
+
+        let durations = UpdateDurations {
+            last_frame: Instant::now(),
+            fixed: self.step,
+        };
+        let mut builder = DispatcherBuilder::new()
+            .with(HierarchySystem::<Thruster>::new(&mut world), "thruster-hierarchy", &[])
+            .with(durations, "update-durations", &[])
+            .with(ReplayInputs, "replay-inputs", &["update-durations"])
+            .with_multi_batch(PhysicsSystems, physics, "physics", &["replay-inputs"])
+            .with_barrier();
+        for plugin in &mut self.plugins {
+            builder = plugin.systems(builder);
+        }
+        let mut dispatcher = builder
+            .with(VictoryDetector, "victory-detector", &[])
+            .with(CollectStats, "collect-stats", &["victory-detector"])
+            .with(
+                LogStateChanges { last: GameState::Started },
+                "log-state-changes",
+                &["victory-detector"],
+            )
+            .with(
+                CountFailures { last: GameState::Started },
+                "count-failures",
+                &["victory-detector"],
+            )
+            .with(
+                RecordProfile { last: GameState::Started },
+                "record-profile",
+                &["victory-detector", "collect-stats"],
+            )
+            .with(TrackTarget, "track-target", &[])
+            .with(PanCamera, "pan-camera", &[])
+            .with(FitCamera, "fit-camera", &["pan-camera"])
+            .with(Spectate, "spectate", &["fit-camera"])
+            .build();
+        dispatcher.setup(&mut world);
+        // Some are used only for drawing, so the systems above don't know about them.
+        world.register::<Star>();
+        world.register::<Comet>();
+        world.register::<Name>();
+        world.register::<Hull>();
+
+        // The level sets it from the difficulty again, but the systems want it from the start.
+        world.insert(DifficultyTimeMod(Rules::default().time_mod));
+        world.insert(Keys::new());
+        world.insert(HeldKeys::default());
+        world.insert(Viewport::default());
+        world.insert(GameState::Started);
+        // Not used by any of the simulation systems, but the level and the front end need them.
+        world.insert(Difficulty::default());
+        world.insert(Attract::default());
+        world.insert(Gamepads::default());
+        world.insert(Leaderboard::default());
+        world.insert(Profiles::default());
+        world.insert(Shipyard::default());
+        world.insert(Loadout::default());
+        world.insert(LevelShip::default());
+        world.insert(Designer::default());
+        world.insert(Options::default());
+        world.insert(OptionsMenu::default());
+        world.insert(Touches::default());
+        #[cfg(all(feature = "serialize", not(target_arch = "wasm32")))]
+        world.insert(SaveMenu::default());
+
+        Game { world, dispatcher }
+    }
+}
+
+impl Default for GameBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Runs the game in the window until the player quits.
+pub async fn run(
+    config: Config,
+    window: Window,
+    gfx: Graphics,
+    mut ev: EventStream,
+) -> Result<(), QError> {
+    let font = VectorFont::load("Ubuntu_Mono/UbuntuMono-Regular.ttf").await?;
+    // :-( I don't like ref cells, but we need to thread the mut-borrow to both us for
+    // synchronization, resizing etc, and the drawing systems.
+    //
+    // We do take turns in who borrow it, it's just each needs to be able to hold onto it in
+    // between.
+    let gfx = RefCell::new(gfx);
+    let gfx = &gfx;
+    let mut game = Game::new();
+    game.world.insert(config.options.clone());
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        game.world.fetch_mut::<Shipyard>().load_dir(Path::new(SHIP_DIR));
+        let profiles = Profiles::load(Path::new(PROFILE_DIR), &config.player_name);
+        game.world.insert(profiles);
+        apply_profile(&mut game.world);
+        // The level got built with only the built-in designs and the default settings
+        game.restart();
+    }
+    let mut render = RenderPlugin::new(gfx, &font);
+    render.setup(game.world_mut());
+    let mut draw = render.systems(DispatcherBuilder::new()).build();
+    draw.setup(game.world_mut());
+
+    // Adjust the viewport before first frame
+    set_letterbox(&game.world, &mut gfx.borrow_mut(), &window, config.options.letterbox);
+    game.world.fetch_mut::<Viewport>().text_scale = config.options.text_scale;
+
+    let demo = load_demo().await;
+    let mut idle_since = Instant::now();
+
+    let mut screenshot_requested = false;
+
+    // Where the mouse was last seen, for dragging the free camera around.
+    let mut pointer = Vector::ZERO;
+    let mut dragging = false;
+
+    #[cfg(not(target_arch = "wasm32"))]
+    let mut export = match &config.export_replay {
+        Some(replay) => match Export::start(&mut game.world, replay) {
+            Ok(export) => Some(export),
+            Err(e) => {
+                error!("Can't export replay {}: {}", replay.display(), e);
+                return Ok(());
+            }
+        },
+        None => None,
+    };
+
+    #[cfg(all(feature = "leaderboard", not(target_arch = "wasm32")))]
+    let (scores_sender, scores) = mpsc::channel();
+    #[cfg(all(feature = "leaderboard", not(target_arch = "wasm32")))]
+    let mut submitted = false;
+
+    info!("Config {:?}", config);
+
+    'mainloop: loop {
+        let frame_start = Instant::now();
+        trace!("Checking for events");
+        while let Some(e) = ev.next_event().await {
+            debug!("Received event {:?}", e);
+            match &e {
+                Event::KeyboardInput(_)
+                | Event::PointerInput(_)
+                | Event::PointerMoved(_)
+                | Event::GamepadButton(_) => {
+                    idle_since = Instant::now();
+                }
+                _ => (),
+            }
+            if game.world.fetch::<Attract>().0.is_some() {
+                // Any key ends the demo, but the key itself doesn't do anything.
+                let released = match &e {
+                    Event::KeyboardInput(event) => Some(!event.is_down()),
+                    Event::PointerInput(event) => Some(!event.is_down()),
+                    Event::GamepadButton(event) => Some(!event.is_down()),
+                    _ => None,
+                };
+                match released {
+                    Some(true) => {
+                        stop_attract(&mut game.world);
+                        continue;
+                    }
+                    Some(false) => continue,
+                    None => (),
+                }
+            }
+            match e {
+                Event::Resized(resize) => {
+                    let viewport = game.world.get_mut::<Viewport>().expect("Viewport is always present");
+                    viewport.adjust_to_window_size(&gfx.borrow_mut(), &window);
+
+                    info!("Resize: {:?}, {:?}", resize, viewport);
+                }
+                Event::ScaleFactorChanged(change) => {
+                    let viewport = game.world.get_mut::<Viewport>().expect("Viewport is always present");
+                    viewport.adjust_to_window_size(&gfx.borrow_mut(), &window);
+
+                    info!("Scale factor: {:?}, {:?}", change, viewport);
+                }
+                Event::GamepadConnected(event) => info!("Gamepad connected: {:?}", event.id()),
+                Event::GamepadDisconnected(event) => {
+                    info!("Gamepad disconnected: {:?}", event.id());
+                    let gamepads = game.world.get_mut::<Gamepads>()
+                        .expect("Gamepads are always present");
+                    gamepads.unpair(event.id());
+                }
+                Event::GamepadButton(event) => {
+                    if !event.is_down() && game.world.fetch::<OptionsMenu>().capturing {
+                        let input = {
+                            let (mut menu, mut options) = game.world
+                                .system_data::<(Write<OptionsMenu>, Write<Options>)>();
+                            menu.button(&mut options, event.button())
+                        };
+                        if let OptionsKey::Changed(setting) = input {
+                            apply_option(&game.world, &mut gfx.borrow_mut(), &window, setting);
+                        }
+                        if input != OptionsKey::Ignored {
+                            // The press went through as a normal button
+                            let source = Source::Button(*event.id(), event.button());
+                            game.world.fetch_mut::<HeldKeys>().release(source);
+                            continue;
+                        }
+                    }
+                    let player = game.world.fetch::<Gamepads>().player(event.id());
+                    match (player, event.button()) {
+                        (None, GamepadButton::Start) if !event.is_down() => {
+                            let gamepads = game.world.get_mut::<Gamepads>()
+                                .expect("Gamepads are always present");
+                            match gamepads.pair(*event.id()) {
+                                Some(player) => info!("Gamepad paired with player {}", player + 1),
+                                None => info!("All players already have a gamepad"),
+                            }
+                        }
+                        (Some(_), GamepadButton::Start) if !event.is_down() => {
+                            let game_state = game.world
+                                .get_mut::<GameState>()
+                                .expect("The running condition is always present");
+                            game_state.toggle();
+                        }
+                        (Some(player), button) => {
+                            let key = game.world.fetch::<Options>()
+                                .pad
+                                .key(&PLAYER_KEYS[player], button)
+                                .filter(|_| event.is_down());
+                            let source = Source::Button(*event.id(), button);
+                            game.world.fetch_mut::<HeldKeys>().set(source, key);
+                        }
+                        (None, _) => (),
+                    }
+                }
+                Event::GamepadAxis(event) => {
+                    let player = game.world.fetch::<Gamepads>().player(event.id());
+                    let steered = player.and_then(|player| {
+                        game.world.fetch::<Options>()
+                            .pad
+                            .axis_keys(&PLAYER_KEYS[player], event.axis())
+                    });
+                    if let Some((negative, positive)) = steered {
+                        let key = if event.value() < -STICK_DEAD_ZONE {
+                            Some(negative)
+                        } else if event.value() > STICK_DEAD_ZONE {
+                            Some(positive)
+                        } else {
+                            None
+                        };
+                        let source = Source::Axis(*event.id(), event.axis());
+                        game.world.fetch_mut::<HeldKeys>().set(source, key);
+                    }
+                }
+                Event::PointerInput(event) if event.button() == MouseButton::Middle => {
+                    dragging = event.is_down();
+                }
+                Event::PointerInput(event) => {
+                    let touched = if event.button() == MouseButton::Left
+                        && game.world.fetch::<Options>().touch
+                    {
+                        let size = window.size().into();
+                        let viewport = *game.world.fetch::<Viewport>();
+                        game.world
+                            .fetch_mut::<Touches>()
+                            .input(&viewport, *event.pointer(), pointer, event.is_down(), size)
+                    } else {
+                        None
+                    };
+                    if let Some(changes) = touched {
+                        press_keys(&game.world, *event.pointer(), changes);
+                        continue;
+                    }
+                    if event.is_down() {
+                        continue;
+                    }
+                    let waypoint = match event.button() {
+                        MouseButton::Left => {
+                            let size = window.size().into();
+                            Some(game.world.fetch::<Viewport>().unproject(pointer, size))
+                        }
+                        MouseButton::Right => None,
+                        _ => continue,
+                    };
+                    info!("Waypoint: {:?}", waypoint);
+                    game.world.fetch_mut::<Waypoint>().0 = waypoint;
+                }
+                Event::PointerMoved(event) => {
+                    let location = event.location().into();
+                    let state = *game.world.fetch::<GameState>();
+                    if dragging && game.world.fetch::<FreeCamera>().active(state) {
+                        let viewport = game.world.get_mut::<Viewport>()
+                            .expect("Viewport is always present");
+                        viewport.rect.pos -= (location - pointer) / viewport.zoom;
+                        viewport.update();
+                    }
+                    pointer = location;
+                    let size = window.size().into();
+                    let viewport = *game.world.fetch::<Viewport>();
+                    let changes = game.world
+                        .fetch_mut::<Touches>()
+                        .moved(&viewport, *event.pointer(), location, size);
+                    press_keys(&game.world, *event.pointer(), changes);
+                }
+                Event::ScrollInput(delta) => {
+                    let state = *game.world.fetch::<GameState>();
+                    if !game.world.fetch::<FreeCamera>().active(state) {
+                        continue;
+                    }
+                    let steps = match delta {
+                        ScrollDelta::Lines(lines) => lines.y,
+                        ScrollDelta::Pixels(pixels) => pixels.y / SCROLL_STEP,
+                    };
+                    let viewport = game.world.get_mut::<Viewport>()
+                        .expect("Viewport is always present");
+                    viewport.zoom *= ZOOM_FACTOR.powf(steps);
+                    viewport.adjust_to_window_size(&gfx.borrow_mut(), &window);
+                    info!("Zoom by scrolling: {:?}", viewport);
+                }
+                Event::KeyboardInput(event) => {
+                    info!("Key press {:?}", event);
+                    if !event.is_down() && game.world.fetch::<Designer>().open {
+                        let closed = {
+                            let (mut designer, mut loadout) = game.world
+                                .system_data::<(Write<Designer>, Write<Loadout>)>();
+                            if designer.key(loadout.edit(), event.key()) {
+                                Some(!designer.open)
+                            } else {
+                                None
+                            }
+                        };
+                        if let Some(closed) = closed {
+                            // The press went through as a normal key
+                            release_key(&game.world, event.key());
+                            if closed {
+                                // The ships get the new thrusters
+                                game.restart();
+                            }
+                            continue;
+                        }
+                    }
+                    if !event.is_down() && game.world.fetch::<OptionsMenu>().open {
+                        let input = {
+                            let (mut menu, mut options) = game.world
+                                .system_data::<(Write<OptionsMenu>, Write<Options>)>();
+                            menu.key(&mut options, event.key())
+                        };
+                        if input != OptionsKey::Ignored {
+                            // The press went through as a normal key
+                            release_key(&game.world, event.key());
+                            if let OptionsKey::Changed(setting) = input {
+                                apply_option(&game.world, &mut gfx.borrow_mut(), &window, setting);
+                            }
+                            continue;
+                        }
+                    }
+                    #[cfg(all(feature = "serialize", not(target_arch = "wasm32")))]
+                    {
+                        let input = if event.is_down() {
+                            MenuKey::Ignored
+                        } else {
+                            game.world.fetch_mut::<SaveMenu>().key(event.key())
+                        };
+                        if input != MenuKey::Ignored {
+                            // The press went through as a normal key
+                            release_key(&game.world, event.key());
+                        }
+                        match input {
+                            MenuKey::Ignored => (),
+                            MenuKey::Handled => continue,
+                            MenuKey::Chosen(MenuMode::Save, slot) => {
+                                match saves::save(&mut game.world, Path::new(SAVE_DIR), slot) {
+                                    Ok(_) => info!("Saved into slot {}", slot + 1),
+                                    Err(e) => error!("Can't save the game: {}", e),
+                                }
+                                continue;
+                            }
+                            MenuKey::Chosen(MenuMode::Load, slot) => {
+                                match saves::load(&mut game.world, Path::new(SAVE_DIR), slot) {
+                                    Ok(info) => info!("Loaded {:?}", info),
+                                    Err(e) => {
+                                        error!("Can't load the game: {}", e);
+                                        // The world might be half gone
+                                        game.restart();
+                                    }
+                                }
+                                continue;
+                            }
+                        }
+                    }
+                    // What the ships know the key as
+                    let bound = game.world.fetch::<Options>().translate(event.key());
+                    let held = game.world.get_mut::<HeldKeys>()
+                        .expect("Held keys are always present");
+                    match event.key() {
+                        Key::Space | Key::Pause if !event.is_down() => {
+                            let game_state = game.world
+                                .get_mut::<GameState>()
+                                .expect("The running condition is always present");
+                            game_state.toggle();
+                        }
+                        Key::Space | Key::Pause => (),
+                        Key::Escape if event.is_down() => {
+                            info!("Terminating");
+                            break 'mainloop;
+                        }
+                        Key::F11 if !event.is_down() => {
+                            toggle_fullscreen(&mut game.world, &gfx.borrow(), &window);
+                        }
+                        Key::F11 => (),
+                        Key::F12 if !event.is_down() => screenshot_requested = true,
+                        Key::F12 => (),
+                        Key::F2 if !event.is_down() => {
+                            let state = *game.world.fetch::<GameState>();
+                            if !game.world.fetch::<Designer>().open {
+                                info!("Opening the options");
+                                if state == GameState::Running {
+                                    *game.world.fetch_mut::<GameState>() = GameState::Paused;
+                                }
+                                let mut menu = game.world.fetch_mut::<OptionsMenu>();
+                                menu.open = true;
+                                menu.capturing = false;
+                            }
+                        }
+                        Key::F2 => (),
+                        #[cfg(not(target_arch = "wasm32"))]
+                        Key::F8 if !event.is_down() => {
+                            let events = game.world.get_mut::<EventLog>()
+                                .expect("Event log is always present");
+                            if let Err(e) = events.toggle() {
+                                error!("Can't switch the event log: {}", e);
+                            }
+                        }
+                        #[cfg(not(target_arch = "wasm32"))]
+                        Key::F8 => (),
+                        // Developer command, records the demo flight shown by the attract mode.
+                        #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+                        Key::F9 if !event.is_down() => {
+                            match game.world.fetch::<Replay>().save_demo() {
+                                Ok(path) => info!("Demo saved to {}", path.display()),
+                                Err(e) => error!("Can't save demo: {}", e),
+                            }
+                        }
+                        // Developer command, runs a single frame of the paused game.
+                        #[cfg(debug_assertions)]
+                        Key::F10 if !event.is_down() => game.step_paused(),
+                        #[cfg(debug_assertions)]
+                        Key::F10 => (),
+                        // Developer command, stores the world as a level file.
+                        #[cfg(all(feature = "serialize", not(target_arch = "wasm32")))]
+                        Key::F7 if !event.is_down() => {
+                            match serialize::export_level(&mut game.world) {
+                                Ok(path) => info!("Level exported to {}", path.display()),
+                                Err(e) => error!("Can't export level: {}", e),
+                            }
+                        }
+                        #[cfg(all(feature = "serialize", not(target_arch = "wasm32")))]
+                        Key::F7 => (),
+                        #[cfg(all(feature = "serialize", not(target_arch = "wasm32")))]
+                        Key::F5 | Key::F6 if !event.is_down() => {
+                            let state = *game.world.fetch::<GameState>();
+                            let under_way = matches!(state, GameState::Running | GameState::Paused);
+                            let mode = if event.key() == Key::F5 {
+                                MenuMode::Save
+                            } else {
+                                MenuMode::Load
+                            };
+                            // Only a level in the middle is worth saving
+                            if (under_way || mode == MenuMode::Load)
+                                && !game.world.fetch::<Designer>().open
+                            {
+                                if state == GameState::Running {
+                                    *game.world.fetch_mut::<GameState>() = GameState::Paused;
+                                }
+                                game.world.fetch_mut::<SaveMenu>().open(mode, Path::new(SAVE_DIR));
+                            }
+                        }
+                        #[cfg(all(feature = "serialize", not(target_arch = "wasm32")))]
+                        Key::F5 | Key::F6 => (),
+                        Key::Return
+                            if !event.is_down()
+                                && (held.contains(Key::LAlt) || held.contains(Key::RAlt)) =>
+                        {
+                            // The press went through as a normal key
+                            held.release(Source::Keyboard(Key::Return));
+                            toggle_fullscreen(&mut game.world, &gfx.borrow(), &window);
+                        }
+                        Key::Return if !event.is_down() => {
+                            if game.state().finished() {
+                                game.restart();
+                            }
+                        }
+                        Key::Return => (),
+                        Key::R if !event.is_down() => {
+                            if game.state().finished() {
+                                game.restart();
+                                *game.world.fetch_mut::<GameState>() = GameState::Running;
+                            }
+                        }
+                        Key::R => (),
+                        Key::End | Key::F1 if !event.is_down() => {
+                            game.restart();
+                        }
+                        Key::End | Key::F1 => (),
+                        Key::Key2 if !event.is_down() => {
+                            if *game.world.fetch::<GameState>() == GameState::Started {
+                                let players = game.world.fetch::<Players>().next();
+                                info!("Players: {}", players);
+                                *game.world.fetch_mut::<Players>() = players;
+                                game.restart();
+                            }
+                        }
+                        Key::Key2 => (),
+                        Key::Key3 if !event.is_down() => {
+                            if *game.world.fetch::<GameState>() == GameState::Started {
+                                let difficulty = game.world.fetch::<Difficulty>().next();
+                                info!("Difficulty: {}", difficulty);
+                                game.world.insert(difficulty);
+                                // The assists were for the old one
+                                game.world.insert(Assists::default());
+                                game.restart();
+                            }
+                        }
+                        Key::Key3 => (),
+                        Key::Key4 if !event.is_down() => {
+                            let state = *game.world.fetch::<GameState>();
+                            let lost = matches!(state, GameState::Lost(_));
+                            let offered = game.world.fetch::<Assists>().offered()
+                                .filter(|_| game.world.fetch::<Options>().assists);
+                            if let (true, Some(assist)) = (lost, offered) {
+                                info!("Assist accepted: {}", assist);
+                                game.world.fetch_mut::<Assists>().accept();
+                                game.restart();
+                            }
+                        }
+                        Key::Key4 => (),
+                        Key::Key5 if !event.is_down() => {
+                            if *game.world.fetch::<GameState>() == GameState::Started {
+                                info!("Designing the ship");
+                                let mut designer = game.world.fetch_mut::<Designer>();
+                                designer.open = true;
+                                designer.selected = 0;
+                            }
+                        }
+                        Key::Key5 => (),
+                        Key::Key6 if !event.is_down() => {
+                            if *game.world.fetch::<GameState>() == GameState::Started {
+                                let design = {
+                                    let loadout = game.world.fetch::<Loadout>();
+                                    let shipyard = game.world.fetch::<Shipyard>();
+                                    let profiles = game.world.fetch::<Profiles>();
+                                    let progress = &profiles.current().progress;
+                                    let current = &loadout.current().name;
+                                    shipyard.next(current, |ship| progress.available(ship)).clone()
+                                };
+                                info!("Ship: {}", design.name);
+                                game.world.fetch_mut::<Loadout>().custom = Some(design);
+                                game.restart();
+                            }
+                        }
+                        Key::Key6 => (),
+                        Key::Key7 if !event.is_down() => {
+                            if *game.world.fetch::<GameState>() == GameState::Started {
+                                game.world.fetch_mut::<Profiles>().next();
+                                apply_profile(&mut game.world);
+                                game.restart();
+                            }
+                        }
+                        Key::Key7 => (),
+                        Key::Back if !event.is_down() => {
+                            if *game.world.fetch::<GameState>() == GameState::Started {
+                                game.world.fetch_mut::<Tutorial>().skip();
+                            }
+                        }
+                        Key::Back => (),
+                        Key::Equals | Key::Add if !event.is_down() => {
+                            let viewport = game.world.get_mut::<Viewport>()
+                                .expect("Viewport is always present");
+                            viewport.zoom *= ZOOM_FACTOR;
+                            viewport.adjust_to_window_size(&gfx.borrow_mut(), &window);
+                            info!("Zoom in: {:?}", viewport);
+                        }
+                        Key::Equals | Key::Add => (),
+                        Key::Subtract | Key::Minus if !event.is_down() => {
+                            let viewport = game.world.get_mut::<Viewport>()
+                                .expect("Viewport is always present");
+                            viewport.zoom /= ZOOM_FACTOR;
+                            viewport.adjust_to_window_size(&gfx.borrow_mut(), &window);
+                            info!("Zoom out: {:?}", viewport);
+                        }
+                        Key::Subtract | Key::Minus => (),
+                        Key::L if !event.is_down() => {
+                            let show = game.world.get_mut::<ShowLagrange>()
+                                .expect("Lagrange switch is always present");
+                            show.0 = !show.0;
+                        }
+                        Key::L => (),
+                        Key::G if !event.is_down() => {
+                            let show = game.world.get_mut::<ShowGravityField>()
+                                .expect("Gravity field switch is always present");
+                            show.0 = !show.0;
+                        }
+                        Key::G => (),
+                        Key::C if !event.is_down() => {
+                            let show = game.world.get_mut::<ShowGrid>()
+                                .expect("Grid switch is always present");
+                            show.0 = !show.0;
+                        }
+                        Key::C => (),
+                        Key::T if !event.is_down() => {
+                            let (entities, landings, mut target) = game.world.system_data::<(
+                                Entities,
+                                ReadStorage<Landing>,
+                                Write<TargetPad>,
+                            )>();
+                            target.cycle(&entities, &landings);
+                        }
+                        Key::T => (),
+                        Key::F if !event.is_down() => {
+                            let free = game.world.get_mut::<FreeCamera>()
+                                .expect("Camera mode is always present");
+                            free.0 = !free.0;
+                            info!("Free camera: {}", free.0);
+                        }
+                        Key::F => (),
+                        Key::Z if !event.is_down() => {
+                            let fit = game.world.get_mut::<FitView>()
+                                .expect("Zoom to fit is always present");
+                            fit.requested = true;
+                        }
+                        Key::Z => (),
+                        Key::Tab if !event.is_down() => {
+                            let spectator = game.world.get_mut::<Spectator>()
+                                .expect("Spectator is always present");
+                            spectator.next_requested = true;
+                        }
+                        Key::Tab => (),
+                        key if event.is_down() => {
+                            info!("Key down: {:?} ({:?})", key, bound);
+                            held.set(Source::Keyboard(key), bound);
+                        }
+                        key => {
+                            held.release(Source::Keyboard(key));
+                            info!("Key up: {:?}", key);
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        let idle = *game.world.fetch::<GameState>() == GameState::Started
+            && !game.world.fetch::<Designer>().open
+            && !game.world.fetch::<OptionsMenu>().open
+            && game.world.fetch::<Replay>().mode == ReplayMode::Recording
+            && idle_since.elapsed() >= ATTRACT_DELAY;
+        #[cfg(all(feature = "serialize", not(target_arch = "wasm32")))]
+        let idle = idle && game.world.fetch::<SaveMenu>().mode.is_none();
+        if let (true, Some(demo)) = (idle, &demo) {
+            start_attract(&mut game.world, demo);
+        }
+
+        let held = game.world.fetch::<HeldKeys>().keys();
+        *game.world.fetch_mut::<Keys>() = held;
+        trace!("Running a frame");
+        game.step();
+        gfx.borrow_mut().clear(Color::BLACK);
+        draw.dispatch(&game.world);
+        if screenshot_requested {
+            screenshot_requested = false;
+            #[cfg(not(target_arch = "wasm32"))]
+            match screenshot(&mut gfx.borrow_mut(), &window) {
+                Ok(path) => info!("Screenshot saved to {}", path.display()),
+                Err(e) => error!("Can't save screenshot: {}", e),
+            }
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            if let Some(export) = &mut export {
+                let replay = game.world.fetch::<Replay>();
+                export.frame(&mut gfx.borrow_mut(), &window, &replay);
+                if replay.mode == ReplayMode::Finished {
+                    info!("Replay exported into {}", export.dir.display());
+                    break 'mainloop;
+                }
+            }
+        }
+        gfx.borrow_mut().present(&window)?;
+        #[cfg(not(target_arch = "wasm32"))]
+        update_diagnostics(&game.world);
+        #[cfg(all(feature = "leaderboard", not(target_arch = "wasm32")))]
+        {
+            let won = *game.world.fetch::<GameState>() == GameState::Won;
+            let recording = game.world.fetch::<Replay>().mode == ReplayMode::Recording;
+            // Assisted runs don't compete with the others
+            let assisted = game.world.fetch::<Assists>().level > 0;
+            let url = config
+                .leaderboard
+                .as_ref()
+                .filter(|_| won && !submitted && recording && !assisted);
+            if let Some(url) = url {
+                submit_score(
+                    url,
+                    &game.world.fetch::<Profiles>().current().name,
+                    *game.world.fetch::<Players>(),
+                    *game.world.fetch::<Difficulty>(),
+                    game.world.fetch::<LevelTime>().0,
+                    scores_sender.clone(),
+                );
+            }
+            submitted = won;
+            while let Ok(entries) = scores.try_recv() {
+                game.world.fetch_mut::<Leaderboard>().entries = entries;
+            }
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let mut profiles = game.world.fetch_mut::<Profiles>();
+            if profiles.unsaved() {
+                if let Err(e) = profiles.save(Path::new(PROFILE_DIR)) {
+                    error!("Can't save profiles: {}", e);
+                }
+            }
+            let mut options = game.world.fetch_mut::<Options>();
+            if options.unsaved {
+                if let Err(e) = options.save(Path::new(OPTIONS_FILE)) {
+                    error!("Can't save options: {}", e);
+                }
+            }
+        }
+        let demo_over = game.world.fetch::<Attract>().0.is_some()
+            && game.world.fetch::<Replay>().mode == ReplayMode::Finished;
+        if demo_over {
+            stop_attract(&mut game.world);
+            idle_since = Instant::now();
+        }
+
+        // The browser takes care of the timing, we can't block there.
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let min_frame_time = game.world.fetch::<Options>().min_frame_time();
+            if let Some(min_frame_time) = min_frame_time {
+                let elapsed = frame_start.elapsed();
+                if elapsed < min_frame_time {
+                    thread::sleep(min_frame_time - elapsed);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// How many of the last log lines go into a crash report.
+#[cfg(not(target_arch = "wasm32"))]
+const CRASH_LOG_LINES: usize = 200;
+
+/// What we know about the game in case it crashes.
+#[cfg(not(target_arch = "wasm32"))]
+struct Diagnostics {
+    log: VecDeque<String>,
+    /// Snapshot of the world from the last frame.
+    snapshot: String,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+static DIAGNOSTICS: Mutex<Diagnostics> = Mutex::new(Diagnostics {
+    log: VecDeque::new(),
+    snapshot: String::new(),
+});
+
+/// Logger that keeps the recent lines around for crash reports.
+///
+/// Info and more important lines are kept even if the wrapped logger filters them out.
+#[cfg(not(target_arch = "wasm32"))]
+struct RecentLog {
+    inner: env_logger::Logger,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl RecentLog {
+    fn init() {
+        let inner = env_logger::Builder::from_default_env().build();
+        let max_level = inner.filter().max(LevelFilter::Info);
+        log::set_boxed_logger(Box::new(RecentLog { inner })).expect("Logger set twice");
+        log::set_max_level(max_level);
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Log for RecentLog {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Info || self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if record.level() <= Level::Info {
+            if let Ok(mut diagnostics) = DIAGNOSTICS.lock() {
+                if diagnostics.log.len() == CRASH_LOG_LINES {
+                    diagnostics.log.pop_front();
+                }
+                let line = format!("{} {}: {}", record.level(), record.target(), record.args());
+                diagnostics.log.push_back(line);
+            }
+        }
+        self.inner.log(record);
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Remembers the interesting parts of the world for a possible crash report.
+#[cfg(not(target_arch = "wasm32"))]
+fn update_diagnostics(world: &World) {
+    let (state, time, ships, positions, speeds, rotations) = world.system_data::<(
+        ReadExpect<GameState>,
+        Read<LevelTime>,
+        ReadStorage<Ship>,
+        ReadStorage<Position>,
+        ReadStorage<Speed>,
+        ReadStorage<Rotation>,
+    )>();
+    let mut snapshot = format!("Game state: {:?}\nLevel time: {:?}\n", *state, time.0);
+    for (ship, pos, speed, rotation) in (&ships, &positions, &speeds, &rotations).join() {
+        snapshot += &format!(
+            "Ship at {:?}, speed {:?}, rotation {}, temperature {}\n",
+            pos.0, speed.0, rotation.0, ship.temperature,
+        );
+    }
+    if let Ok(mut diagnostics) = DIAGNOSTICS.lock() {
+        diagnostics.snapshot = snapshot;
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write_crash_report(panic: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let diagnostics = match DIAGNOSTICS.try_lock() {
+        Ok(diagnostics) => diagnostics,
+        Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+        Err(TryLockError::WouldBlock) => return Err("Diagnostics are locked".into()),
+    };
+    let stamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+    let path = PathBuf::from(format!("thrust-crash-{}.txt", stamp));
+    let mut file = BufWriter::new(File::create(&path)?);
+    writeln!(file, "Thrust {} crashed: {}", env!("CARGO_PKG_VERSION"), panic)?;
+    writeln!(file, "Level: {}", LEVEL_ID)?;
+    // Nothing is random in the game yet
+    writeln!(file, "Seed: none")?;
+    writeln!(file, "{}", diagnostics.snapshot)?;
+    writeln!(file, "Last log lines:")?;
+    for line in &diagnostics.log {
+        writeln!(file, "{}", line)?;
+    }
+    file.flush()?;
+    Ok(path)
+}
+
+/// Writes a crash report on panic, before the usual panic handling.
+#[cfg(not(target_arch = "wasm32"))]
+fn install_crash_handler() {
+    let default = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        match write_crash_report(&info.to_string()) {
+            Ok(path) => eprintln!("Crash report written to {}", path.display()),
+            Err(e) => eprintln!("Can't write a crash report: {}", e),
+        }
+        default(info);
+    }));
+}
+
+/// Sets up the logging, and on desktop also the crash reports.
+pub fn init_logging() {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        RecentLog::init();
+        install_crash_handler();
+    }
+    #[cfg(target_arch = "wasm32")]
+    env_logger::init();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quicksilver::geom::Rectangle;
+    use shred::MultiDispatchController;
+
+    use crate::components::{
+        AtLagrange, Capture, Condition, Lagrange, LagrangePoint, Mass, Planet, Strain,
+    };
+    use crate::physics::{G, LAND_DISTANCE, OVERHEAT_DAMAGE};
+    use crate::state::LostReason;
+    use crate::state::RunStats;
+    use crate::test_support::TestWorld;
+
+    fn assert_close(actual: Vector, expected: Vector) {
+        assert!(
+            actual.distance(expected) < 0.001,
+            "{:?} is not {:?}",
+            actual,
+            expected,
+        );
+    }
+
+    #[test]
+    fn gravity_pulls_bodies_together() {
+        let mut world = TestWorld::new();
+        let a = world.body(Vector::new(0, 0), Vector::ZERO, 10.0);
+        let b = world.body(Vector::new(100, 0), Vector::ZERO, 10.0);
+        world.step(1);
+        let (speed_a, speed_b) = (world.speed(a), world.speed(b));
+        assert!(speed_a.x > 0.0);
+        assert_eq!(speed_a.y, 0.0);
+        assert_close(speed_b, -speed_a);
+
+        world.step(10);
+        assert!(world.position(a).distance(world.position(b)) < 100.0);
+    }
+
+    #[test]
+    fn gravity_weakens_with_square_of_distance() {
+        let pulled = |distance: f32| {
+            let mut world = TestWorld::new();
+            let body = world.body(Vector::ZERO, Vector::ZERO, 1.0);
+            world.body(Vector::new(distance, 0.0), Vector::ZERO, 100.0);
+            world.step(1);
+            world.speed(body).x
+        };
+        let ratio = pulled(100.0) / pulled(200.0);
+        assert!((ratio - 4.0).abs() < 0.001, "Ratio {}", ratio);
+    }
+
+    #[test]
+    fn no_gravity_when_too_close() {
+        let mut world = TestWorld::new();
+        let a = world.body(Vector::new(0, 0), Vector::ZERO, 10.0);
+        let b = world.body(Vector::new(5, 5), Vector::ZERO, 10.0);
+        world.step(1);
+        assert_eq!(world.speed(a), Vector::ZERO);
+        assert_eq!(world.speed(b), Vector::ZERO);
+    }
+
+    #[test]
+    fn movement_follows_speed() {
+        let mut world = TestWorld::new();
+        let body = world.body(Vector::new(10, 20), Vector::new(10, -5), 1.0);
+        // 1 second
+        world.step(100);
+        assert_close(world.position(body), Vector::new(20, 15));
+    }
+
+    #[test]
+    fn movement_scales_with_difficulty() {
+        let mut world = TestWorld::new();
+        world.world.insert(DifficultyTimeMod(2.0));
+        let body = world.body(Vector::ZERO, Vector::new(1, 0), 1.0);
+        world.step(100);
+        assert_close(world.position(body), Vector::new(2, 0));
+    }
+
+    #[test]
+    fn rotation_wraps_around() {
+        let mut world = TestWorld::new();
+        let forward = world.spinner(350.0, 20.0);
+        let backward = world.spinner(10.0, -20.0);
+        world.step(100);
+        assert!((world.rotation(forward) - 10.0).abs() < 0.01);
+        assert!((world.rotation(backward) - 350.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn physics_runs_only_while_running() {
+        let mut world = TestWorld::new();
+        let mut plan = |state| {
+            world.world.insert(state);
+            PhysicsSystems.plan(world.world.system_data())
+        };
+        assert_eq!(plan(GameState::Running), 1);
+        assert_eq!(plan(GameState::Paused), 0);
+        assert_eq!(plan(GameState::Started), 0);
+        assert_eq!(plan(GameState::Won), 0);
+    }
+
+    #[test]
+    fn victory_on_landing() {
+        let mut world = TestWorld::new();
+        world.ship(Vector::new(0, 0));
+        world.landing(Vector::new(LAND_DISTANCE / 2.0, 0.0));
+        world.hold();
+        assert_eq!(world.state(), GameState::Won);
+    }
+
+    #[test]
+    fn landing_takes_holding_still() {
+        let mut world = TestWorld::new();
+        let ship = world.ship(Vector::new(0, 0));
+        world.landing(Vector::new(LAND_DISTANCE / 2.0, 0.0));
+        world.step(10);
+        assert_eq!(world.state(), GameState::Running);
+        assert!(world.world.read_storage::<Capture>().contains(ship));
+
+        let mut world = TestWorld::new();
+        let ship = world.ship(Vector::new(0, 0));
+        world.world.write_storage::<Speed>().insert(ship, Speed(Vector::new(1_000, 0))).unwrap();
+        world.landing(Vector::new(0, 0));
+        world.step(1);
+        assert!(!world.world.read_storage::<Capture>().contains(ship));
+    }
+
+    #[test]
+    fn looking_around_while_paused() {
+        let mut world = TestWorld::new();
+        let ship = world.ship(Vector::new(0, 0));
+        let start = world.world.fetch::<Viewport>().rect.pos;
+        world.world.fetch_mut::<Keys>().insert(Key::Up);
+        *world.world.fetch_mut::<GameState>() = GameState::Paused;
+        world.step(10);
+        let moved = world.world.fetch::<Viewport>().rect.pos;
+        assert!(moved.y < start.y);
+        assert_eq!(moved.x, start.x);
+        assert_eq!(world.position(ship), Vector::ZERO);
+        // Once flying, the arrows are for the ship
+        *world.world.fetch_mut::<GameState>() = GameState::Running;
+        world.step(10);
+        assert_eq!(world.world.fetch::<Viewport>().rect.pos, moved);
+    }
+
+    #[test]
+    fn no_victory_away_from_landing() {
+        let mut world = TestWorld::new();
+        world.ship(Vector::new(0, 0));
+        world.landing(Vector::new(LAND_DISTANCE * 2.0, 0.0));
+        world.step(10);
+        assert_eq!(world.state(), GameState::Running);
+    }
+
+    #[test]
+    fn results_of_the_run() {
+        let score = |offset: f32| {
+            let mut world = TestWorld::new();
+            world.ship(Vector::new(0, 0));
+            world.landing(Vector::new(offset, 0.0));
+            world.hold();
+            assert_eq!(world.state(), GameState::Won);
+            let stats = world.world.fetch::<RunStats>().clone();
+            assert!(stats.accuracy.unwrap() > 0.0);
+            assert_eq!(stats.fuel_used, 0.0);
+            stats.score(Duration::from_secs(10), Difficulty::Normal)
+        };
+        assert!(score(0.0) > score(LAND_DISTANCE / 2.0));
+
+        let lost = RunStats { accuracy: None, ..RunStats::default() };
+        assert_eq!(lost.score(Duration::from_secs(10), Difficulty::Normal), 0);
+    }
+
+    #[test]
+    fn refueling_keeps_the_fuel_used() {
+        let mut world = TestWorld::new();
+        let ent = world.ship(Vector::new(0, 0));
+        world.world.write_storage::<Ship>().get_mut(ent).unwrap().fuel -= 2.0;
+        world.step(1);
+        {
+            let mut ships = world.world.write_storage::<Ship>();
+            let ship = ships.get_mut(ent).unwrap();
+            ship.fuel = ship.max_fuel;
+        }
+        world.step(1);
+        let stats = world.world.fetch::<RunStats>();
+        assert!((stats.fuel_used - 2.0).abs() < 0.001);
+        assert_eq!(stats.fuel_left, stats.fuel_total);
+    }
+
+    #[test]
+    fn smaller_pads_are_worth_more() {
+        let small = Landing::new(0.5);
+        assert!(small.points > Landing::default().points);
+
+        let mut world = TestWorld::new();
+        world.ship(Vector::new(0, 0));
+        world.landing(Vector::new(LAND_DISTANCE * 2.0, 0.0));
+        let pad = world
+            .world
+            .create_entity()
+            .with(small)
+            .with(Position(Vector::new(LAND_DISTANCE * 0.4, 0.0)))
+            .build();
+        world.hold();
+        assert_eq!(world.state(), GameState::Won);
+        let stats = world.world.fetch::<RunStats>();
+        assert_eq!(stats.pads, vec![pad]);
+        assert_eq!(stats.pad_points, small.points);
+    }
+
+    #[test]
+    fn parking_at_a_lagrange_point() {
+        let mut world = TestWorld::new();
+        let sun = world.world.create_entity().with(Position(Vector::ZERO)).with(Mass(50.0)).build();
+        let planet = world.body(Vector::new(250, 0), Vector::new(0, -10), 20.0);
+        world.world.write_storage::<Lagrange>().insert(planet, Lagrange { primary: sun }).unwrap();
+        let pad = world
+            .world
+            .create_entity()
+            .with(Landing::default())
+            .with(AtLagrange { secondary: planet, point: LagrangePoint::L4 })
+            .with(Position(Vector::ZERO))
+            .with(Speed(Vector::ZERO))
+            .build();
+        world.step(1);
+        // The landing area moves around the sun together with the planet
+        assert!((world.speed(pad).len() - 10.0).abs() < 0.5, "{:?}", world.speed(pad));
+
+        // Standing still, the ship can't land in it
+        let ship = world.ship(world.position(pad));
+        world.step(10);
+        assert!(!world.world.read_storage::<Capture>().contains(ship));
+
+        // Flying along, it can
+        let (pos, speed) = (world.position(pad), world.speed(pad));
+        world.world.write_storage::<Position>().insert(ship, Position(pos)).unwrap();
+        world.world.write_storage::<Speed>().insert(ship, Speed(speed)).unwrap();
+        world.hold();
+        assert_eq!(world.state(), GameState::Won);
+    }
+
+    #[test]
+    fn easy_difficulty_lands_farther() {
+        let mut world = TestWorld::new();
+        world.world.insert(Difficulty::Easy.rules());
+        world.ship(Vector::new(0, 0));
+        world.landing(Vector::new(LAND_DISTANCE * 1.2, 0.0));
+        world.hold();
+        assert_eq!(world.state(), GameState::Won);
+    }
+
+    #[test]
+    fn assists_escalate() {
+        let mut assists = Assists { failures: 1, level: 0 };
+        assert_eq!(assists.offered(), None);
+        assists.failures = 2;
+        assert_eq!(assists.offered(), Some("slower time"));
+        assists.accept();
+        assert_eq!(assists, Assists { failures: 0, level: 1 });
+        let normal = Difficulty::Normal.rules();
+        let rules = assists.apply(normal);
+        assert!(rules.time_mod < normal.time_mod);
+        assert_eq!(rules.land_distance, normal.land_distance);
+
+        assists.level = 3;
+        assists.failures = 5;
+        assert_eq!(assists.offered(), None);
+        assert!(assists.apply(normal).fuel > normal.fuel);
+    }
+
+    #[test]
+    fn thrusters_need_fuel() {
+        let mut world = TestWorld::new();
+        let ship = world.ship(Vector::new(0, 0));
+        world.world.write_storage::<Ship>().get_mut(ship).unwrap().fuel = 0.05;
+        world.world.fetch_mut::<Keys>().insert(PLAYER_KEYS[0].forward);
+        world.step(10);
+        assert_eq!(world.world.read_storage::<Ship>().get(ship).unwrap().fuel, 0.0);
+        let speed = world.speed(ship);
+        assert_ne!(speed, Vector::ZERO);
+        world.step(10);
+        assert_eq!(world.speed(ship), speed);
+    }
+
+    fn conditions(world: &TestWorld) -> Vec<Condition> {
+        let thrusters = world.world.read_storage::<Thruster>();
+        thrusters.join().map(|thruster| thruster.condition).collect()
+    }
+
+    #[test]
+    fn crash_damages_one_thruster() {
+        let mut world = TestWorld::new();
+        let ship = world.ship(Vector::new(0, 0));
+        world.world.write_storage::<Speed>().insert(ship, Speed(Vector::new(20, 0))).unwrap();
+        world.world
+            .create_entity()
+            .with(Planet { color: Color::WHITE, radius: 20.0 })
+            .with(Position(Vector::new(30, 0)))
+            .build();
+        // Flies all the way through the planet
+        world.step(300);
+        let conditions = conditions(&world);
+        let degraded = conditions.iter().filter(|c| **c == Condition::Degraded).count();
+        let intact = conditions.iter().filter(|c| **c == Condition::Intact).count();
+        assert_eq!(degraded, 1);
+        assert_eq!(intact, conditions.len() - 1);
+    }
+
+    #[test]
+    fn overheating_damages_firing_thrusters() {
+        let mut world = TestWorld::new();
+        let ship = world.ship(Vector::new(0, 0));
+        {
+            let mut ships = world.world.write_storage::<Ship>();
+            let ship = ships.get_mut(ship).unwrap();
+            ship.temp_dec = 0.0;
+            ship.temperature = ship.max_temp * OVERHEAT_DAMAGE - 0.001;
+        }
+        world.world.fetch_mut::<Keys>().insert(PLAYER_KEYS[0].forward);
+        world.step(1);
+        let thrusters = world.world.read_storage::<Thruster>();
+        for thruster in thrusters.join() {
+            let expected = if thruster.key == PLAYER_KEYS[0].forward {
+                Condition::Degraded
+            } else {
+                Condition::Intact
+            };
+            assert_eq!(thruster.condition, expected);
+        }
+    }
+
+    #[test]
+    fn damaged_thrusters_push_less() {
+        let pushed = |condition| {
+            let mut world = TestWorld::new();
+            let ship = world.ship(Vector::new(0, 0));
+            for thruster in (&mut world.world.write_storage::<Thruster>()).join() {
+                thruster.condition = condition;
+            }
+            world.world.fetch_mut::<Keys>().insert(PLAYER_KEYS[0].forward);
+            world.step(1);
+            world.speed(ship)
+        };
+        assert_close(pushed(Condition::Degraded), pushed(Condition::Intact) * 0.5);
+        assert_eq!(pushed(Condition::Dead), Vector::ZERO);
+    }
+
+    #[test]
+    fn thrust_is_felt() {
+        let mut world = TestWorld::new();
+        let ship = world.ship(Vector::new(0, 0));
+        world.world.fetch_mut::<Keys>().insert(PLAYER_KEYS[0].forward);
+        world.step(1);
+        let strain = *world.world.read_storage::<Strain>().get(ship).unwrap();
+        let push = world.world.read_storage::<Thruster>()
+            .join()
+            .find(|thruster| thruster.key == PLAYER_KEYS[0].forward)
+            .unwrap()
+            .push;
+        assert!((strain.g - push / G).abs() < 0.001, "{:?}", strain);
+        assert_eq!(strain.over, Duration::default());
+    }
+
+    #[test]
+    fn tidal_forces_tear_ship_apart() {
+        let mut world = TestWorld::new();
+        world.ship(Vector::new(0, 0));
+        // Pulling from both sides, so the ship stays in place
+        for x in &[-30, 30] {
+            world.world
+                .create_entity()
+                .with(Position(Vector::new(*x, 0)))
+                .with(Mass(50_000.0))
+                .build();
+        }
+        let tolerance = world.world.fetch::<Rules>().g_tolerance;
+        let frames = (tolerance.as_nanos() / test_support::FRAME.as_nanos()) as usize;
+        world.step(frames / 2);
+        assert_eq!(world.state(), GameState::Running);
+        let damaged = conditions(&world).into_iter().filter(|c| *c != Condition::Intact).count();
+        assert_eq!(damaged, 1);
+        world.step(frames / 2);
+        assert_eq!(world.state(), GameState::Lost(LostReason::TornApart));
+    }
+
+    #[test]
+    fn race_needs_only_one_ship_landed() {
+        let mut world = TestWorld::new();
+        world.world.insert(Players::Race);
+        world.ship(Vector::new(0, 0));
+        world.ship(Vector::new(1000, 0));
+        world.landing(Vector::new(0, 0));
+        world.hold();
+        assert_eq!(world.state(), GameState::Won);
+
+        let mut world = TestWorld::new();
+        world.world.insert(Players::Coop);
+        world.ship(Vector::new(0, 0));
+        world.ship(Vector::new(1000, 0));
+        world.landing(Vector::new(0, 0));
+        world.hold();
+        assert_eq!(world.state(), GameState::Running);
+    }
+
+    #[test]
+    fn game_runs_without_graphics() {
+        let mut game = Game::new();
+        game.step();
+        assert_eq!(game.state(), GameState::Started);
+        game.world_mut().fetch_mut::<GameState>().toggle();
+        game.step();
+        assert_eq!(game.state(), GameState::Running);
+        assert!(game.world().fetch::<Replay>().frames.len() <= 1);
+        game.restart();
+        assert_eq!(game.state(), GameState::Started);
+    }
+
+    #[test]
+    fn checksums_use_fnv() {
+        // The checksums are stored in the replays, the hash must never change
+        let mut hasher = Fnv::new();
+        assert_eq!(hasher.0, 0xcbf2_9ce4_8422_2325);
+        hasher.write(b"a");
+        assert_eq!(hasher.0, 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn replays_play_the_same() {
+        let mut game = Game::new();
+        *game.world_mut().fetch_mut::<GameState>() = GameState::Running;
+        for frame in 0..100 {
+            if frame == 10 {
+                game.world_mut().fetch_mut::<Keys>().insert(PLAYER_KEYS[0].forward);
+            }
+            if frame == 60 {
+                game.world_mut().fetch_mut::<Keys>().clear();
+            }
+            game.step();
+        }
+        let mut replay = Replay::clone(&game.world().fetch());
+        // The playback starts by restarting the level, so the entities get other ids than when
+        // recording
+        assert_eq!(check_replay(&replay).unwrap(), 100);
+        // Something actually happened in the meantime
+        let checksums = replay_checksums(&replay);
+        assert_ne!(checksums[0], checksums[50]);
+        let checked = &mut replay.frames[CHECKSUM_INTERVAL - 1].checksum;
+        assert_eq!(*checked, Some(checksums[CHECKSUM_INTERVAL - 1]));
+        *checked = checked.map(|checksum| checksum + 1);
+        assert!(check_replay(&replay).is_err());
+    }
+
+    #[test]
+    fn demo_plays_to_the_end() {
+        let demo = Replay::load(Path::new("static/demo.replay")).unwrap();
+        assert_eq!(check_replay(&demo).unwrap(), demo.frames.len());
+
+        // The same way the attract mode shows it
+        let mut game = Game::new();
+        start_attract(game.world_mut(), &demo);
+        for _ in 0..=demo.frames.len() {
+            game.step();
+        }
+        let replay = game.world().fetch::<Replay>();
+        assert_eq!(replay.mode, ReplayMode::Finished);
+        assert_eq!(replay.diverged, None);
+    }
+
+    #[test]
+    fn menus_let_go_of_rebound_keys() {
+        let mut game = Game::new();
+        // Up and Down swap places
+        game.world_mut().fetch_mut::<Options>().bind(0, 1, Key::Up);
+        let bound = game.world().fetch::<Options>().translate(Key::Up);
+        assert_eq!(bound, Some(Key::Down));
+        game.world_mut().fetch_mut::<HeldKeys>().set(Source::Keyboard(Key::Up), bound);
+        release_key(game.world(), Key::Up);
+        assert!(game.world().fetch::<HeldKeys>().keys().is_empty());
+    }
+
+    #[test]
+    fn stepping_a_paused_game() {
+        let mut game = Game::new();
+        game.step_paused();
+        assert_eq!(game.state(), GameState::Started);
+        *game.world_mut().fetch_mut::<GameState>() = GameState::Paused;
+        let time = game.world().fetch::<LevelTime>().0;
+        game.step_paused();
+        assert_eq!(game.state(), GameState::Paused);
+        assert!(game.world().fetch::<LevelTime>().0 > time);
+        assert_eq!(game.world().fetch::<Replay>().frames.len(), 1);
+    }
+
+    #[test]
+    fn chosen_ship_class_flies() {
+        let mut game = Game::new();
+        let hauler = game.world().fetch::<Shipyard>().design("Hauler").unwrap().clone();
+        game.world_mut().fetch_mut::<Loadout>().custom = Some(hauler);
+        game.restart();
+        let world = game.world();
+        let ships = world.read_storage::<Ship>();
+        let masses = world.read_storage::<Mass>();
+        for (ship, mass) in (&ships, &masses).join() {
+            assert_eq!(mass.0, 80.0);
+            assert_eq!(ship.strength, 1.5);
+            assert_eq!(ship.max_fuel, world.fetch::<Rules>().fuel * 1.6);
+        }
+    }
+
+    #[test]
+    fn clicks_land_under_the_pointer() {
+        let mut viewport = Viewport::default();
+        viewport.zoom = 2.0;
+        viewport.rect = Rectangle::new(Vector::new(100, 50), DESIGN_SIZE / 2.0);
+        let center = viewport.unproject(DESIGN_SIZE / 2.0, DESIGN_SIZE);
+        assert_close(center, Vector::new(356, 242));
+        // With the bars on the sides, their edges are the edges of the view
+        viewport.letterbox = true;
+        let window = DESIGN_SIZE + Vector::new(200, 0);
+        assert_close(viewport.unproject(Vector::new(100, 0), window), Vector::new(100, 50));
+        assert_close(viewport.unproject(window / 2.0, window), center);
+    }
+}
@@ -0,0 +1,174 @@
+//! The ships the players get, and the designer screen for placing their thrusters.
+
+use quicksilver::lifecycle::Key;
+
+use log::info;
+
+use crate::ships::ShipDesign;
+
+/// How much push all the thrusters of a ship may have together.
+pub const BUDGET: f32 = 18.0;
+
+/// Steps of the designer's controls.
+const ANGLE_STEP: f32 = 15.0;
+const MOVE_STEP: f32 = 1.0;
+const PUSH_STEP: f32 = 0.5;
+
+/// The design the ships of the players get when the level starts.
+#[derive(Clone, Debug, Default)]
+pub struct Loadout {
+    /// The one the level asks for.
+    pub level: ShipDesign,
+    /// The one the player made, replacing the level's one.
+    pub custom: Option<ShipDesign>,
+}
+
+impl Loadout {
+    pub fn current(&self) -> &ShipDesign {
+        self.custom.as_ref().unwrap_or(&self.level)
+    }
+
+    /// The player's own design, starting from the level's one.
+    pub fn edit(&mut self) -> &mut ShipDesign {
+        let level = &self.level;
+        self.custom.get_or_insert_with(|| level.clone())
+    }
+}
+
+/// The designer screen, shown before a level instead of the instructions.
+#[derive(Clone, Debug, Default)]
+pub struct Designer {
+    pub open: bool,
+    /// Index of the thruster being edited.
+    pub selected: usize,
+}
+
+impl Designer {
+    /// Edits the design by a released key.
+    ///
+    /// Returns if the key belongs to the designer, the caller shouldn't use it for anything else
+    /// then.
+    pub fn key(&mut self, design: &mut ShipDesign, key: Key) -> bool {
+        let count = design.thrusters.len();
+        let budget_left = BUDGET - design.cost();
+        let bounds = design.bounds();
+        let (min, max) = (bounds.pos, bounds.pos + bounds.size);
+        let selected = &mut design.thrusters[self.selected];
+        match key {
+            Key::Return | Key::Key5 => {
+                info!("Design done:\n{}", design);
+                self.open = false;
+                return true;
+            }
+            // The level doesn't start before the designer is closed
+            Key::Space | Key::Pause => return true,
+            Key::Up => {
+                self.selected = (self.selected + count - 1) % count;
+                return true;
+            }
+            Key::Down => {
+                self.selected = (self.selected + 1) % count;
+                return true;
+            }
+            Key::Left => selected.angle = (selected.angle - ANGLE_STEP).rem_euclid(360.0),
+            Key::Right => selected.angle = (selected.angle + ANGLE_STEP).rem_euclid(360.0),
+            Key::W => selected.position.y = (selected.position.y - MOVE_STEP).max(min.y),
+            Key::S => selected.position.y = (selected.position.y + MOVE_STEP).min(max.y),
+            Key::A => selected.position.x = (selected.position.x - MOVE_STEP).max(min.x),
+            Key::D => selected.position.x = (selected.position.x + MOVE_STEP).min(max.x),
+            Key::PageUp if budget_left >= PUSH_STEP => selected.push += PUSH_STEP,
+            Key::PageDown if selected.push > PUSH_STEP => selected.push -= PUSH_STEP,
+            Key::C => selected.control = selected.control.next(),
+            Key::Insert if budget_left >= selected.push => {
+                let copy = *selected;
+                design.thrusters.push(copy);
+                self.selected = count;
+                return true;
+            }
+            Key::Delete if count > 1 => {
+                design.thrusters.remove(self.selected);
+                self.selected = self.selected.min(count - 2);
+                return true;
+            }
+            Key::PageUp | Key::PageDown | Key::Insert | Key::Delete => (),
+            _ => return false,
+        }
+        design.thrusters[self.selected].retune();
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quicksilver::geom::Vector;
+
+    use crate::ships::{Control, Shipyard};
+
+    fn designer() -> (Designer, ShipDesign) {
+        let designer = Designer {
+            open: true,
+            selected: 0,
+        };
+        (designer, ShipDesign::default())
+    }
+
+    #[test]
+    fn builtin_ships_fit() {
+        for design in &Shipyard::default().designs {
+            assert!(design.cost() <= BUDGET, "{} is over budget", design.name);
+        }
+    }
+
+    #[test]
+    fn custom_design_replaces_level_one() {
+        let mut loadout = Loadout::default();
+        assert_eq!(loadout.current(), &loadout.level);
+        loadout.edit().thrusters.pop();
+        assert_eq!(loadout.current().thrusters.len(), 3);
+        assert_eq!(loadout.level.thrusters.len(), 4);
+    }
+
+    #[test]
+    fn push_stays_within_budget() {
+        let (mut designer, mut design) = designer();
+        for _ in 0..100 {
+            assert!(designer.key(&mut design, Key::PageUp));
+        }
+        assert!((design.cost() - BUDGET).abs() < 0.001);
+        for _ in 0..10 {
+            designer.key(&mut design, Key::Insert);
+        }
+        assert!(design.cost() <= BUDGET);
+    }
+
+    #[test]
+    fn thrusters_stay_on_hull() {
+        let (mut designer, _) = designer();
+        let mut design = Shipyard::default().design("Hauler").unwrap().clone();
+        for _ in 0..100 {
+            designer.key(&mut design, Key::W);
+            designer.key(&mut design, Key::D);
+        }
+        assert_eq!(design.thrusters[0].position, Vector::new(12, -4));
+    }
+
+    #[test]
+    fn turning_thruster_rotates_the_ship() {
+        let (mut designer, mut design) = designer();
+        // Pointing straight back from the nose doesn't turn the ship
+        designer.key(&mut design, Key::Down);
+        designer.key(&mut design, Key::Down);
+        designer.key(&mut design, Key::Down);
+        designer.key(&mut design, Key::Left);
+        designer.key(&mut design, Key::Right);
+        let main = design.thrusters[3];
+        assert_eq!(main.control, Control::Forward);
+        assert_eq!(main.rotation, 0.0);
+        designer.key(&mut design, Key::Right);
+        assert!(design.thrusters[3].rotation > 0.0);
+        assert!(!designer.key(&mut design, Key::Escape));
+        assert!(designer.key(&mut design, Key::Return));
+        assert!(!designer.open);
+    }
+}
@@ -0,0 +1,696 @@
+//! The options of the game, changed in the options menu and kept in the options file.
+//!
+//! ```text
+//! thrust-options 1
+//! fullscreen 0
+//! window 1280 720
+//! vsync 1
+//! frame-cap 0
+//! letterbox 0
+//! assists 1
+//! touch 0
+//! reduced-motion 0
+//! text-scale 1
+//! keys 1 Up Down Left Right Home PageDown
+//! keys 2 W S A D Q E
+//! pad-buttons forward DPadUp South RightTrigger
+//! pad-buttons back DPadDown LeftTrigger
+//! pad-buttons left DPadLeft LeftShoulder
+//! pad-buttons right DPadRight RightShoulder
+//! pad-buttons homing North
+//! pad-buttons repair West
+//! pad-turn LeftStickX
+//! pad-thrust none
+//! ```
+//!
+//! The keys the players choose don't reach the game as they are. Each is turned into the default
+//! key of the same action, so the ships, the tutorial and the replays know only the default ones.
+//! The gamepads turn into the default keys too.
+
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+use std::time::Duration;
+
+use quicksilver::geom::Vector;
+use quicksilver::lifecycle::{GamepadAxis, GamepadButton, Key};
+
+#[cfg(not(target_arch = "wasm32"))]
+use log::error;
+use log::info;
+
+use crate::input::{KeyMap, PadMap, PLAYER_KEYS, REPLAY_KEYS};
+use crate::render::DESIGN_SIZE;
+
+const OPTIONS_HEADER: &str = "thrust-options 1";
+
+/// Where the options are kept.
+#[cfg(not(target_arch = "wasm32"))]
+pub const OPTIONS_FILE: &str = "thrust.cfg";
+
+/// The window sizes to choose from.
+const WINDOW_SIZES: [Vector; 5] = [
+    DESIGN_SIZE,
+    Vector { x: 1280.0, y: 720.0 },
+    Vector { x: 1280.0, y: 1024.0 },
+    Vector { x: 1600.0, y: 900.0 },
+    Vector { x: 1920.0, y: 1080.0 },
+];
+
+/// The frame rates to choose from, when vsync is off.
+const FRAME_CAPS: [Option<u32>; 5] = [None, Some(30), Some(60), Some(120), Some(144)];
+
+/// The sizes of the text to choose from, relative to the default one.
+const TEXT_SCALES: [f32; 5] = [0.75, 1.0, 1.25, 1.5, 2.0];
+
+/// What the keys of a player do, in the order of `KeyMap::keys`.
+const ACTION_NAMES: [&str; 6] = ["forward", "back", "left", "right", "center view", "repair"];
+
+/// The actions in the options file, in the order of `KeyMap::keys`.
+const ACTION_IDS: [&str; 6] = ["forward", "back", "left", "right", "homing", "repair"];
+
+/// The buttons that can be given to the actions.
+///
+/// Start is left out, it pairs the gamepads and pauses the game.
+const PAD_BUTTONS: [GamepadButton; 16] = [
+    GamepadButton::South, GamepadButton::East, GamepadButton::North, GamepadButton::West,
+    GamepadButton::LeftShoulder, GamepadButton::LeftTrigger, GamepadButton::RightShoulder,
+    GamepadButton::RightTrigger, GamepadButton::Select, GamepadButton::Home,
+    GamepadButton::LeftStick, GamepadButton::RightStick, GamepadButton::DPadUp,
+    GamepadButton::DPadDown, GamepadButton::DPadLeft, GamepadButton::DPadRight,
+];
+
+/// The stick axes to choose from, or none.
+const PAD_AXES: [Option<GamepadAxis>; 5] = [
+    None,
+    Some(GamepadAxis::LeftStickX),
+    Some(GamepadAxis::LeftStickY),
+    Some(GamepadAxis::RightStickX),
+    Some(GamepadAxis::RightStickY),
+];
+
+/// Keys the game uses for its own commands, they never get to the ships.
+const COMMAND_KEYS: [Key; 15] = [
+    Key::End, Key::Space, Key::Return, Key::C, Key::F, Key::G, Key::L, Key::R, Key::T, Key::Z,
+    Key::Key2, Key::Key3, Key::Key4, Key::Key5, Key::Key6,
+];
+
+/// Can the key control a ship?
+fn bindable(key: Key) -> bool {
+    REPLAY_KEYS.contains(&key) && !COMMAND_KEYS.contains(&key)
+}
+
+fn parse_key(name: &str) -> Result<Key, Box<dyn Error>> {
+    REPLAY_KEYS
+        .iter()
+        .find(|key| format!("{:?}", key) == name)
+        .copied()
+        .filter(|key| bindable(*key))
+        .ok_or_else(|| format!("Can't control a ship with {}", name).into())
+}
+
+fn parse_button(name: &str) -> Result<GamepadButton, Box<dyn Error>> {
+    PAD_BUTTONS
+        .iter()
+        .find(|button| format!("{:?}", button) == name)
+        .copied()
+        .ok_or_else(|| format!("Unknown gamepad button {}", name).into())
+}
+
+fn parse_axis(name: &str) -> Result<Option<GamepadAxis>, Box<dyn Error>> {
+    PAD_AXES
+        .iter()
+        .find(|axis| axis_name(**axis) == name)
+        .copied()
+        .ok_or_else(|| format!("Unknown gamepad axis {}", name).into())
+}
+
+fn axis_name(axis: Option<GamepadAxis>) -> String {
+    match axis {
+        Some(axis) => format!("{:?}", axis),
+        None => "none".to_owned(),
+    }
+}
+
+fn on_off(value: bool) -> &'static str {
+    if value {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+/// The value after (or before) the current one.
+fn cycle<T: Copy + PartialEq>(values: &[T], current: T, forward: bool) -> T {
+    let position = values.iter().position(|value| *value == current);
+    let next = match (position, forward) {
+        (None, _) => 0,
+        (Some(position), true) => (position + 1) % values.len(),
+        (Some(position), false) => (position + values.len() - 1) % values.len(),
+    };
+    values[next]
+}
+
+/// The stick axis after (or before) the current one.
+///
+/// Skips the axis the other stick setting uses, one axis can't both turn and thrust.
+fn cycle_axis(
+    current: Option<GamepadAxis>,
+    other: Option<GamepadAxis>,
+    forward: bool,
+) -> Option<GamepadAxis> {
+    let axes = PAD_AXES
+        .iter()
+        .copied()
+        .filter(|axis| axis.is_none() || *axis != other)
+        .collect::<Vec<_>>();
+    cycle(&axes, current, forward)
+}
+
+/// One line of the options menu.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Setting {
+    Fullscreen,
+    Window,
+    Vsync,
+    FrameCap,
+    Letterbox,
+    Assists,
+    Touch,
+    ReducedMotion,
+    TextScale,
+    Key { player: usize, action: usize },
+    Button { action: usize },
+    PadTurn,
+    PadThrust,
+}
+
+impl Setting {
+    /// All of them, in the order of the menu.
+    pub fn all() -> Vec<Setting> {
+        let keys = (0..PLAYER_KEYS.len()).flat_map(|player| {
+            (0..ACTION_NAMES.len()).map(move |action| Setting::Key { player, action })
+        });
+        let buttons = (0..ACTION_NAMES.len()).map(|action| Setting::Button { action });
+        vec![
+            Setting::Fullscreen,
+            Setting::Window,
+            Setting::Vsync,
+            Setting::FrameCap,
+            Setting::Letterbox,
+            Setting::Assists,
+            Setting::Touch,
+            Setting::ReducedMotion,
+            Setting::TextScale,
+        ]
+        .into_iter()
+        .chain(keys)
+        .chain(buttons)
+        .chain(vec![Setting::PadTurn, Setting::PadThrust])
+        .collect()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Options {
+    pub fullscreen: bool,
+    /// The size of the window, when not fullscreen.
+    pub window: Vector,
+    /// Can be changed only on the next start.
+    pub vsync: bool,
+    /// The highest frame rate, when vsync is off.
+    pub frame_cap: Option<u32>,
+    /// Keep the design aspect ratio (with bars around) instead of showing more of the world in
+    /// bigger windows.
+    pub letterbox: bool,
+    /// Offer the assists after losing a few times.
+    pub assists: bool,
+    /// Show the on-screen buttons.
+    pub touch: bool,
+    /// No flickering and no camera flights, for the players sensitive to motion.
+    pub reduced_motion: bool,
+    /// How big the text is, relative to the default size.
+    pub text_scale: f32,
+    /// The keys of each player.
+    pub keys: [KeyMap; 2],
+    pub pad: PadMap,
+    /// Changed since it was last stored.
+    pub unsaved: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            fullscreen: false,
+            window: DESIGN_SIZE,
+            vsync: true,
+            frame_cap: None,
+            letterbox: false,
+            assists: true,
+            touch: cfg!(target_arch = "wasm32"),
+            reduced_motion: false,
+            text_scale: 1.0,
+            keys: PLAYER_KEYS,
+            pad: PadMap::default(),
+            unsaved: false,
+        }
+    }
+}
+
+impl Options {
+    /// How long a frame should take at minimum, if we need to limit the frame rate ourselves.
+    pub fn min_frame_time(&self) -> Option<Duration> {
+        if self.vsync {
+            return None;
+        }
+        self.frame_cap.map(|cap| Duration::from_secs(1) / cap)
+    }
+
+    /// The key the game knows the pressed one as.
+    ///
+    /// `None` for the default keys of the ships moved somewhere else.
+    pub fn translate(&self, key: Key) -> Option<Key> {
+        for (keys, defaults) in self.keys.iter().zip(&PLAYER_KEYS) {
+            if let Some(action) = keys.keys().iter().position(|bound| *bound == key) {
+                return Some(defaults.keys()[action]);
+            }
+        }
+        if PLAYER_KEYS.iter().any(|defaults| defaults.contains(key)) {
+            None
+        } else {
+            Some(key)
+        }
+    }
+
+    /// Puts the key to the player's action.
+    ///
+    /// If another action had the key, it gets the one the action had before.
+    pub fn bind(&mut self, player: usize, action: usize, key: Key) {
+        let old = self.keys[player].keys()[action];
+        for map in &mut self.keys {
+            let mut keys = map.keys();
+            for bound in keys.iter_mut().filter(|bound| **bound == key) {
+                *bound = old;
+            }
+            *map = KeyMap::from_keys(keys);
+        }
+        let mut keys = self.keys[player].keys();
+        keys[action] = key;
+        self.keys[player] = KeyMap::from_keys(keys);
+        info!("Player {} {}: {:?}", player + 1, ACTION_NAMES[action], key);
+        self.unsaved = true;
+    }
+
+    /// Gives the gamepad button to the action, taking it from any other one.
+    pub fn bind_button(&mut self, action: usize, button: GamepadButton) {
+        self.pad.bind(action, button);
+        info!("Gamepad {}: {:?}", ACTION_NAMES[action], button);
+        self.unsaved = true;
+    }
+
+    /// Switches the setting to the next (or previous) value.
+    ///
+    /// The keys and buttons are changed by [`bind`][Options::bind] and
+    /// [`bind_button`][Options::bind_button] instead.
+    pub fn change(&mut self, setting: Setting, forward: bool) {
+        match setting {
+            Setting::Fullscreen => self.fullscreen = !self.fullscreen,
+            Setting::Window => self.window = cycle(&WINDOW_SIZES, self.window, forward),
+            Setting::Vsync => self.vsync = !self.vsync,
+            Setting::FrameCap => self.frame_cap = cycle(&FRAME_CAPS, self.frame_cap, forward),
+            Setting::Letterbox => self.letterbox = !self.letterbox,
+            Setting::Assists => self.assists = !self.assists,
+            Setting::Touch => self.touch = !self.touch,
+            Setting::ReducedMotion => self.reduced_motion = !self.reduced_motion,
+            Setting::TextScale => {
+                self.text_scale = cycle(&TEXT_SCALES, self.text_scale, forward)
+            }
+            Setting::PadTurn => {
+                self.pad.turn = cycle_axis(self.pad.turn, self.pad.thrust, forward)
+            }
+            Setting::PadThrust => {
+                self.pad.thrust = cycle_axis(self.pad.thrust, self.pad.turn, forward)
+            }
+            Setting::Key { .. } | Setting::Button { .. } => return,
+        }
+        info!("Options changed: {:?}", self);
+        self.unsaved = true;
+    }
+
+    /// The name and the value of the setting, for the menu.
+    pub fn describe(&self, setting: Setting) -> (String, String) {
+        let (name, value) = match setting {
+            Setting::Fullscreen => ("Fullscreen", on_off(self.fullscreen).to_owned()),
+            Setting::Window => (
+                "Window size",
+                format!("{}x{}", self.window.x, self.window.y),
+            ),
+            Setting::Vsync => ("Vsync (after a restart)", on_off(self.vsync).to_owned()),
+            Setting::FrameCap => (
+                "Frame rate limit (without vsync)",
+                match self.frame_cap {
+                    Some(cap) => format!("{} fps", cap),
+                    None => "none".to_owned(),
+                },
+            ),
+            Setting::Letterbox => ("Keep the aspect ratio", on_off(self.letterbox).to_owned()),
+            Setting::Assists => ("Offer assists", on_off(self.assists).to_owned()),
+            Setting::Touch => ("Touch buttons", on_off(self.touch).to_owned()),
+            Setting::ReducedMotion => ("Reduce motion", on_off(self.reduced_motion).to_owned()),
+            Setting::TextScale => ("Text size", format!("{:.0}%", self.text_scale * 100.0)),
+            Setting::Key { player, action } => {
+                let name = format!("Player {} {}", player + 1, ACTION_NAMES[action]);
+                let key = format!("{:?}", self.keys[player].keys()[action]);
+                return (name, key);
+            }
+            Setting::Button { action } => {
+                let name = format!("Gamepad {}", ACTION_NAMES[action]);
+                let buttons = &self.pad.buttons[action];
+                let buttons = if buttons.is_empty() {
+                    "none".to_owned()
+                } else {
+                    let names = buttons.iter().map(|button| format!("{:?}", button));
+                    names.collect::<Vec<_>>().join(", ")
+                };
+                return (name, buttons);
+            }
+            Setting::PadTurn => ("Gamepad stick turning", axis_name(self.pad.turn)),
+            Setting::PadThrust => ("Gamepad stick thrusting", axis_name(self.pad.thrust)),
+        };
+        (name.to_owned(), value)
+    }
+
+    pub fn parse(content: &str) -> Result<Self, Box<dyn Error>> {
+        let mut lines = content.lines().filter(|line| !line.trim().is_empty());
+        if lines.next() != Some(OPTIONS_HEADER) {
+            return Err("Not an options file".into());
+        }
+        let mut options = Options::default();
+        for line in lines {
+            let (key, value) = line.split_at(line.find(' ').ok_or("Missing value")?);
+            let mut fields = value.split_whitespace();
+            let mut field = || fields.next().ok_or("Missing value");
+            let mut flag = || field().map(|value| value != "0");
+            match key {
+                "fullscreen" => options.fullscreen = flag()?,
+                "vsync" => options.vsync = flag()?,
+                "letterbox" => options.letterbox = flag()?,
+                "assists" => options.assists = flag()?,
+                "touch" => options.touch = flag()?,
+                "reduced-motion" => options.reduced_motion = flag()?,
+                "text-scale" => {
+                    let scale = field()?.parse::<f32>()?;
+                    if !(0.1..=10.0).contains(&scale) {
+                        return Err("Text scale out of range".into());
+                    }
+                    options.text_scale = scale;
+                }
+                "window" => {
+                    let width = field()?.parse::<f32>()?;
+                    let height = field()?.parse::<f32>()?;
+                    if [width, height].iter().any(|size| !size.is_finite() || *size <= 0.0) {
+                        return Err("Window size needs to be positive".into());
+                    }
+                    options.window = Vector::new(width, height);
+                }
+                "frame-cap" => {
+                    options.frame_cap = match field()?.parse()? {
+                        0 => None,
+                        cap => Some(cap),
+                    };
+                }
+                "keys" => {
+                    let player = field()?.parse::<usize>()?;
+                    let map = options
+                        .keys
+                        .get_mut(player.wrapping_sub(1))
+                        .ok_or("Unknown player")?;
+                    let mut keys = map.keys();
+                    for key in &mut keys {
+                        *key = parse_key(field()?)?;
+                    }
+                    *map = KeyMap::from_keys(keys);
+                }
+                "pad-buttons" => {
+                    let id = field()?;
+                    let action = ACTION_IDS
+                        .iter()
+                        .position(|known| *known == id)
+                        .ok_or("Unknown action")?;
+                    let buttons = fields.map(parse_button).collect::<Result<_, _>>()?;
+                    options.pad.buttons[action] = buttons;
+                }
+                "pad-turn" => options.pad.turn = parse_axis(field()?)?,
+                "pad-thrust" => options.pad.thrust = parse_axis(field()?)?,
+                _ => return Err(format!("Unknown line {}", line).into()),
+            }
+        }
+        if options.pad.turn.is_some() && options.pad.turn == options.pad.thrust {
+            return Err("The same stick axis can't both turn and thrust".into());
+        }
+        Ok(options)
+    }
+
+    /// The options from the file, the default ones if there are none.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(path: &Path) -> Self {
+        let options = match fs::read_to_string(path) {
+            Ok(content) => Options::parse(&content),
+            Err(e) => {
+                info!("No options in {}: {}", path.display(), e);
+                return Options::default();
+            }
+        };
+        options.unwrap_or_else(|e| {
+            error!("Broken options {}: {}", path.display(), e);
+            Options::default()
+        })
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        fs::write(path, self.to_string())?;
+        self.unsaved = false;
+        Ok(())
+    }
+}
+
+impl Display for Options {
+    /// Writes the options as the content of an options file.
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        writeln!(fmt, "{}", OPTIONS_HEADER)?;
+        writeln!(fmt, "fullscreen {}", self.fullscreen as u8)?;
+        writeln!(fmt, "window {} {}", self.window.x, self.window.y)?;
+        writeln!(fmt, "vsync {}", self.vsync as u8)?;
+        writeln!(fmt, "frame-cap {}", self.frame_cap.unwrap_or_default())?;
+        writeln!(fmt, "letterbox {}", self.letterbox as u8)?;
+        writeln!(fmt, "assists {}", self.assists as u8)?;
+        writeln!(fmt, "touch {}", self.touch as u8)?;
+        writeln!(fmt, "reduced-motion {}", self.reduced_motion as u8)?;
+        writeln!(fmt, "text-scale {}", self.text_scale)?;
+        for (player, map) in self.keys.iter().enumerate() {
+            write!(fmt, "keys {}", player + 1)?;
+            for key in &map.keys() {
+                write!(fmt, " {:?}", key)?;
+            }
+            writeln!(fmt)?;
+        }
+        for (id, buttons) in ACTION_IDS.iter().zip(&self.pad.buttons) {
+            write!(fmt, "pad-buttons {}", id)?;
+            for button in buttons {
+                write!(fmt, " {:?}", button)?;
+            }
+            writeln!(fmt)?;
+        }
+        writeln!(fmt, "pad-turn {}", axis_name(self.pad.turn))?;
+        writeln!(fmt, "pad-thrust {}", axis_name(self.pad.thrust))?;
+        Ok(())
+    }
+}
+
+/// What a key did to the options menu.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum OptionsKey {
+    /// Not a key of the menu, the caller may use it for something else.
+    Ignored,
+    Handled,
+    /// The setting got a new value, to be put into effect.
+    Changed(Setting),
+}
+
+/// The options menu, shown over the level.
+#[derive(Clone, Debug, Default)]
+pub struct OptionsMenu {
+    pub open: bool,
+    pub selected: usize,
+    /// Waiting for the key (or gamepad button) to put to the selected action.
+    pub capturing: bool,
+}
+
+impl OptionsMenu {
+    /// Handles a released key.
+    pub fn key(&mut self, options: &mut Options, key: Key) -> OptionsKey {
+        if !self.open {
+            return OptionsKey::Ignored;
+        }
+        let settings = Setting::all();
+        let setting = settings[self.selected];
+        if self.capturing {
+            match (setting, key) {
+                (_, Key::Back) => self.capturing = false,
+                (Setting::Key { player, action }, key) if bindable(key) => {
+                    options.bind(player, action, key);
+                    self.capturing = false;
+                    return OptionsKey::Changed(setting);
+                }
+                // Nothing else is a key a ship can use, so keep waiting for one
+                _ => (),
+            }
+            return OptionsKey::Handled;
+        }
+        match (setting, key) {
+            (_, Key::Up) => self.selected = (self.selected + settings.len() - 1) % settings.len(),
+            (_, Key::Down) => self.selected = (self.selected + 1) % settings.len(),
+            (Setting::Key { .. }, Key::Return) | (Setting::Button { .. }, Key::Return) => {
+                self.capturing = true;
+            }
+            (Setting::Key { .. }, Key::Left)
+            | (Setting::Key { .. }, Key::Right)
+            | (Setting::Button { .. }, Key::Left)
+            | (Setting::Button { .. }, Key::Right) => (),
+            (_, Key::Left) | (_, Key::Right) | (_, Key::Return) => {
+                options.change(setting, key != Key::Left);
+                return OptionsKey::Changed(setting);
+            }
+            (_, Key::Back) | (_, Key::F2) => self.open = false,
+            // The level doesn't go on under the menu
+            (_, Key::Space) | (_, Key::Pause) => (),
+            _ => return OptionsKey::Ignored,
+        }
+        OptionsKey::Handled
+    }
+
+    /// Handles a released gamepad button.
+    ///
+    /// Only waiting for a button to give to an action takes them.
+    pub fn button(&mut self, options: &mut Options, button: GamepadButton) -> OptionsKey {
+        if !self.open || !self.capturing {
+            return OptionsKey::Ignored;
+        }
+        let setting = Setting::all()[self.selected];
+        match setting {
+            Setting::Button { action } if PAD_BUTTONS.contains(&button) => {
+                options.bind_button(action, button);
+                self.capturing = false;
+                OptionsKey::Changed(setting)
+            }
+            _ => OptionsKey::Handled,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn options_round_trip() {
+        let mut options = Options::default();
+        options.change(Setting::Window, true);
+        options.change(Setting::FrameCap, false);
+        options.change(Setting::Assists, true);
+        options.bind(1, 0, Key::I);
+        options.bind_button(4, GamepadButton::South);
+        options.change(Setting::PadThrust, true);
+        options.change(Setting::TextScale, false);
+        options.unsaved = false;
+        let parsed = Options::parse(&options.to_string()).unwrap();
+        assert_eq!(parsed, options);
+        assert_eq!(parsed.window, WINDOW_SIZES[1]);
+        assert_eq!(parsed.frame_cap, Some(144));
+        assert!(!parsed.assists);
+        assert_eq!(parsed.pad.buttons[4], vec![GamepadButton::South]);
+        // The turning one is skipped
+        assert_eq!(parsed.pad.thrust, Some(GamepadAxis::LeftStickY));
+        assert_eq!(parsed.text_scale, 0.75);
+        // Space is a command
+        let space = "thrust-options 1\nkeys 1 Up Down Left Right Home Space\n";
+        assert!(Options::parse(space).is_err());
+        assert!(Options::parse("thrust-profile 1\n").is_err());
+        let same_axis = "thrust-options 1\npad-turn LeftStickY\npad-thrust LeftStickY\n";
+        assert!(Options::parse(same_axis).is_err());
+        assert!(Options::parse("thrust-options 1\nwindow 800 600\n").is_ok());
+        for window in &["0 600", "800 -600", "NaN 600", "800 inf"] {
+            let broken = format!("thrust-options 1\nwindow {}\n", window);
+            assert!(Options::parse(&broken).is_err(), "{}", window);
+        }
+    }
+
+    #[test]
+    fn bound_keys_turn_into_default_ones() {
+        let mut options = Options::default();
+        assert_eq!(options.translate(Key::Up), Some(Key::Up));
+        options.bind(0, 0, Key::I);
+        assert_eq!(options.translate(Key::I), Some(Key::Up));
+        // The old key doesn't fire the thrusters any more
+        assert_eq!(options.translate(Key::Up), None);
+        assert_eq!(options.translate(Key::Escape), Some(Key::Escape));
+        // Taking a key from another action swaps them
+        options.bind(0, 1, Key::I);
+        assert_eq!(options.keys[0].forward, Key::Down);
+        assert_eq!(options.translate(Key::I), Some(Key::Down));
+        assert_eq!(options.translate(Key::Down), Some(Key::Up));
+    }
+
+    #[test]
+    fn capturing_a_key() {
+        let mut options = Options::default();
+        let mut menu = OptionsMenu {
+            open: true,
+            ..OptionsMenu::default()
+        };
+        menu.selected = Setting::all()
+            .iter()
+            .position(|setting| *setting == Setting::Key { player: 1, action: 4 })
+            .unwrap();
+        assert_eq!(menu.key(&mut options, Key::Return), OptionsKey::Handled);
+        assert!(menu.capturing);
+        // Commands can't steer
+        assert_eq!(menu.key(&mut options, Key::Space), OptionsKey::Handled);
+        assert_eq!(menu.key(&mut options, Key::L), OptionsKey::Handled);
+        assert!(menu.capturing);
+        let changed = menu.key(&mut options, Key::X);
+        assert_eq!(changed, OptionsKey::Changed(Setting::Key { player: 1, action: 4 }));
+        assert_eq!(options.keys[1].homing, Key::X);
+        assert!(options.unsaved);
+        assert_eq!(menu.key(&mut options, Key::F11), OptionsKey::Ignored);
+        assert_eq!(menu.key(&mut options, Key::Back), OptionsKey::Handled);
+        assert!(!menu.open);
+    }
+
+    #[test]
+    fn capturing_a_button() {
+        let mut options = Options::default();
+        let keys = PLAYER_KEYS[1];
+        assert_eq!(options.pad.key(&keys, GamepadButton::South), Some(keys.forward));
+        let mut menu = OptionsMenu {
+            open: true,
+            ..OptionsMenu::default()
+        };
+        assert_eq!(menu.button(&mut options, GamepadButton::East), OptionsKey::Ignored);
+        menu.selected = Setting::all()
+            .iter()
+            .position(|setting| *setting == Setting::Button { action: 5 })
+            .unwrap();
+        menu.key(&mut options, Key::Return);
+        assert_eq!(menu.button(&mut options, GamepadButton::Start), OptionsKey::Handled);
+        let changed = menu.button(&mut options, GamepadButton::South);
+        assert_eq!(changed, OptionsKey::Changed(Setting::Button { action: 5 }));
+        assert_eq!(options.pad.key(&keys, GamepadButton::South), Some(keys.repair));
+        assert_eq!(options.pad.key(&keys, GamepadButton::West), None);
+        assert_eq!(options.pad.key(&keys, GamepadButton::DPadUp), Some(keys.forward));
+    }
+}
@@ -0,0 +1,926 @@
+//! Systems moving the world forward in time.
+
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::ops::Deref;
+use std::time::{Duration, Instant};
+
+use quicksilver::geom::Vector;
+use shred::MultiDispatchController;
+use specs::prelude::*;
+use specs::storage::MaskedStorage;
+use specs::{Storage, SystemData};
+use specs_hierarchy::Hierarchy;
+
+use log::{debug, info, trace};
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
+
+use crate::components::{
+    AtLagrange, Condition, Effects, Hull, Interference, Lagrange, LagrangePoint, Landed, Mass,
+    Orbit, Planet, Position, Rotation, RotationSpeed, Ship, Speed, Star, Strain, Thruster,
+    LINE_EXTENT,
+};
+use crate::input::Keys;
+use crate::plugin::Plugin;
+use crate::render::Viewport;
+use crate::state::{EventLog, GameEvent, GameState, LevelTime, LostReason, Rules};
+
+/// How close to a landing area a ship needs to get, on the normal difficulty.
+pub const LAND_DISTANCE: f32 = 25.0;
+/// Ships slower than this (relative to the surface) touching a planet stay on it, on the normal
+/// difficulty.
+pub const TOUCHDOWN_SPEED: f32 = 3.0;
+/// The part of the maximal temperature over which the firing thrusters get damaged.
+pub const OVERHEAT_DAMAGE: f32 = 0.8;
+/// One G, the acceleration of the main thruster.
+pub const G: f32 = 8.0;
+
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serialize", derive(Deserialize, Serialize))]
+pub struct DifficultyTimeMod(pub f32);
+
+#[derive(Copy, Clone, Default, Debug)]
+pub struct FrameDuration(pub Duration);
+
+/// How long a single frame stepped while paused takes.
+pub const FRAME_STEP: Duration = Duration::from_micros(16_667);
+
+/// The current frame is a single step of a paused game, for debugging.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct FrameStep(pub bool);
+
+#[derive(Debug)]
+pub struct UpdateDurations {
+    pub last_frame: Instant,
+    /// Pretend each frame took this long, instead of measuring the real time.
+    pub fixed: Option<Duration>,
+}
+
+impl<'a> System<'a> for UpdateDurations {
+    type SystemData = (Read<'a, FrameStep>, Write<'a, FrameDuration>);
+
+    fn run(&mut self, (step, mut fd): Self::SystemData) {
+        if let Some(fixed) = self.fixed {
+            fd.0 = fixed;
+            return;
+        }
+        let now = Instant::now();
+        // The time spent paused doesn't count into the next frame
+        fd.0 = if step.0 { FRAME_STEP } else { now - self.last_frame };
+        self.last_frame = now;
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serialize", derive(Deserialize, Serialize))]
+pub struct Gravity {
+    /// Gravity constant tuned to match our unit-less masses and pixel-distances.
+    pub force: f32,
+    /// Disable gravity when closer than this, to prevent shooting away.
+    ///
+    /// Measured in distance *squared*.
+    closeness_limit: f32,
+}
+
+impl Gravity {
+    /// The pull of the second body, per unit of mass of the first one.
+    ///
+    /// This is not yet multiplied by the gravity force.
+    pub fn pull(&self, pos_1: Position, mass_2: Mass, pos_2: Position) -> Vector {
+        let dist_euclid = pos_2 - pos_1;
+        let dist_sq = dist_euclid.0.len2();
+        if dist_sq <= self.closeness_limit {
+            return Vector::ZERO;
+        }
+        let force_size = mass_2.0 / dist_sq;
+        debug_assert!(force_size >= 0.0);
+        // TODO: Cap it somehow so it doesn't „shoot“ away
+        dist_euclid.0.normalize() * force_size
+    }
+
+    /// The gravity adjusted by the rules of the level.
+    pub fn with_rules(self, rules: &Rules) -> Self {
+        Gravity {
+            force: self.force * rules.gravity,
+            closeness_limit: self.closeness_limit * rules.closeness,
+        }
+    }
+}
+
+/// Orders things by where they are, the same way each time.
+///
+/// The entities come in the order of their ids, which depends on what existed before. Floats added
+/// up in another order come out a bit different, and that's enough for a replay to go somewhere
+/// else than the recording did. So whatever adds up over the other bodies sorts them first.
+pub fn by_position(a: &Position, b: &Position) -> Ordering {
+    a.0.x.total_cmp(&b.0.x).then(a.0.y.total_cmp(&b.0.y))
+}
+
+/// The thrusters of the ship, always in the same order.
+///
+/// The hierarchy lists them in the order of their ids, which changes as `by_position` describes.
+pub fn ship_thrusters<'t, D>(
+    hierarchy: &Hierarchy<Thruster>,
+    thrusters: &'t Storage<Thruster, D>,
+    ship: Entity,
+) -> Vec<(Entity, &'t Thruster)>
+where
+    D: Deref<Target = MaskedStorage<Thruster>>,
+{
+    let mut found = hierarchy
+        .children(ship)
+        .iter()
+        .map(|id| (*id, thrusters.get(*id).expect("Missing thruster")))
+        .collect::<Vec<_>>();
+    found.sort_by(|(_, a), (_, b)| {
+        a.key
+            .cmp(&b.key)
+            .then(a.position.x.total_cmp(&b.position.x))
+            .then(a.position.y.total_cmp(&b.position.y))
+            .then(a.direction.total_cmp(&b.direction))
+    });
+    found
+}
+
+#[derive(SystemData)]
+pub struct GravityParams<'a> {
+    frame_duration: Read<'a, FrameDuration>,
+    difficulty_mod: ReadExpect<'a, DifficultyTimeMod>,
+    rules: Read<'a, Rules>,
+    masses: ReadStorage<'a, Mass>,
+    positions: ReadStorage<'a, Position>,
+    speeds: WriteStorage<'a, Speed>,
+    effects: ReadStorage<'a, Effects>,
+}
+
+impl<'a> System<'a> for Gravity {
+    type SystemData = GravityParams<'a>;
+
+    fn run(&mut self, params: GravityParams) {
+        let GravityParams {
+            frame_duration,
+            difficulty_mod,
+            rules,
+            masses,
+            positions,
+            mut speeds,
+            effects,
+        } = params;
+        let gravity = self.with_rules(&rules);
+        let multiplier = gravity.force * frame_duration.0.as_secs_f32() * difficulty_mod.0;
+        let mut bodies = (&masses, &positions).join().collect::<Vec<_>>();
+        bodies.sort_by(|(mass_a, pos_a), (mass_b, pos_b)| {
+            by_position(pos_a, pos_b).then(mass_a.0.total_cmp(&mass_b.0))
+        });
+        (&mut speeds, &masses, &positions, effects.maybe())
+            .par_join()
+            .for_each(|(speed_1, mass_1, pos_1, effects)| {
+                let speed_inc: Vector = bodies
+                    .iter()
+                    .map(|(mass_2, pos_2)| gravity.pull(*pos_1, **mass_2, **pos_2) * mass_1.0)
+                    .fold(Vector::ZERO, |a, b| a + b);
+                speed_1.0 += speed_inc * multiplier * effects.map_or(1.0, Effects::gravity);
+            })
+    }
+}
+
+struct Movement;
+
+impl<'a> System<'a> for Movement {
+    type SystemData = (
+        Read<'a, FrameDuration>,
+        ReadExpect<'a, DifficultyTimeMod>,
+        ReadStorage<'a, Speed>,
+        WriteStorage<'a, Position>,
+    );
+
+    fn run(&mut self, (frame_duration, difficulty, speeds, mut positions): Self::SystemData) {
+        let dur = frame_duration.0.as_secs_f32() * difficulty.0;
+
+        (&speeds, &mut positions)
+            .par_join()
+            .for_each(|(speed, position)| {
+                position.0 += speed.0 * dur;
+            });
+    }
+}
+
+/// What's needed to compute Lagrange points, except for the positions and speeds.
+///
+/// These are passed separately, so the caller can be the one who moves things.
+#[derive(SystemData)]
+pub struct LagrangeData<'a> {
+    pub entities: Entities<'a>,
+    pub lagrange: ReadStorage<'a, Lagrange>,
+    masses: ReadStorage<'a, Mass>,
+}
+
+/// How far ahead to look when computing how fast a Lagrange point moves, in seconds.
+const LAGRANGE_LOOKAHEAD: f32 = 0.01;
+
+impl LagrangeData<'_> {
+    /// Where the given Lagrange point of the secondary is and how fast it moves.
+    ///
+    /// Returns `None` if the secondary is not part of a (complete) pair.
+    pub fn point<P, S>(
+        &self,
+        positions: &Storage<Position, P>,
+        speeds: &Storage<Speed, S>,
+        secondary: Entity,
+        point: LagrangePoint,
+    ) -> Option<(Vector, Vector)>
+    where
+        P: Deref<Target = MaskedStorage<Position>>,
+        S: Deref<Target = MaskedStorage<Speed>>,
+    {
+        let primary = self.lagrange.get(secondary)?.primary;
+        let speed = |ent| speeds.get(ent).map(|s| s.0).unwrap_or(Vector::ZERO);
+        // Where the body is after the given time
+        let body = |ent, time: f32| -> Option<(Vector, f32)> {
+            Some((positions.get(ent)?.0 + speed(ent) * time, self.masses.get(ent)?.0))
+        };
+        let rel_speed = speed(secondary) - speed(primary);
+        let at = |time| {
+            Some(point.position(body(primary, time)?, body(secondary, time)?, rel_speed))
+        };
+        let pos = at(0.0)?;
+        let ahead = at(LAGRANGE_LOOKAHEAD)?;
+        Some((pos, (ahead - pos) / LAGRANGE_LOOKAHEAD))
+    }
+}
+
+/// Moves things sitting in Lagrange points together with the points.
+///
+/// The ones with a `Speed` get the speed of the point too, so landing in a landing area there
+/// works like anywhere else.
+struct FollowLagrange;
+
+impl<'a> System<'a> for FollowLagrange {
+    type SystemData = (
+        LagrangeData<'a>,
+        ReadStorage<'a, AtLagrange>,
+        WriteStorage<'a, Position>,
+        WriteStorage<'a, Speed>,
+    );
+
+    fn run(&mut self, (lagrange, at_lagrange, mut positions, mut speeds): Self::SystemData) {
+        // Can't write positions while computing from them :-(
+        let updates = (&lagrange.entities, &at_lagrange)
+            .join()
+            .filter_map(|(ent, at)| {
+                Some((ent, lagrange.point(&positions, &speeds, at.secondary, at.point)?))
+            })
+            .collect::<Vec<_>>();
+        for (ent, (pos, speed)) in updates {
+            if let Some(position) = positions.get_mut(ent) {
+                position.0 = pos;
+            }
+            if let Some(own_speed) = speeds.get_mut(ent) {
+                own_speed.0 = speed;
+            }
+        }
+    }
+}
+
+/// Worsens the condition of a thruster, if it still has anywhere to go.
+fn damage_thruster(thruster: &mut Thruster, time: Duration, events: &mut EventLog) {
+    let condition = thruster.condition.worse();
+    if condition == thruster.condition {
+        return;
+    }
+    thruster.condition = condition;
+    info!("Thruster {:?} of ship {:?} is {:?}", thruster.key, thruster.ship, condition);
+    let event = GameEvent::ThrusterDamaged {
+        ship: thruster.ship,
+        key: thruster.key,
+        condition,
+    };
+    events.record(time, event);
+}
+
+/// Multiplies the push of the ship's thrusters.
+fn response(interference: Option<&Interference>, effects: Option<&Effects>) -> f32 {
+    interference.map_or(1.0, |i| i.response) * effects.map_or(1.0, Effects::push)
+}
+
+#[derive(Default)]
+struct FireThrusters {
+    /// The thrusters firing in the last frame, to log when they start and stop.
+    active: HashSet<Entity>,
+}
+
+#[derive(SystemData)]
+struct FireThrustersData<'a> {
+    frame_duration: Read<'a, FrameDuration>,
+    time: Read<'a, LevelTime>,
+    events: Write<'a, EventLog>,
+    entities: Entities<'a>,
+    ships: WriteStorage<'a, Ship>,
+    thrusters: ReadStorage<'a, Thruster>,
+    rotations: ReadStorage<'a, Rotation>,
+    thruster_hierarchy: ReadExpect<'a, Hierarchy<Thruster>>,
+    speeds: WriteStorage<'a, Speed>,
+    rotation_speeds: WriteStorage<'a, RotationSpeed>,
+    interferences: ReadStorage<'a, Interference>,
+    effects: ReadStorage<'a, Effects>,
+    keys: Read<'a, Keys>,
+}
+
+impl<'a> System<'a> for FireThrusters {
+    type SystemData = FireThrustersData<'a>;
+
+    fn run(&mut self, mut d: Self::SystemData) {
+        let dur = d.frame_duration.0.as_secs_f32();
+        let parts = (
+            &mut d.ships,
+            &d.rotations,
+            &mut d.speeds,
+            &mut d.rotation_speeds,
+            &d.entities,
+        );
+        for (ship, rotated, trans, rot, ent) in parts.join() {
+            trace!("Fire thrusters of ship {:?} {:?}", trans, rot);
+            let interference = d.interferences.get(ent);
+            let response = response(interference, d.effects.get(ent));
+            rot.0 += interference.map_or(0.0, |i| i.drift) * dur;
+            let thrusters = ship_thrusters(&d.thruster_hierarchy, &d.thrusters, ent);
+            for (thruster_ent, thruster) in thrusters {
+                if ship.fires(thruster, interference, &d.keys) {
+                    trace!("Thruster {:?} active", thruster.key);
+                    ship.fuel = (ship.fuel - dur).max(0.0);
+                    let push = thruster.thrust(rotated.0) * response;
+                    // For unknown reasons, it seems to work in the opposite direction
+                    trans.0 -= push * dur;
+                    rot.0 -= thruster.rotation * thruster.condition.push() * response * dur;
+                    if self.active.insert(thruster_ent) {
+                        let event = GameEvent::ThrustStart {
+                            ship: ent,
+                            key: thruster.key,
+                            push: -push,
+                        };
+                        d.events.record(d.time.0, event);
+                    }
+                } else if self.active.remove(&thruster_ent) {
+                    let event = GameEvent::ThrustStop {
+                        ship: ent,
+                        key: thruster.key,
+                    };
+                    d.events.record(d.time.0, event);
+                }
+            }
+        }
+    }
+}
+
+struct Rotate;
+
+impl<'a> System<'a> for Rotate {
+    type SystemData = (
+        Read<'a, FrameDuration>,
+        ReadExpect<'a, DifficultyTimeMod>,
+        ReadStorage<'a, RotationSpeed>,
+        WriteStorage<'a, Rotation>,
+    );
+
+    fn run(&mut self, (frame_duration, difficulty, speeds, mut rotations): Self::SystemData) {
+        let dur = frame_duration.0.as_secs_f32() * difficulty.0;
+
+        (&speeds, &mut rotations)
+            .par_join()
+            .for_each(|(speed, rotation)| {
+                // Seems like quicksilver works in degrees. Someone is sane at least.
+                rotation.0 = (rotation.0 + speed.0 * dur).rem_euclid(360.0);
+            });
+    }
+}
+
+pub struct PhysicsSystems;
+
+impl<'a> MultiDispatchController<'a> for PhysicsSystems {
+    type SystemData = ReadExpect<'a, GameState>;
+
+    fn plan(&mut self, game_state: Self::SystemData) -> usize {
+        (*game_state == GameState::Running) as usize
+    }
+}
+
+struct Homing;
+
+impl<'a> System<'a> for Homing {
+    type SystemData = (
+        ReadStorage<'a, Ship>,
+        ReadStorage<'a, Position>,
+        ReadExpect<'a, Keys>,
+        WriteExpect<'a, Viewport>,
+    );
+
+    fn run(&mut self, (ships, positions, keys, mut viewport): Self::SystemData) {
+        for (ship, position) in (&ships, &positions).join() {
+            if keys.contains(&ship.keys.homing) {
+                viewport.rect.pos = position.0 - viewport.rect.size / 2.0;
+                viewport.update();
+            }
+        }
+    }
+}
+
+#[derive(SystemData)]
+struct OrbitsData<'a> {
+    entities: Entities<'a>,
+    ships: ReadStorage<'a, Ship>,
+    masses: ReadStorage<'a, Mass>,
+    positions: ReadStorage<'a, Position>,
+    speeds: ReadStorage<'a, Speed>,
+    orbits: WriteStorage<'a, Orbit>,
+    effects: ReadStorage<'a, Effects>,
+    rules: Read<'a, Rules>,
+}
+
+struct Orbits {
+    /// Needs to be the same as the one in `Gravity`.
+    force: f32,
+}
+
+impl<'a> System<'a> for Orbits {
+    type SystemData = OrbitsData<'a>;
+
+    fn run(&mut self, mut d: Self::SystemData) {
+        let force = self.force * d.rules.gravity;
+        for (_, ship_mass, ship_pos, ship_speed, ent) in
+            (&d.ships, &d.masses, &d.positions, &d.speeds, &d.entities).join()
+        {
+            // The one pulling the most, other ships excluded.
+            let dominant = (&d.masses, &d.positions, &d.entities, !&d.ships)
+                .join()
+                .map(|(mass, pos, body, _)| {
+                    let pull = mass.0 / pos.0.distance(ship_pos.0).powi(2);
+                    (pull, mass, pos, body)
+                })
+                .filter(|(pull, ..)| pull.is_finite())
+                .max_by(|a, b| a.0.partial_cmp(&b.0).expect("NaN pull"));
+
+            let orbit = dominant.and_then(|(_, mass, pos, body)| {
+                let body_speed = d.speeds.get(body).map(|s| s.0).unwrap_or(Vector::ZERO);
+                let dampening = d.effects.get(ent).map_or(1.0, Effects::gravity);
+                let mu = force * ship_mass.0 * mass.0 * dampening;
+                Orbit::new(body, mu, ship_pos.0 - pos.0, ship_speed.0 - body_speed)
+            });
+
+            match orbit {
+                Some(orbit) => {
+                    d.orbits.insert(ent, orbit).expect("Orbiting a dead ship");
+                }
+                None => {
+                    d.orbits.remove(ent);
+                }
+            }
+        }
+    }
+}
+
+#[derive(SystemData)]
+struct TemperatureData<'a> {
+    state: WriteExpect<'a, GameState>,
+    duration: ReadExpect<'a, FrameDuration>,
+    entities: Entities<'a>,
+    ships: WriteStorage<'a, Ship>,
+    stars: ReadStorage<'a, Star>,
+    keys: ReadExpect<'a, Keys>,
+    thrusters: WriteStorage<'a, Thruster>,
+    thruster_hierarchy: ReadExpect<'a, Hierarchy<Thruster>>,
+    interferences: ReadStorage<'a, Interference>,
+    positions: ReadStorage<'a, Position>,
+    time: Read<'a, LevelTime>,
+    events: Write<'a, EventLog>,
+}
+
+/// Heats the ships up and cools them down.
+///
+/// The thrusters firing as a ship gets over `OVERHEAT_DAMAGE` of its maximal temperature get
+/// damaged, a ship getting over the maximum is lost.
+struct Temperature {
+    min_temp: f32,
+    heat_mult: f32,
+}
+
+impl<'a> System<'a> for Temperature {
+    type SystemData = TemperatureData<'a>;
+
+    fn run(&mut self, mut d: Self::SystemData) {
+        let positions = &d.positions;
+        let stars = &d.stars;
+        let thruster_hierarchy = &d.thruster_hierarchy;
+        let thrusters = &d.thrusters;
+        let keys = &d.keys;
+        let interferences = &d.interferences;
+        let duration = d.duration.0.as_secs_f32();
+        let heat_mult = self.heat_mult;
+        let mut stars = (stars, positions).join().map(|(_, p)| p).collect::<Vec<_>>();
+        stars.sort_by(|a, b| by_position(a, b));
+        let heated = (&mut d.ships, &d.positions, &d.entities)
+            .par_join()
+            .map(|(ship, sp, ent)| {
+                let heating_stars = stars
+                    .iter()
+                    .map(|p| {
+                        let dist = sp.0.distance(p.0);
+                        heat_mult / (dist * dist)
+                    })
+                    .sum::<f32>();
+
+
+                let firing = ship_thrusters(thruster_hierarchy, thrusters, ent)
+                    .into_iter()
+                    .filter(|(_, thruster)| ship.fires(thruster, interferences.get(ent), keys))
+                    .collect::<Vec<_>>();
+                let heating_thrusters = firing
+                    .iter()
+                    .map(|(_, thruster)| thruster.heating)
+                    .sum::<f32>();
+                let firing = firing.into_iter().map(|(id, _)| id).collect::<Vec<_>>();
+
+                let temp_diff = ship.temperature - self.min_temp;
+                let dec = ship.temp_dec * temp_diff;
+
+                let damage_temp = ship.max_temp * OVERHEAT_DAMAGE;
+                let was_hot = ship.temperature > damage_temp;
+                ship.temperature += duration * (heating_stars + heating_thrusters - dec);
+
+                if ship.temperature < self.min_temp {
+                    ship.temperature = self.min_temp;
+                }
+
+                debug!("Ship: {:?}", ship);
+
+                let damaged = if !was_hot && ship.temperature > damage_temp {
+                    firing
+                } else {
+                    Vec::new()
+                };
+                // Overheated?
+                (ship.temperature > ship.max_temp, damaged)
+            })
+            .collect::<Vec<_>>();
+        let time = d.time.0;
+        for thruster in heated.iter().flat_map(|(_, damaged)| damaged) {
+            let thruster = d.thrusters.get_mut(*thruster).expect("Missing thruster");
+            damage_thruster(thruster, time, &mut d.events);
+        }
+        if heated.iter().any(|(lost, _)| *lost) {
+            *d.state = GameState::Lost(LostReason::Overheated);
+        }
+    }
+}
+
+#[derive(SystemData)]
+struct SurfaceData<'a> {
+    entities: Entities<'a>,
+    ships: ReadStorage<'a, Ship>,
+    planets: ReadStorage<'a, Planet>,
+    landed: WriteStorage<'a, Landed>,
+    keys: ReadExpect<'a, Keys>,
+    thrusters: WriteStorage<'a, Thruster>,
+    thruster_hierarchy: ReadExpect<'a, Hierarchy<Thruster>>,
+    interferences: ReadStorage<'a, Interference>,
+    positions: WriteStorage<'a, Position>,
+    speeds: WriteStorage<'a, Speed>,
+    rotations: WriteStorage<'a, Rotation>,
+    rotation_speeds: WriteStorage<'a, RotationSpeed>,
+    time: Read<'a, LevelTime>,
+    events: Write<'a, EventLog>,
+    rules: Read<'a, Rules>,
+}
+
+/// Lands ships on planets, carries the landed ones with the surface and lets them take off.
+///
+/// This needs to run after everything else moved things around, it overrides whatever the other
+/// physics did to landed ships.
+///
+/// A ship hitting a planet too fast damages the thruster closest to the planet.
+#[derive(Default)]
+struct Surface {
+    /// The ships inside a planet in the last frame, so they get damaged only as they hit it.
+    crashed: HashSet<Entity>,
+}
+
+impl<'a> System<'a> for Surface {
+    type SystemData = SurfaceData<'a>;
+
+    fn run(&mut self, mut d: Self::SystemData) {
+        let mut touchdowns = Vec::new();
+        let mut takeoffs = Vec::new();
+        let mut crashed = HashSet::new();
+        let mut impacts = Vec::new();
+
+        let touchdown_speed = d.rules.touchdown_speed;
+        for (ship, ent) in (&d.ships, &d.entities).join() {
+            let interference = d.interferences.get(ent);
+            let thrusting = d.thruster_hierarchy
+                .children(ent)
+                .iter()
+                .map(|id| d.thrusters.get(*id).expect("Missing thruster"))
+                .any(|t| ship.fires(t, interference, &d.keys));
+
+            if let Some(landed) = d.landed.get(ent) {
+                if thrusting {
+                    // Keep the speed from the last frame, as if jumping off the surface.
+                    takeoffs.push(ent);
+                    continue;
+                }
+                let planet_pos = d.positions.get(landed.planet).map(|p| p.0);
+                let planet_pos = match planet_pos {
+                    Some(pos) => pos,
+                    // The planet is gone (restarting the level?)
+                    None => {
+                        takeoffs.push(ent);
+                        continue;
+                    }
+                };
+                let planet_speed = d.speeds
+                    .get(landed.planet)
+                    .map(|s| s.0)
+                    .unwrap_or(Vector::ZERO);
+                let planet_rot = d.rotations.get(landed.planet).map(|r| r.0).unwrap_or_default();
+                let spin = d.rotation_speeds
+                    .get(landed.planet)
+                    .map(|r| r.0)
+                    .unwrap_or_default();
+
+                let angle = planet_rot + landed.angle;
+                let surface_speed = Vector::from_angle(angle + 90.0)
+                    * spin.to_radians()
+                    * landed.distance;
+                let landed = *landed;
+
+                if let Some(pos) = d.positions.get_mut(ent) {
+                    pos.0 = planet_pos + Vector::from_angle(angle) * landed.distance;
+                }
+                if let Some(speed) = d.speeds.get_mut(ent) {
+                    speed.0 = planet_speed + surface_speed;
+                }
+                if let Some(rotation) = d.rotations.get_mut(ent) {
+                    rotation.0 = (planet_rot + landed.rotation).rem_euclid(360.0);
+                }
+                if let Some(rotation_speed) = d.rotation_speeds.get_mut(ent) {
+                    rotation_speed.0 = spin;
+                }
+            } else if !thrusting {
+                let ship_pos = match d.positions.get(ent) {
+                    Some(pos) => pos.0,
+                    None => continue,
+                };
+                let ship_speed = d.speeds.get(ent).map(|s| s.0).unwrap_or(Vector::ZERO);
+                let ship_rot = d.rotations.get(ent).map(|r| r.0).unwrap_or_default();
+                let touching = (&d.planets, &d.positions, &d.entities)
+                    .join()
+                    .find(|(planet, pos, _)| ship_pos.distance(pos.0) <= planet.radius);
+                if let Some((_, planet_pos, planet)) = touching {
+                    let planet_speed = d.speeds.get(planet).map(|s| s.0).unwrap_or(Vector::ZERO);
+                    let speed = (ship_speed - planet_speed).len();
+                    if speed > touchdown_speed {
+                        let event = GameEvent::Collision { ship: ent, planet, speed };
+                        d.events.record(d.time.0, event);
+                        crashed.insert(ent);
+                        if !self.crashed.contains(&ent) {
+                            impacts.push((ent, planet_pos.0 - ship_pos, ship_rot));
+                        }
+                        continue;
+                    }
+                    d.events.record(d.time.0, GameEvent::Touchdown { ship: ent, planet, speed });
+                    let planet_rot = d.rotations.get(planet).map(|r| r.0).unwrap_or_default();
+                    let offset = ship_pos - planet_pos.0;
+                    let landed = Landed {
+                        planet,
+                        angle: offset.angle() - planet_rot,
+                        distance: offset.len(),
+                        rotation: ship_rot - planet_rot,
+                    };
+                    touchdowns.push((ent, landed));
+                }
+            }
+        }
+
+        for ent in takeoffs {
+            debug!("Ship {:?} took off", ent);
+            if let Some(landed) = d.landed.remove(ent) {
+                let event = GameEvent::Takeoff { ship: ent, planet: landed.planet };
+                d.events.record(d.time.0, event);
+            }
+        }
+        for (ent, landed) in touchdowns {
+            debug!("Ship {:?} landed: {:?}", ent, landed);
+            d.landed.insert(ent, landed).expect("Landing a dead ship");
+        }
+        for (ent, towards, rotation) in impacts {
+            let closest = ship_thrusters(&d.thruster_hierarchy, &d.thrusters, ent)
+                .into_iter()
+                .map(|(id, thruster)| (id, thruster.nozzle(rotation).distance(towards)))
+                .min_by(|(_, a), (_, b)| a.total_cmp(b))
+                .map(|(id, _)| id);
+            if let Some(thruster) = closest {
+                let thruster = d.thrusters.get_mut(thruster).expect("Missing thruster");
+                damage_thruster(thruster, d.time.0, &mut d.events);
+            }
+        }
+        self.crashed = crashed;
+    }
+}
+
+/// Gravity of the game world.
+pub const GRAVITY: Gravity = Gravity {
+    force: 1.0,
+    closeness_limit: 100.0,
+};
+
+/// Adds the systems that move things by the forces acting on them.
+///
+/// The game runs them only while it is not paused, after the forces from the plugins.
+pub fn motion<'a, 'b>(builder: DispatcherBuilder<'a, 'b>) -> DispatcherBuilder<'a, 'b> {
+    let temperature = Temperature {
+        heat_mult: 2_500_000.0,
+        min_temp: -200.0,
+    };
+    builder
+        .with(Movement, "movement", &[])
+        .with(Rotate, "rotate", &[])
+        .with(temperature, "temperature", &["movement"])
+        .with(Surface::default(), "surface", &["movement", "rotate"])
+        .with(FollowLagrange, "follow-lagrange", &["movement"])
+}
+
+#[derive(SystemData)]
+struct GForcesData<'a> {
+    frame_duration: Read<'a, FrameDuration>,
+    difficulty_mod: ReadExpect<'a, DifficultyTimeMod>,
+    rules: Read<'a, Rules>,
+    keys: Read<'a, Keys>,
+    state: WriteExpect<'a, GameState>,
+    time: Read<'a, LevelTime>,
+    events: Write<'a, EventLog>,
+    entities: Entities<'a>,
+    ships: ReadStorage<'a, Ship>,
+    masses: ReadStorage<'a, Mass>,
+    positions: ReadStorage<'a, Position>,
+    rotations: ReadStorage<'a, Rotation>,
+    hulls: ReadStorage<'a, Hull>,
+    thrusters: WriteStorage<'a, Thruster>,
+    thruster_hierarchy: ReadExpect<'a, Hierarchy<Thruster>>,
+    interferences: ReadStorage<'a, Interference>,
+    effects: ReadStorage<'a, Effects>,
+    strains: WriteStorage<'a, Strain>,
+}
+
+/// Measures the G-forces on the ships and breaks the ones over the limit.
+///
+/// A ship in free fall doesn't feel the gravity, only the push of its thrusters and the tidal
+/// forces stretching it close to heavy bodies. Being over the limit for half the tolerance of the
+/// rules damages the strongest working thruster, for the whole tolerance breaks the ship apart.
+struct GForces {
+    /// Needs to be the same as the one doing the gravity.
+    gravity: Gravity,
+}
+
+impl<'a> System<'a> for GForces {
+    type SystemData = GForcesData<'a>;
+
+    fn run(&mut self, d: Self::SystemData) {
+        let GForcesData {
+            frame_duration,
+            difficulty_mod,
+            rules,
+            keys,
+            mut state,
+            time,
+            mut events,
+            entities,
+            ships,
+            masses,
+            positions,
+            rotations,
+            hulls,
+            mut thrusters,
+            thruster_hierarchy,
+            interferences,
+            effects,
+            mut strains,
+        } = d;
+        let gravity = self.gravity.with_rules(&rules);
+        let multiplier = gravity.force * difficulty_mod.0;
+        let mut damaged = Vec::new();
+        let mut lost = false;
+        let mut bodies = (&masses, &positions, &entities).join().collect::<Vec<_>>();
+        bodies.sort_by(|(_, a, _), (_, b, _)| by_position(a, b));
+        let parts = (&entities, &ships, &positions, rotations.maybe());
+        for (ent, ship, pos, rotation) in parts.join() {
+            let interference = interferences.get(ent);
+            let ship_effects = effects.get(ent);
+            let rotation = rotation.map_or(0.0, |r| r.0);
+            let ship_thrusters = ship_thrusters(&thruster_hierarchy, &thrusters, ent);
+            let thrust = ship_thrusters
+                .iter()
+                .filter(|(_, thruster)| ship.fires(thruster, interference, &keys))
+                .map(|(_, thruster)| thruster.thrust(rotation))
+                .sum::<Vector>()
+                * response(interference, ship_effects);
+            // The pull changes with the distance, so the ends of the ship are pulled differently
+            let extent = hulls.get(ent).map_or(LINE_EXTENT, Hull::extent);
+            let tidal = bodies
+                .iter()
+                .filter(|(_, _, body)| *body != ent)
+                .map(|(body_mass, body_pos, _)| (body_mass, body_pos, pos.0.distance(body_pos.0)))
+                .filter(|(_, _, distance)| *distance > 0.0)
+                .map(|(body_mass, body_pos, distance)| {
+                    let pull = gravity.pull(*pos, **body_mass, **body_pos).len();
+                    2.0 * pull * extent / distance
+                })
+                .sum::<f32>()
+                * multiplier
+                * ship_effects.map_or(1.0, Effects::gravity);
+            let g = (thrust.len() + tidal) / G;
+
+            let strain = strains
+                .entry(ent)
+                .expect("Straining a dead ship")
+                .or_insert_with(Strain::default);
+            strain.g = g;
+            if g <= rules.g_limit * ship.strength {
+                strain.over = Duration::default();
+                continue;
+            }
+            let before = strain.over;
+            strain.over += frame_duration.0;
+            let half = rules.g_tolerance / 2;
+            if before < half && strain.over >= half {
+                let strongest = ship_thrusters
+                    .iter()
+                    .filter(|(_, thruster)| thruster.condition != Condition::Dead)
+                    .max_by(|(_, a), (_, b)| a.push.total_cmp(&b.push));
+                damaged.extend(strongest.map(|(id, _)| *id));
+            }
+            if strain.over >= rules.g_tolerance {
+                info!("Ship {:?} was torn apart at {} G", ent, g);
+                lost = true;
+            }
+        }
+        for thruster in damaged {
+            let thruster = thrusters.get_mut(thruster).expect("Missing thruster");
+            damage_thruster(thruster, time.0, &mut events);
+        }
+        if lost {
+            *state = GameState::Lost(LostReason::TornApart);
+        }
+    }
+}
+
+/// Everything pulling everything else, and the orbits that come out of it.
+#[derive(Copy, Clone, Debug)]
+pub struct GravityPlugin {
+    pub gravity: Gravity,
+}
+
+impl Default for GravityPlugin {
+    fn default() -> Self {
+        GravityPlugin { gravity: GRAVITY }
+    }
+}
+
+impl<'a, 'b> Plugin<'a, 'b> for GravityPlugin {
+    /// The gravity is a resource too, for whoever wants to know it (eg. to draw the field).
+    fn setup(&mut self, world: &mut World) {
+        world.insert(self.gravity);
+    }
+
+    fn physics(&mut self, builder: DispatcherBuilder<'a, 'b>) -> DispatcherBuilder<'a, 'b> {
+        let g_forces = GForces {
+            gravity: self.gravity,
+        };
+        builder
+            .with(self.gravity, "gravity", &[])
+            .with(g_forces, "g-forces", &[])
+    }
+
+    fn systems(&mut self, builder: DispatcherBuilder<'a, 'b>) -> DispatcherBuilder<'a, 'b> {
+        let orbits = Orbits {
+            force: self.gravity.force,
+        };
+        builder.with(orbits, "orbits", &[])
+    }
+}
+
+/// The ships firing their thrusters and turning towards the target.
+///
+/// Needs the `ZonePlugin` before it, the thrusters wait for the anomalies disturbing them.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ThrusterPlugin;
+
+impl<'a, 'b> Plugin<'a, 'b> for ThrusterPlugin {
+    fn physics(&mut self, builder: DispatcherBuilder<'a, 'b>) -> DispatcherBuilder<'a, 'b> {
+        builder.with(FireThrusters::default(), "fire-thrusters", &["interfere"])
+    }
+
+    fn systems(&mut self, builder: DispatcherBuilder<'a, 'b>) -> DispatcherBuilder<'a, 'b> {
+        builder.with(Homing, "homing", &[])
+    }
+}
@@ -0,0 +1,25 @@
+//! Composing the game from separate pieces.
+
+use specs::prelude::*;
+
+/// A piece of the game that can be plugged in at startup.
+///
+/// The game calls the hooks of all the plugins in the order they were added, so a plugin can name
+/// systems of the plugins before it as dependencies.
+pub trait Plugin<'a, 'b> {
+    /// Registers components and inserts resources the systems don't set up by themselves.
+    fn setup(&mut self, _world: &mut World) {}
+
+    /// Adds systems into the physics.
+    ///
+    /// These run only while the game is running and before anything moves, so they are the place
+    /// for the forces acting on the bodies.
+    fn physics(&mut self, builder: DispatcherBuilder<'a, 'b>) -> DispatcherBuilder<'a, 'b> {
+        builder
+    }
+
+    /// Adds systems that run every frame, after the physics.
+    fn systems(&mut self, builder: DispatcherBuilder<'a, 'b>) -> DispatcherBuilder<'a, 'b> {
+        builder
+    }
+}
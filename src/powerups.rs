@@ -0,0 +1,162 @@
+//! Power-ups, boosting the ships that pick them up for a while.
+
+use std::time::Duration;
+
+use specs::prelude::*;
+
+use log::info;
+
+use crate::components::{Effects, Position, PowerUp, Ship};
+use crate::physics::FrameDuration;
+use crate::plugin::Plugin;
+
+/// How close a ship needs to fly to a power-up to pick it up.
+pub const PICKUP_DISTANCE: f32 = 15.0;
+
+#[derive(SystemData)]
+struct CollectPowerUpsData<'a> {
+    entities: Entities<'a>,
+    ships: ReadStorage<'a, Ship>,
+    power_ups: ReadStorage<'a, PowerUp>,
+    positions: ReadStorage<'a, Position>,
+    effects: WriteStorage<'a, Effects>,
+}
+
+/// Gives the power-ups to the ships flying through them.
+struct CollectPowerUps;
+
+impl<'a> System<'a> for CollectPowerUps {
+    type SystemData = CollectPowerUpsData<'a>;
+
+    fn run(&mut self, mut d: Self::SystemData) {
+        for (power_up, power_up_pos, power_up_ent) in
+            (&d.power_ups, &d.positions, &d.entities).join()
+        {
+            let collector = (&d.ships, &d.positions, &d.entities)
+                .join()
+                .find(|(_, pos, _)| pos.0.distance(power_up_pos.0) <= PICKUP_DISTANCE)
+                .map(|(_, _, ship)| ship);
+            if let Some(ship) = collector {
+                info!("Ship {:?} picked up {}", ship, power_up.boost);
+                let duration = Duration::from_secs_f32(power_up.duration);
+                d.effects
+                    .entry(ship)
+                    .expect("Boosting a dead ship")
+                    .or_insert_with(Effects::default)
+                    .add(power_up.boost, duration);
+                d.entities
+                    .delete(power_up_ent)
+                    .expect("Picking up a dead power-up");
+            }
+        }
+    }
+}
+
+/// Counts down the boosts and removes them when they run out.
+struct ExpireEffects;
+
+impl<'a> System<'a> for ExpireEffects {
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, FrameDuration>,
+        WriteStorage<'a, Effects>,
+    );
+
+    fn run(&mut self, (entities, frame_duration, mut effects): Self::SystemData) {
+        let mut expired = Vec::new();
+        for (ent, effects) in (&entities, &mut effects).join() {
+            for (_, left) in &mut effects.0 {
+                *left = left.saturating_sub(frame_duration.0);
+            }
+            effects.0.retain(|(boost, left)| {
+                let active = *left > Duration::default();
+                if !active {
+                    info!("{} of ship {:?} ran out", boost, ent);
+                }
+                active
+            });
+            if effects.0.is_empty() {
+                expired.push(ent);
+            }
+        }
+        for ent in expired {
+            effects.remove(ent);
+        }
+    }
+}
+
+/// The power-ups, picked up by the ships and boosting them for a while.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct PowerUpPlugin;
+
+impl<'a, 'b> Plugin<'a, 'b> for PowerUpPlugin {
+    fn physics(&mut self, builder: DispatcherBuilder<'a, 'b>) -> DispatcherBuilder<'a, 'b> {
+        builder
+            .with(CollectPowerUps, "collect-power-ups", &[])
+            .with(ExpireEffects, "expire-effects", &["collect-power-ups"])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use quicksilver::geom::Vector;
+
+    use super::*;
+    use crate::components::Boost;
+    use crate::input::{Keys, PLAYER_KEYS};
+    use crate::test_support::TestWorld;
+
+    fn power_up(world: &mut TestWorld, boost: Boost, position: Vector) -> Entity {
+        world
+            .world
+            .create_entity()
+            .with(PowerUp {
+                boost,
+                duration: 1.0,
+            })
+            .with(Position(position))
+            .build()
+    }
+
+    #[test]
+    fn boost_runs_out() {
+        let mut world = TestWorld::new();
+        let ship = world.ship(Vector::ZERO);
+        let far = power_up(&mut world, Boost::Booster, Vector::new(100, 0));
+        let near = power_up(&mut world, Boost::GravityDampener, Vector::new(10, 0));
+        world.step(1);
+        assert!(world.world.is_alive(far));
+        assert!(!world.world.is_alive(near));
+        assert!(world
+            .world
+            .read_storage::<Effects>()
+            .get(ship)
+            .unwrap()
+            .has(Boost::GravityDampener));
+        world.step(100);
+        assert!(!world.world.read_storage::<Effects>().contains(ship));
+    }
+
+    #[test]
+    fn booster_doubles_push() {
+        let mut world = TestWorld::new();
+        let ship = world.ship(Vector::ZERO);
+        world
+            .world
+            .fetch_mut::<Keys>()
+            .insert(PLAYER_KEYS[0].forward);
+        world.step(1);
+        let normal = world.speed(ship);
+
+        let mut world = TestWorld::new();
+        let ship = world.ship(Vector::ZERO);
+        power_up(&mut world, Boost::Booster, Vector::ZERO);
+        world.step(1);
+        world
+            .world
+            .fetch_mut::<Keys>()
+            .insert(PLAYER_KEYS[0].forward);
+        world.step(1);
+        assert!((world.speed(ship) - normal * 2.0).len() < 0.001);
+    }
+}
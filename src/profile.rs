@@ -0,0 +1,460 @@
+//! Player profiles, what each player achieved and how they like to play.
+//!
+//! Each profile lives in its own text file in the `profiles` directory:
+//!
+//! ```text
+//! thrust-profile 1
+//! name Anna
+//! players Single
+//! difficulty Normal
+//! flights 12
+//! wins 3
+//! losses 9
+//! flight-time 345.2
+//! best-time default 42.13
+//! best default 2731
+//! unlocked Dart
+//! ```
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use specs::prelude::*;
+
+#[cfg(not(target_arch = "wasm32"))]
+use log::error;
+use log::info;
+
+use crate::input::{Attract, Replay, ReplayMode};
+use crate::loadout::Loadout;
+use crate::progress::Progress;
+use crate::state::{Assists, Difficulty, GameState, LevelTime, Players, RunStats, LEVEL_ID};
+
+const PROFILE_HEADER: &str = "thrust-profile 1";
+
+/// Where the profiles are kept.
+#[cfg(not(target_arch = "wasm32"))]
+pub const PROFILE_DIR: &str = "profiles";
+
+/// Remembers which of the profiles was used last, inside the `PROFILE_DIR`.
+#[cfg(not(target_arch = "wasm32"))]
+const LAST_PROFILE: &str = "last";
+
+/// How the runs of a player went, all together.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Statistics {
+    /// Levels finished, either way.
+    pub flights: u32,
+    pub wins: u32,
+    pub losses: u32,
+    /// Time spent in the finished levels, pauses excluded.
+    pub flight_time: Duration,
+}
+
+/// A player, with everything kept between the runs of the game.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    pub players: Players,
+    pub difficulty: Difficulty,
+    pub progress: Progress,
+    /// The fastest win of each level, by the level id.
+    pub best_times: BTreeMap<String, Duration>,
+    pub stats: Statistics,
+    /// Changed since it was last stored.
+    pub unsaved: bool,
+    /// The file the profile is stored in, once it has one.
+    pub file: Option<PathBuf>,
+}
+
+impl Profile {
+    pub fn new(name: &str) -> Self {
+        Profile {
+            name: name.to_owned(),
+            players: Players::default(),
+            difficulty: Difficulty::default(),
+            progress: Progress::default(),
+            best_times: BTreeMap::new(),
+            stats: Statistics::default(),
+            unsaved: true,
+            file: None,
+        }
+    }
+
+    /// Notes a finished level.
+    ///
+    /// Returns the ships a win unlocked.
+    pub fn record(
+        &mut self,
+        level: &str,
+        state: GameState,
+        time: Duration,
+        score: u32,
+    ) -> Vec<&'static str> {
+        self.unsaved = true;
+        self.stats.flights += 1;
+        self.stats.flight_time += time;
+        if state != GameState::Won {
+            self.stats.losses += 1;
+            return Vec::new();
+        }
+        self.stats.wins += 1;
+        let best = self.best_times.entry(level.to_owned()).or_insert(time);
+        *best = (*best).min(time);
+        self.progress.record(level, score)
+    }
+
+    pub fn parse(content: &str) -> Result<Self, Box<dyn Error>> {
+        let mut lines = content.lines().filter(|line| !line.trim().is_empty());
+        if lines.next() != Some(PROFILE_HEADER) {
+            return Err("Not a profile".into());
+        }
+        let mut profile = Profile::new("");
+        profile.unsaved = false;
+        for line in lines {
+            let (key, value) = line.split_at(line.find(' ').ok_or("Missing value")?);
+            let value = value.trim();
+            let secs = |value: &str| -> Result<Duration, Box<dyn Error>> {
+                Ok(Duration::try_from_secs_f32(value.parse()?)?)
+            };
+            match key {
+                "name" => profile.name = value.to_owned(),
+                "players" => profile.players = Players::parse(value).ok_or("Unknown players")?,
+                "difficulty" => {
+                    profile.difficulty = Difficulty::parse(value).ok_or("Unknown difficulty")?;
+                }
+                "flights" => profile.stats.flights = value.parse()?,
+                "wins" => profile.stats.wins = value.parse()?,
+                "losses" => profile.stats.losses = value.parse()?,
+                "flight-time" => profile.stats.flight_time = secs(value)?,
+                "best-time" | "best" => {
+                    let mut fields = value.split_whitespace();
+                    let level = fields.next().ok_or("Missing level")?.to_owned();
+                    let best = fields.next().ok_or("Missing best")?;
+                    if key == "best" {
+                        profile.progress.best.insert(level, best.parse()?);
+                    } else {
+                        profile.best_times.insert(level, secs(best)?);
+                    }
+                }
+                "unlocked" => {
+                    profile.progress.unlocked.insert(value.to_owned());
+                }
+                _ => return Err(format!("Unknown line {}", line).into()),
+            }
+        }
+        if profile.name.is_empty() {
+            return Err("Missing name".into());
+        }
+        Ok(profile)
+    }
+
+    /// A new file for the profile, named after the player.
+    ///
+    /// Different names can end up the same in a file name, so a number is added to the ones
+    /// already taken, either by another profile or by some file there.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn path(&self, dir: &Path, taken: &[PathBuf]) -> PathBuf {
+        let name = self
+            .name
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '_' })
+            .collect::<String>();
+        (1..)
+            .map(|n| match n {
+                1 => dir.join(format!("{}.profile", name)),
+                n => dir.join(format!("{}-{}.profile", name, n)),
+            })
+            .find(|path| !taken.contains(path) && !path.exists())
+            .expect("Ran out of numbers")
+    }
+}
+
+impl Display for Profile {
+    /// Writes the profile as the content of a profile file.
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        writeln!(fmt, "{}", PROFILE_HEADER)?;
+        writeln!(fmt, "name {}", self.name)?;
+        writeln!(fmt, "players {:?}", self.players)?;
+        writeln!(fmt, "difficulty {:?}", self.difficulty)?;
+        writeln!(fmt, "flights {}", self.stats.flights)?;
+        writeln!(fmt, "wins {}", self.stats.wins)?;
+        writeln!(fmt, "losses {}", self.stats.losses)?;
+        writeln!(fmt, "flight-time {}", self.stats.flight_time.as_secs_f32())?;
+        for (level, time) in &self.best_times {
+            writeln!(fmt, "best-time {} {}", level, time.as_secs_f32())?;
+        }
+        for (level, score) in &self.progress.best {
+            writeln!(fmt, "best {} {}", level, score)?;
+        }
+        for ship in &self.progress.unlocked {
+            writeln!(fmt, "unlocked {}", ship)?;
+        }
+        Ok(())
+    }
+}
+
+/// All the players' profiles, one of them playing.
+#[derive(Clone, Debug)]
+pub struct Profiles {
+    pub profiles: Vec<Profile>,
+    pub current: usize,
+}
+
+impl Default for Profiles {
+    fn default() -> Self {
+        Profiles::new("Player 1")
+    }
+}
+
+impl Profiles {
+    /// Just a fresh profile of the given name.
+    pub fn new(name: &str) -> Self {
+        Profiles {
+            profiles: vec![Profile::new(name)],
+            current: 0,
+        }
+    }
+
+    pub fn current(&self) -> &Profile {
+        &self.profiles[self.current]
+    }
+
+    pub fn current_mut(&mut self) -> &mut Profile {
+        &mut self.profiles[self.current]
+    }
+
+    /// Switches to the next profile.
+    ///
+    /// After the last one comes a fresh one, unless the last one is still fresh.
+    pub fn next(&mut self) {
+        let last = self.profiles.last().expect("There's always a profile");
+        if self.current + 1 == self.profiles.len() && last.stats.flights > 0 {
+            let name = format!("Player {}", self.profiles.len() + 1);
+            info!("New profile {}", name);
+            self.profiles.push(Profile::new(&name));
+        }
+        self.current = (self.current + 1) % self.profiles.len();
+    }
+
+    /// All the profiles from the directory, the last used one playing.
+    ///
+    /// If there are none, there's a fresh one of the given name.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(dir: &Path, name: &str) -> Self {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                info!("No profiles in {}: {}", dir.display(), e);
+                return Profiles::new(name);
+            }
+        };
+        let mut profiles = Vec::new();
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            if path.extension() == Some("profile".as_ref()) {
+                let profile = fs::read_to_string(&path)
+                    .map_err(Box::<dyn Error>::from)
+                    .and_then(|content| Profile::parse(&content));
+                match profile {
+                    Ok(profile) => profiles.push(Profile {
+                        file: Some(path),
+                        ..profile
+                    }),
+                    Err(e) => error!("Broken profile {}: {}", path.display(), e),
+                }
+            }
+        }
+        if profiles.is_empty() {
+            return Profiles::new(name);
+        }
+        profiles.sort_by(|a, b| a.name.cmp(&b.name));
+        let last = fs::read_to_string(dir.join(LAST_PROFILE)).unwrap_or_default();
+        let current = profiles
+            .iter()
+            .position(|profile| profile.name == last.trim())
+            .unwrap_or_default();
+        Profiles { profiles, current }
+    }
+
+    /// Stores the changed profiles and which one plays.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&mut self, dir: &Path) -> Result<(), Box<dyn Error>> {
+        fs::create_dir_all(dir)?;
+        self.assign_files(dir);
+        for profile in self.profiles.iter_mut().filter(|profile| profile.unsaved) {
+            let file = profile.file.as_ref().expect("Profile without a file");
+            fs::write(file, profile.to_string())?;
+            profile.unsaved = false;
+        }
+        fs::write(dir.join(LAST_PROFILE), &self.current().name)?;
+        Ok(())
+    }
+
+    /// Finds files for the profiles that don't have one yet.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn assign_files(&mut self, dir: &Path) {
+        let mut taken = self
+            .profiles
+            .iter()
+            .filter_map(|profile| profile.file.clone())
+            .collect::<Vec<_>>();
+        for profile in self.profiles.iter_mut().filter(|profile| profile.file.is_none()) {
+            let path = profile.path(dir, &taken);
+            taken.push(path.clone());
+            profile.file = Some(path);
+        }
+    }
+
+    /// Needs storing?
+    pub fn unsaved(&self) -> bool {
+        self.profiles.iter().any(|profile| profile.unsaved)
+    }
+}
+
+/// Switches the game to the settings of the current profile.
+///
+/// The caller is expected to restart the level.
+pub fn apply_profile(world: &mut World) {
+    let (players, difficulty) = {
+        let profiles = world.fetch::<Profiles>();
+        let profile = profiles.current();
+        info!("Profile {}", profile.name);
+        let mut loadout = world.fetch_mut::<Loadout>();
+        let ship = &loadout.current().name;
+        if !profile.progress.available(ship) {
+            info!("Ship {} not unlocked in the profile", ship);
+            loadout.custom = None;
+        }
+        (profile.players, profile.difficulty)
+    };
+    *world.fetch_mut::<Players>() = players;
+    world.insert(difficulty);
+    // The assists were for another player
+    world.insert(Assists::default());
+}
+
+/// Notes the finished levels and the chosen settings into the current profile.
+///
+/// Only the player's own runs count, not replays.
+pub struct RecordProfile {
+    pub last: GameState,
+}
+
+#[derive(SystemData)]
+pub struct RecordProfileData<'a> {
+    state: ReadExpect<'a, GameState>,
+    replay: Read<'a, Replay>,
+    attract: Read<'a, Attract>,
+    players: Read<'a, Players>,
+    difficulty: Read<'a, Difficulty>,
+    time: Read<'a, LevelTime>,
+    stats: Write<'a, RunStats>,
+    profiles: Write<'a, Profiles>,
+}
+
+impl<'a> System<'a> for RecordProfile {
+    type SystemData = RecordProfileData<'a>;
+
+    fn run(&mut self, mut d: Self::SystemData) {
+        if d.replay.mode != ReplayMode::Recording || d.attract.0.is_some() {
+            self.last = *d.state;
+            return;
+        }
+        let profile = d.profiles.current_mut();
+        if (profile.players, profile.difficulty) != (*d.players, *d.difficulty) {
+            profile.players = *d.players;
+            profile.difficulty = *d.difficulty;
+            profile.unsaved = true;
+        }
+        if *d.state == self.last {
+            return;
+        }
+        self.last = *d.state;
+        if let GameState::Won | GameState::Lost(_) = *d.state {
+            let score = d.stats.score(d.time.0, *d.difficulty);
+            d.stats.unlocked = profile.record(LEVEL_ID, *d.state, d.time.0, score);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::LostReason;
+
+    #[test]
+    fn profile_round_trip() {
+        let mut profile = Profile::new("Anna Smith");
+        profile.difficulty = Difficulty::Hard;
+        profile.record(LEVEL_ID, GameState::Won, Duration::from_secs(40), 2_000);
+        profile.record(LEVEL_ID, GameState::Won, Duration::from_secs(50), 2_600);
+        profile.unsaved = false;
+        let parsed = Profile::parse(&profile.to_string()).unwrap();
+        assert_eq!(parsed, profile);
+        assert_eq!(parsed.best_times[LEVEL_ID], Duration::from_secs(40));
+        assert_eq!(parsed.progress.best[LEVEL_ID], 2_600);
+        assert!(Profile::parse("thrust-progress 1\n").is_err());
+    }
+
+    #[test]
+    fn losses_unlock_nothing() {
+        let mut profile = Profile::new("Bob");
+        let lost = GameState::Lost(LostReason::Overheated);
+        assert!(profile
+            .record(LEVEL_ID, lost, Duration::from_secs(10), 0)
+            .is_empty());
+        assert_eq!(profile.stats.losses, 1);
+        assert!(profile.best_times.is_empty());
+    }
+
+    #[test]
+    fn broken_times() {
+        let profile = Profile::new("Anna").to_string();
+        for bad in &["flight-time -1", "flight-time NaN", "best-time default inf"] {
+            let content = format!("{}{}\n", profile, bad);
+            assert!(Profile::parse(&content).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn profiles_get_own_files() {
+        let dir = Path::new("no-such-dir");
+        let mut profiles = Profiles::new("Anna?");
+        profiles.profiles.push(Profile::new("Anna!"));
+        profiles.profiles.push(Profile::new("Bob"));
+        profiles.assign_files(dir);
+        let files = profiles
+            .profiles
+            .iter()
+            .map(|profile| profile.file.clone().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(files[0], dir.join("Anna_.profile"));
+        assert_eq!(files[1], dir.join("Anna_-2.profile"));
+        assert_eq!(files[2], dir.join("Bob.profile"));
+        // Once a profile has its file, it keeps it
+        profiles.profiles.remove(0);
+        profiles.assign_files(dir);
+        assert_eq!(profiles.profiles[0].file.as_ref(), Some(&files[1]));
+    }
+
+    #[test]
+    fn switching_profiles() {
+        let mut profiles = Profiles::new("Anna");
+        // A fresh profile doesn't get another fresh one after it
+        profiles.next();
+        assert_eq!(profiles.profiles.len(), 1);
+        profiles.current_mut().stats.flights = 1;
+        profiles.next();
+        assert_eq!(profiles.current().name, "Player 2");
+        profiles.next();
+        assert_eq!(profiles.current().name, "Anna");
+    }
+}
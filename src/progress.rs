@@ -0,0 +1,98 @@
+//! The player's progress through the game and the ships it unlocks.
+//!
+//! Some of the ships need to be earned first, by winning levels or scoring high enough. The
+//! progress is kept in the player's profile.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use log::info;
+
+/// What earns a ship.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Requirement {
+    /// Winning any level.
+    Win,
+    /// Reaching the score in any level.
+    Score(u32),
+}
+
+impl Display for Requirement {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match self {
+            Requirement::Win => write!(fmt, "win a level"),
+            Requirement::Score(score) => write!(fmt, "score {} points", score),
+        }
+    }
+}
+
+/// The ships that need to be earned, the rest is available from the start.
+pub const UNLOCKS: &[(&str, Requirement)] = &[
+    ("Dart", Requirement::Win),
+    ("Hauler", Requirement::Score(2_500)),
+];
+
+/// What the player has achieved so far.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Progress {
+    /// The best score of each won level, by the level id.
+    pub best: BTreeMap<String, u32>,
+    /// The ships earned so far.
+    pub unlocked: BTreeSet<String>,
+}
+
+impl Progress {
+    /// Can the player fly the ship already?
+    pub fn available(&self, ship: &str) -> bool {
+        self.unlocked.contains(ship) || UNLOCKS.iter().all(|(name, _)| *name != ship)
+    }
+
+    /// The ships still waiting to be earned, with what earns them.
+    pub fn locked(&self) -> impl Iterator<Item = &(&'static str, Requirement)> + '_ {
+        UNLOCKS
+            .iter()
+            .filter(move |(ship, _)| !self.unlocked.contains(*ship))
+    }
+
+    /// Notes a won level.
+    ///
+    /// Returns the ships this unlocked.
+    pub fn record(&mut self, level: &str, score: u32) -> Vec<&'static str> {
+        let best = self.best.entry(level.to_owned()).or_default();
+        *best = (*best).max(score);
+        let top = self.best.values().copied().max().unwrap_or_default();
+        let earned = self
+            .locked()
+            .filter(|(_, requirement)| match requirement {
+                Requirement::Win => true,
+                Requirement::Score(score) => top >= *score,
+            })
+            .map(|(ship, _)| *ship)
+            .collect::<Vec<_>>();
+        for ship in &earned {
+            info!("Unlocked the {} ship", ship);
+            self.unlocked.insert((*ship).to_owned());
+        }
+        earned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::LEVEL_ID;
+
+    #[test]
+    fn ships_unlock_once() {
+        let mut progress = Progress::default();
+        assert!(progress.available("Courier"));
+        assert!(!progress.available("Dart"));
+        assert_eq!(progress.record(LEVEL_ID, 1_000), vec!["Dart"]);
+        assert!(progress.available("Dart"));
+        assert!(!progress.available("Hauler"));
+        assert!(progress.record(LEVEL_ID, 900).is_empty());
+        assert_eq!(progress.best[LEVEL_ID], 1_000);
+        assert_eq!(progress.record(LEVEL_ID, 3_000), vec!["Hauler"]);
+        assert_eq!(progress.locked().count(), 0);
+    }
+}
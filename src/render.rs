@@ -0,0 +1,2397 @@
+//! The camera and the drawing of the world.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::f32::consts::PI;
+use std::fmt::Debug;
+use std::time::Duration;
+#[cfg(all(feature = "serialize", not(target_arch = "wasm32")))]
+use std::time::SystemTime;
+
+use quicksilver::geom::{Circle, Rectangle, Transform, Vector};
+use quicksilver::graphics::{Color, FontRenderer, Graphics, VectorFont};
+use quicksilver::lifecycle::{Key, Window};
+use quicksilver::QuicksilverError as QError;
+use specs::prelude::*;
+use specs::SystemData;
+use specs_hierarchy::Hierarchy;
+
+use log::{debug, error, info, trace};
+
+use crate::components::{
+    Boost, Capture, Comet, Condition, Effects, FuelStation, Hull, Interference, LagrangePoint,
+    Landing, Mass, Name, Orbit, Planet, Position, PowerUp, Refueling, Repair, Rotation, Ship, Speed,
+    Star, Strain, Thruster, Zone, ZoneEffect, ZoneShape, LINE_EXTENT,
+};
+use crate::input::{Attract, Gamepads, Keys, Replay, ReplayMode};
+use crate::loadout::{Designer, Loadout, BUDGET};
+use crate::options::{Options, OptionsMenu, Setting};
+use crate::physics::{DifficultyTimeMod, FrameDuration, Gravity, LagrangeData};
+use crate::plugin::Plugin;
+use crate::profile::Profiles;
+use crate::repair::REPAIR_TIME;
+#[cfg(all(feature = "serialize", not(target_arch = "wasm32")))]
+use crate::saves::{MenuMode, SaveMenu, SLOTS};
+use crate::state::{
+    Assists, Difficulty, GameState, Leaderboard, LevelTime, Players, Rules, RunStats, TargetPad,
+    LEVEL_ID,
+};
+use crate::touch::{stick_area, Touches, TOUCH_BUTTONS};
+use crate::tutorial::{Highlight, Hints, Tutorial};
+
+pub const ZOOM_FACTOR: f32 = 1.05;
+/// How fast the free camera moves, in screen pixels per second.
+const PAN_SPEED: f32 = 400.0;
+const OVERHEAT_INDICATOR: f32 = 0.8;
+/// The screen size the game is designed for.
+///
+/// Used as the size of the world visible at zoom 1.0 when keeping the aspect ratio.
+pub const DESIGN_SIZE: Vector = Vector { x: 1024.0, y: 768.0 };
+
+#[derive(Copy, Clone, Debug)]
+pub struct Viewport {
+    pub zoom: f32,
+    pub rect: Rectangle,
+    transform: Transform,
+    /// Keep the design aspect ratio (with bars around) instead of showing more of the world in
+    /// bigger windows.
+    pub letterbox: bool,
+    /// Physical pixels per logical one (HiDPI screens have more than 1).
+    scale_factor: f32,
+    /// How big the text is, relative to the default size.
+    pub text_scale: f32,
+}
+
+impl Default for Viewport {
+    fn default() -> Viewport {
+        let mut me = Viewport {
+            zoom: 1.0,
+            rect: Rectangle::new(Vector::ZERO, DESIGN_SIZE),
+            transform: Transform::default(),
+            letterbox: false,
+            scale_factor: 1.0,
+            text_scale: 1.0,
+        };
+        me.update();
+        me
+    }
+}
+
+impl Viewport {
+    pub fn update(&mut self) {
+        self.transform = Transform::orthographic(self.rect);
+    }
+
+    fn set_size(&mut self, size: Vector) {
+        self.rect.size = size / self.zoom;
+        self.update();
+    }
+
+    fn center(&self) -> Vector {
+        self.rect.pos + self.rect.size / 2.0
+    }
+
+    /// Changes zoom and moves to look at the given center.
+    ///
+    /// Unlike `adjust_to_window_size`, this doesn't need the window, it keeps the current size.
+    fn look_at(&mut self, center: Vector, zoom: f32) {
+        let window_size = self.rect.size * self.zoom;
+        self.zoom = zoom;
+        self.rect.size = window_size / zoom;
+        self.rect.pos = center - self.rect.size / 2.0;
+        self.update();
+    }
+
+    /// The point of the world under a point of the window.
+    ///
+    /// Undoes the orthographic projection onto the window, or onto the part between the bars
+    /// when letterboxed.
+    pub fn unproject(&self, screen: Vector, window_size: Vector) -> Vector {
+        let (offset, size) = if self.letterbox {
+            let scale = (window_size.x / DESIGN_SIZE.x).min(window_size.y / DESIGN_SIZE.y);
+            let size = DESIGN_SIZE * scale;
+            ((window_size - size) / 2.0, size)
+        } else {
+            (Vector::ZERO, window_size)
+        };
+        let relative = screen - offset;
+        self.rect.pos
+            + Vector::new(
+                relative.x / size.x * self.rect.size.x,
+                relative.y / size.y * self.rect.size.y,
+            )
+    }
+
+    pub fn adjust_to_window_size(&mut self, gfx: &Graphics, window: &Window) {
+        self.scale_factor = window.scale_factor();
+        if self.letterbox {
+            self.set_size(DESIGN_SIZE);
+        } else {
+            self.set_size(window.size().into());
+        }
+        gfx.fit_to_window(&window);
+    }
+}
+
+/// The distance of the lines of text, in multiples of the font size.
+const LINE_SPACING: f32 = 1.25;
+
+/// Font renderer that stays crisp on HiDPI screens.
+///
+/// The glyphs are rendered at the physical resolution and scaled down when drawing. The renderer
+/// is recreated whenever the scale factor changes.
+struct TextRenderer<'a> {
+    font: &'a VectorFont,
+    size: f32,
+    /// How much bigger than `size` the glyphs are rendered.
+    scale: f32,
+    renderer: Option<FontRenderer>,
+}
+
+impl<'a> TextRenderer<'a> {
+    /// Creates the renderer.
+    ///
+    /// The glyphs are rendered only once it draws for the first time.
+    pub fn new(font: &'a VectorFont, size: f32) -> Self {
+        TextRenderer {
+            font,
+            size,
+            scale: 1.0,
+            renderer: None,
+        }
+    }
+
+    /// Draws the text, returns its (logical) size.
+    ///
+    /// Expects the default transform to be set and leaves it that way.
+    fn draw(
+        &mut self,
+        gfx: &mut Graphics,
+        viewport: &Viewport,
+        text: &str,
+        color: Color,
+        pos: Vector,
+    ) -> Result<Vector, QError> {
+        let scale_factor = viewport.scale_factor;
+        // The text scale makes the glyphs bigger, the scale factor only sharper
+        let scale = scale_factor * viewport.text_scale;
+        // The lines are laid out here, so they move apart as the text scale makes them taller
+        let line_height = self.line_height(viewport) * scale_factor;
+        let renderer = match &mut self.renderer {
+            Some(renderer) if scale == self.scale => renderer,
+            renderer => {
+                debug!(
+                    "Building font renderer for scale factor {}, text scale {}",
+                    scale_factor, viewport.text_scale,
+                );
+                self.scale = scale;
+                renderer.insert(self.font.to_renderer(gfx, self.size * scale)?)
+            }
+        };
+        gfx.set_transform(Transform::translate(pos) * Transform::scale(Vector::ONE / scale_factor));
+        let lines = text
+            .split('\n')
+            .enumerate()
+            .map(|(i, line)| {
+                renderer.draw(gfx, line, color, Vector::new(0.0, i as f32 * line_height))
+            })
+            .collect::<Result<Vec<_>, _>>();
+        gfx.set_transform(Transform::default());
+        let lines = lines?;
+        let width = lines.iter().map(|line| line.x).fold(0.0, f32::max);
+        Ok(Vector::new(width, lines.len() as f32 * line_height) / scale_factor)
+    }
+
+    /// How far apart the lines of the text are (logically).
+    fn line_height(&self, viewport: &Viewport) -> f32 {
+        self.size * viewport.text_scale * LINE_SPACING
+    }
+}
+
+const COLOR_THRUSTER_OFF: Color = Color {
+    r: 0.5,
+    g: 0.5,
+    b: 0.5,
+    a: 0.5,
+};
+
+const COLOR_THRUSTER_ON: Color = Color {
+    r: 1.0,
+    g: 0.8,
+    b: 0.1,
+    a: 1.0,
+};
+
+/// A blackened nozzle of a dead thruster.
+const COLOR_THRUSTER_DEAD: Color = Color {
+    r: 0.15,
+    g: 0.1,
+    b: 0.1,
+    a: 1.0,
+};
+
+/// How many times a second a degraded thruster flickers.
+const DEGRADED_FLICKER: f32 = 8.0;
+
+/// A degraded thruster, when it doesn't flicker.
+const COLOR_THRUSTER_DEGRADED: Color = Color {
+    r: 0.6,
+    g: 0.35,
+    b: 0.1,
+    a: 1.0,
+};
+
+const COLOR_FUEL: Color = Color {
+    r: 0.2,
+    g: 0.8,
+    b: 0.3,
+    a: 0.8,
+};
+
+/// Length of a full fuel gauge.
+const FUEL_GAUGE_WIDTH: f32 = 20.0;
+/// How far below the ship the fuel gauge is.
+const FUEL_GAUGE_OFFSET: f32 = 15.0;
+
+/// Are the Lagrange points shown?
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ShowLagrange(pub bool);
+
+/// Is the coordinate grid shown?
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ShowGrid(pub bool);
+
+/// Roughly how many grid lines across the screen.
+const GRID_LINES: f32 = 10.0;
+
+const COLOR_GRID: Color = Color {
+    r: 0.3,
+    g: 0.3,
+    b: 0.3,
+    a: 0.5,
+};
+
+const COLOR_GRID_AXIS: Color = Color {
+    r: 0.6,
+    g: 0.6,
+    b: 0.6,
+    a: 0.8,
+};
+
+/// Picks a round grid step (1, 2 or 5 times a power of 10) for the span.
+fn grid_step(span: f32) -> f32 {
+    let raw = span / GRID_LINES;
+    let magnitude = 10f32.powf(raw.log10().floor());
+    let normalized = raw / magnitude;
+    let nice = if normalized < 1.5 {
+        1.0
+    } else if normalized < 3.5 {
+        2.0
+    } else if normalized < 7.5 {
+        5.0
+    } else {
+        10.0
+    };
+    nice * magnitude
+}
+
+/// The grid lines between `from` and `to`, `step` apart.
+///
+/// Counted by an index, adding the step to a huge coordinate may not move it at all. There's a
+/// limit on how many lines there are, in case the rounding goes wrong anyway.
+fn grid_lines(from: f32, to: f32, step: f32) -> impl Iterator<Item = f32> {
+    let first = (from / step).ceil();
+    let count = ((to - first * step) / step) as usize + 1;
+    (0..count.min(4 * GRID_LINES as usize)).map(move |i| first * step + i as f32 * step)
+}
+
+/// World-space coordinate grid, labeled at the top and left edges of the screen.
+struct DrawGrid<'a> {
+    gfx: &'a RefCell<Graphics>,
+    renderer: TextRenderer<'a>,
+}
+
+impl<'a> System<'a> for DrawGrid<'_> {
+    type SystemData = (
+        Read<'a, ShowGrid>,
+        ReadExpect<'a, Viewport>,
+    );
+
+    fn run(&mut self, (show, viewport): Self::SystemData) {
+        if !show.0 {
+            return;
+        }
+        let mut gfx = self.gfx.borrow_mut();
+
+        trace!("Drawing grid");
+        let rect = viewport.rect;
+        let step = grid_step(rect.size.x);
+        let end = rect.pos + rect.size;
+        let mut labels = Vec::new();
+
+        for x in grid_lines(rect.pos.x, end.x, step) {
+            let color = if x == 0.0 { COLOR_GRID_AXIS } else { COLOR_GRID };
+            gfx.stroke_path(&[Vector::new(x, rect.pos.y), Vector::new(x, end.y)], color);
+            let label_pos = Vector::new(x + 2.0, rect.pos.y + 12.0);
+            labels.push((format!("{}", x), label_pos));
+        }
+        for y in grid_lines(rect.pos.y, end.y, step) {
+            let color = if y == 0.0 { COLOR_GRID_AXIS } else { COLOR_GRID };
+            gfx.stroke_path(&[Vector::new(rect.pos.x, y), Vector::new(end.x, y)], color);
+            labels.push((format!("{}", y), Vector::new(rect.pos.x + 2.0, y - 2.0)));
+        }
+
+        for (label, pos) in labels {
+            if let Err(e) = self.renderer.draw(&mut gfx, &viewport, &label, COLOR_GRID_AXIS, pos) {
+                error!("Can't write text: {}", e);
+            }
+        }
+    }
+}
+
+/// Labels disappear when zoomed out below this.
+const LABELS_MIN_ZOOM: f32 = 0.5;
+/// And are fully visible when zoomed in this much.
+const LABELS_FULL_ZOOM: f32 = 0.8;
+
+const COLOR_LABEL: Color = Color {
+    r: 0.8,
+    g: 0.8,
+    b: 0.8,
+    a: 1.0,
+};
+
+struct DrawLabels<'a> {
+    gfx: &'a RefCell<Graphics>,
+    renderer: TextRenderer<'a>,
+}
+
+impl<'a> System<'a> for DrawLabels<'_> {
+    type SystemData = (
+        ReadExpect<'a, Viewport>,
+        ReadStorage<'a, Name>,
+        ReadStorage<'a, Position>,
+    );
+
+    fn run(&mut self, (viewport, names, positions): Self::SystemData) {
+        let visibility = (viewport.zoom - LABELS_MIN_ZOOM) / (LABELS_FULL_ZOOM - LABELS_MIN_ZOOM);
+        if visibility <= 0.0 {
+            return;
+        }
+        let color = COLOR_LABEL.with_alpha(visibility.min(1.0));
+        let mut gfx = self.gfx.borrow_mut();
+
+        trace!("Drawing labels");
+        for (name, pos) in (&names, &positions).join() {
+            let label_pos = pos.0 + Vector::new(10.0, -10.0);
+            if let Err(e) = self.renderer.draw(&mut gfx, &viewport, &name.0, color, label_pos) {
+                error!("Can't write text: {}", e);
+            }
+        }
+    }
+}
+
+/// Is the gravity field shown?
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ShowGravityField(pub bool);
+
+/// How many arrows across the screen.
+const GRAVITY_FIELD_COLUMNS: usize = 32;
+/// Field strength that is drawn as half-way between weak and strong.
+const GRAVITY_FIELD_REFERENCE: f32 = 0.005;
+
+/// Debug view of the gravity field, as arrows over the visible area.
+struct DrawGravityField<'a> {
+    gfx: &'a RefCell<Graphics>,
+}
+
+impl<'a> System<'a> for DrawGravityField<'_> {
+    type SystemData = (
+        Read<'a, ShowGravityField>,
+        ReadExpect<'a, Viewport>,
+        ReadExpect<'a, Gravity>,
+        Read<'a, Rules>,
+        ReadStorage<'a, Mass>,
+        ReadStorage<'a, Position>,
+    );
+
+    fn run(&mut self, (show, viewport, gravity, rules, masses, positions): Self::SystemData) {
+        if !show.0 {
+            return;
+        }
+        let mut gfx = self.gfx.borrow_mut();
+        let gravity = gravity.with_rules(&rules);
+
+        trace!("Drawing gravity field");
+        let cell = viewport.rect.size.x / GRAVITY_FIELD_COLUMNS as f32;
+        let rows = (viewport.rect.size.y / cell).ceil() as usize;
+        for row in 0..rows {
+            for column in 0..GRAVITY_FIELD_COLUMNS {
+                let offset = Vector::new(column as f32 + 0.5, row as f32 + 0.5) * cell;
+                let center = Position(viewport.rect.pos + offset);
+                let field = (&masses, &positions)
+                    .join()
+                    .map(|(mass, pos)| gravity.pull(center, *mass, *pos))
+                    .fold(Vector::ZERO, |a, b| a + b)
+                    * gravity.force;
+                let strength = field.len();
+                if strength <= 0.0 {
+                    continue;
+                }
+                // Maps the strength into 0..1, not linear, the field is very steep.
+                let heat = strength / (strength + GRAVITY_FIELD_REFERENCE);
+                let color = Color {
+                    r: heat,
+                    g: 0.2,
+                    b: 1.0 - heat,
+                    a: 0.6,
+                };
+                let end = center.0 + field.normalize() * (cell * 0.8 * heat);
+                gfx.stroke_path(&[center.0, end], color);
+            }
+        }
+    }
+}
+
+struct DrawStars<'a> {
+    gfx: &'a RefCell<Graphics>,
+}
+
+impl<'a> System<'a> for DrawStars<'_> {
+    type SystemData = (
+        ReadStorage<'a, Star>,
+        ReadStorage<'a, Position>,
+    );
+
+    fn run(&mut self, (stars, positions): Self::SystemData) {
+        let mut gfx = self.gfx.borrow_mut();
+
+        trace!("Drawing stars");
+        // :-( Can't use par_join here, because of gfx not !Send
+        for (star, pos) in (&stars, &positions).join() {
+            gfx.fill_circle(&Circle::new(pos.0, star.size), star.color);
+        }
+    }
+}
+
+const COMET_TAIL_SEGMENTS: usize = 8;
+
+const COLOR_COMET: Color = Color {
+    r: 0.7,
+    g: 0.9,
+    b: 1.0,
+    a: 1.0,
+};
+
+struct DrawComets<'a> {
+    gfx: &'a RefCell<Graphics>,
+}
+
+impl<'a> System<'a> for DrawComets<'_> {
+    type SystemData = (
+        ReadStorage<'a, Comet>,
+        ReadStorage<'a, Star>,
+        ReadStorage<'a, Position>,
+    );
+
+    fn run(&mut self, (comets, stars, positions): Self::SystemData) {
+        let mut gfx = self.gfx.borrow_mut();
+
+        trace!("Drawing comets");
+        for (comet, pos) in (&comets, &positions).join() {
+            let nearest = (&stars, &positions)
+                .join()
+                .map(|(_, star_pos)| pos.0 - star_pos.0)
+                .min_by(|a, b| a.len2().total_cmp(&b.len2()));
+
+            // Without a star around, there's no wind to blow the tail.
+            if let Some(away) = nearest {
+                let dist = away.len();
+                if dist > 0.0 {
+                    let len = (comet.tail / dist).min(comet.max_tail);
+                    let step = away.normalize() * (len / COMET_TAIL_SEGMENTS as f32);
+                    // Fade the tail out in a few segments, looks almost like particles.
+                    for i in 0..COMET_TAIL_SEGMENTS {
+                        let alpha = 1.0 - i as f32 / COMET_TAIL_SEGMENTS as f32;
+                        let start = pos.0 + step * i as f32;
+                        let end = start + step;
+                        gfx.stroke_path(&[start, end], COLOR_COMET.with_alpha(alpha * 0.6));
+                    }
+                }
+            }
+
+            gfx.fill_circle(&Circle::new(pos.0, comet.size), COLOR_COMET);
+        }
+    }
+}
+
+struct DrawPlanets<'a> {
+    gfx: &'a RefCell<Graphics>,
+}
+
+impl<'a> System<'a> for DrawPlanets<'_> {
+    type SystemData = (
+        ReadStorage<'a, Planet>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, Rotation>,
+    );
+
+    fn run(&mut self, (planets, positions, rotations): Self::SystemData) {
+        let mut gfx = self.gfx.borrow_mut();
+
+        trace!("Drawing planets");
+        for (planet, pos, rotation) in (&planets, &positions, rotations.maybe()).join() {
+            gfx.fill_circle(&Circle::new(pos.0, planet.radius), planet.color);
+            // A mark on the surface, so one can see it spin.
+            let rotation = rotation.map(|r| r.0).unwrap_or_default();
+            let mark = pos.0 + Vector::from_angle(rotation) * planet.radius;
+            gfx.stroke_path(&[pos.0, mark], Color::BLACK);
+        }
+    }
+}
+
+const LAGRANGE_MARK: f32 = 5.0;
+
+struct DrawLagrange<'a> {
+    gfx: &'a RefCell<Graphics>,
+    renderer: TextRenderer<'a>,
+}
+
+impl<'a> System<'a> for DrawLagrange<'_> {
+    type SystemData = (
+        LagrangeData<'a>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, Speed>,
+        Read<'a, ShowLagrange>,
+        ReadExpect<'a, Viewport>,
+    );
+
+    fn run(&mut self, (lagrange, positions, speeds, show, viewport): Self::SystemData) {
+        if !show.0 {
+            return;
+        }
+        let mut gfx = self.gfx.borrow_mut();
+
+        trace!("Drawing Lagrange points");
+        for (ent, _) in (&lagrange.entities, &lagrange.lagrange).join() {
+            for point in &LagrangePoint::ALL {
+                let pos = match lagrange.point(&positions, &speeds, ent, *point) {
+                    Some((pos, _)) => pos,
+                    None => continue,
+                };
+                let a = Vector::new(LAGRANGE_MARK, LAGRANGE_MARK);
+                let b = Vector::new(LAGRANGE_MARK, -LAGRANGE_MARK);
+                gfx.stroke_path(&[pos - a, pos + a], Color::MAGENTA);
+                gfx.stroke_path(&[pos - b, pos + b], Color::MAGENTA);
+                let label = point.to_string();
+                let label_pos = pos + a;
+                if let Err(e) = self.renderer.draw(&mut gfx, &viewport, &label, Color::MAGENTA, label_pos) {
+                    error!("Can't write text: {}", e);
+                }
+            }
+        }
+    }
+}
+
+struct DrawShips<'a> {
+    gfx: &'a RefCell<Graphics>,
+}
+
+#[derive(SystemData)]
+struct DrawShipData<'a> {
+    entities: Entities<'a>,
+    ships: ReadStorage<'a, Ship>,
+    positions: ReadStorage<'a, Position>,
+    rotations: ReadStorage<'a, Rotation>,
+    hulls: ReadStorage<'a, Hull>,
+    thrusters: ReadStorage<'a, Thruster>,
+    thruster_hierarchy: ReadExpect<'a, Hierarchy<Thruster>>,
+    // We need to know which thrusters are active
+    keys: Read<'a, Keys>,
+    interferences: ReadStorage<'a, Interference>,
+    time: Read<'a, LevelTime>,
+    options: Read<'a, Options>,
+}
+
+impl<'a> System<'a> for DrawShips<'_> {
+    type SystemData = DrawShipData<'a>;
+
+    fn run(&mut self, d: Self::SystemData) {
+        let mut gfx = self.gfx.borrow_mut();
+
+        trace!("Drawing ships");
+
+        for (ship, pos, rotation, ent) in (&d.ships, &d.positions, &d.rotations, &d.entities).join() {
+            d.draw(&mut gfx, ent, ship, pos, rotation);
+        }
+        gfx.set_transform(Transform::default());
+    }
+}
+
+impl DrawShipData<'_> {
+    /// How far from its middle the ship reaches when drawn, including the thrusters and the gauge.
+    fn extent(&self, ent: Entity) -> f32 {
+        let hull = self.hulls.get(ent).map_or(LINE_EXTENT, Hull::extent);
+        let gauge = Vector::new(FUEL_GAUGE_WIDTH / 2.0, FUEL_GAUGE_OFFSET).len();
+        self.thruster_hierarchy
+            .children(ent)
+            .iter()
+            .filter_map(|thruster| self.thrusters.get(*thruster))
+            .map(|thruster| thruster.position.len() + thruster.len)
+            .fold(hull.max(gauge), f32::max)
+    }
+
+    /// Draws a single ship, including its thrusters.
+    ///
+    /// Leaves the transformation set, the caller is expected to reset it when done.
+    fn draw(
+        &self,
+        gfx: &mut Graphics,
+        ent: Entity,
+        ship: &Ship,
+        pos: &Position,
+        rotation: &Rotation,
+    ) {
+        trace!("Draw ship {:?} {:?}", pos, rotation);
+        let transform = Transform::translate(pos.0) * Transform::rotate(rotation.0);
+        gfx.set_transform(transform);
+        let ship_color = if ship.max_temp * OVERHEAT_INDICATOR <= ship.temperature {
+            Color::RED
+        } else {
+            Color::WHITE
+        };
+        match self.hulls.get(ent) {
+            Some(hull) => gfx.stroke_path(&hull.0, ship_color),
+            None => {
+                let line = [
+                    Vector::new(-LINE_EXTENT, 0.0),
+                    Vector::new(LINE_EXTENT, 0.0),
+                ];
+                gfx.stroke_path(&line, ship_color)
+            }
+        }
+        if ship.max_fuel > 0.0 {
+            // The gauge doesn't turn with the ship
+            gfx.set_transform(Transform::translate(pos.0));
+            let left = Vector::new(-FUEL_GAUGE_WIDTH / 2.0, FUEL_GAUGE_OFFSET);
+            let full = left + Vector::new(FUEL_GAUGE_WIDTH * ship.fuel / ship.max_fuel, 0.0);
+            gfx.stroke_path(&[left, full], COLOR_FUEL);
+        }
+        for thruster in self.thruster_hierarchy.children(ent) {
+            let thruster = self.thrusters
+                .get(*thruster)
+                .expect("Missing thruster reported as child");
+            let t = transform
+                * Transform::translate(thruster.position)
+                * Transform::rotate(thruster.direction);
+            gfx.set_transform(t);
+            let flicker = (self.time.0.as_secs_f32() * DEGRADED_FLICKER * 2.0 * PI).sin() > 0.0;
+            let color = match thruster.condition {
+                Condition::Dead => COLOR_THRUSTER_DEAD,
+                Condition::Degraded if self.options.reduced_motion => COLOR_THRUSTER_DEGRADED,
+                Condition::Degraded if flicker => COLOR_THRUSTER_DEAD,
+                _ if ship.fires(thruster, self.interferences.get(ent), &self.keys) => {
+                    COLOR_THRUSTER_ON
+                }
+                _ => COLOR_THRUSTER_OFF,
+            };
+            gfx.stroke_path(&[Vector::ZERO, Vector::new(thruster.len, 0.0)], color);
+        }
+    }
+}
+
+struct SetViewport<'a> {
+    gfx: &'a RefCell<Graphics>,
+}
+
+impl<'a> System<'a> for SetViewport<'_> {
+    type SystemData = ReadExpect<'a, Viewport>;
+
+    fn run(&mut self, viewport: Self::SystemData) {
+        self.gfx.borrow_mut().set_projection(viewport.transform);
+    }
+}
+
+const COLOR_ZONE: Color = Color {
+    r: 0.6,
+    g: 0.8,
+    b: 1.0,
+    a: 0.3,
+};
+
+const COLOR_ANOMALY: Color = Color {
+    r: 0.9,
+    g: 0.3,
+    b: 1.0,
+    a: 0.4,
+};
+
+/// Distance between the streaks in a force field.
+const STREAK_SPACING: f32 = 40.0;
+const STREAK_LEN: f32 = 12.0;
+/// How fast the streaks in a force field move, in pixels per second.
+const STREAK_SPEED: f32 = 20.0;
+
+/// The zones, as faint outlines with streaks going along the force inside.
+struct DrawZones<'a> {
+    gfx: &'a RefCell<Graphics>,
+}
+
+impl<'a> System<'a> for DrawZones<'_> {
+    type SystemData = (
+        Read<'a, LevelTime>,
+        ReadStorage<'a, Zone>,
+        ReadStorage<'a, Position>,
+    );
+
+    fn run(&mut self, (time, zones, positions): Self::SystemData) {
+        let mut gfx = self.gfx.borrow_mut();
+        for (zone, pos) in (&zones, &positions).join() {
+            let outline = match zone.effect {
+                ZoneEffect::Force { .. } => COLOR_ZONE,
+                ZoneEffect::Magnetic(_) => COLOR_ANOMALY,
+            };
+            let half = match zone.shape {
+                ZoneShape::Rect { size } => {
+                    gfx.stroke_rect(&Rectangle::new(pos.0 - size / 2.0, size), outline);
+                    size / 2.0
+                }
+                ZoneShape::Circle { radius } => {
+                    gfx.stroke_circle(&Circle::new(pos.0, radius), outline);
+                    Vector::ONE * radius
+                }
+            };
+
+            let accel = match zone.effect {
+                ZoneEffect::Force { accel, .. } => accel,
+                ZoneEffect::Magnetic(_) => continue,
+            };
+            let force = zone.effect.force(time.0);
+            if force == Vector::ZERO {
+                continue;
+            }
+            // Weaker as an oscillating force swings through zero
+            let color = COLOR_ZONE.with_alpha(COLOR_ZONE.a * force.len() / accel.len());
+            let dir = force.normalize();
+            let shift = dir * ((time.0.as_secs_f32() * STREAK_SPEED) % STREAK_SPACING);
+            let count = half * 2.0 / STREAK_SPACING;
+            // One more on each side, so the streaks flow in as the grid moves
+            for x in -1..=count.x.ceil() as i32 {
+                for y in -1..=count.y.ceil() as i32 {
+                    let start = pos.0 - half + Vector::new(x, y) * STREAK_SPACING + shift;
+                    let end = start + dir * STREAK_LEN;
+                    if zone.shape.contains(pos.0, start) && zone.shape.contains(pos.0, end) {
+                        gfx.stroke_path(&[start, end], color);
+                    }
+                }
+            }
+        }
+    }
+}
+
+const COLOR_INTERFERENCE: Color = Color {
+    r: 0.9,
+    g: 0.3,
+    b: 1.0,
+    a: 1.0,
+};
+
+/// How many times a second the warning about disturbed controls flickers.
+const INTERFERENCE_FLICKER: f32 = 2.0;
+
+/// How many lines the frame of the warning has.
+const INTERFERENCE_FRAME: usize = 4;
+
+/// Warns the players of their ships' controls being disturbed, by a flickering frame around the
+/// screen and a note next to each disturbed ship.
+struct DrawInterference<'a> {
+    gfx: &'a RefCell<Graphics>,
+    renderer: TextRenderer<'a>,
+}
+
+impl<'a> System<'a> for DrawInterference<'_> {
+    type SystemData = (
+        Read<'a, LevelTime>,
+        Read<'a, Options>,
+        ReadExpect<'a, Viewport>,
+        ReadStorage<'a, Ship>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, Interference>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (time, options, viewport, ships, positions, interferences) = data;
+        if (&ships, &interferences).join().next().is_none() {
+            return;
+        }
+        let mut gfx = self.gfx.borrow_mut();
+        let phase = time.0.as_secs_f32() * INTERFERENCE_FLICKER * 2.0 * PI;
+        let color = if options.reduced_motion {
+            COLOR_INTERFERENCE
+        } else {
+            COLOR_INTERFERENCE.with_alpha(0.5 + 0.5 * phase.sin())
+        };
+        for i in 0..INTERFERENCE_FRAME {
+            let inset = Vector::ONE * (i as f32 + 0.5) / viewport.zoom;
+            let frame = Rectangle::new(viewport.rect.pos + inset, viewport.rect.size - inset * 2.0);
+            gfx.stroke_rect(&frame, color);
+        }
+        // Each pilot needs to know their own controls are scrambled
+        for (_, pos, interference) in (&ships, &positions, &interferences).join() {
+            let text = format!("Magnetic anomaly: {}", interference);
+            let pos = pos.0 + Vector::new(10.0, 10.0);
+            let drawn = self.renderer.draw(&mut gfx, &viewport, &text, COLOR_INTERFERENCE, pos);
+            if let Err(e) = drawn {
+                error!("Can't write text: {}", e);
+            }
+        }
+    }
+}
+
+fn boost_color(boost: Boost) -> Color {
+    match boost {
+        Boost::GravityDampener => Color {
+            r: 0.4,
+            g: 0.6,
+            b: 1.0,
+            a: 1.0,
+        },
+        Boost::Booster => Color {
+            r: 1.0,
+            g: 0.5,
+            b: 0.1,
+            a: 1.0,
+        },
+    }
+}
+
+/// Half of the diagonal of the diamond marking a power-up.
+const POWER_UP_SIZE: f32 = 6.0;
+
+struct DrawPowerUps<'a> {
+    gfx: &'a RefCell<Graphics>,
+}
+
+impl<'a> System<'a> for DrawPowerUps<'_> {
+    type SystemData = (ReadStorage<'a, PowerUp>, ReadStorage<'a, Position>);
+
+    fn run(&mut self, (power_ups, positions): Self::SystemData) {
+        let mut gfx = self.gfx.borrow_mut();
+        for (power_up, pos) in (&power_ups, &positions).join() {
+            let diamond = [
+                pos.0 + Vector::new(0.0, -POWER_UP_SIZE),
+                pos.0 + Vector::new(POWER_UP_SIZE, 0.0),
+                pos.0 + Vector::new(0.0, POWER_UP_SIZE),
+                pos.0 + Vector::new(-POWER_UP_SIZE, 0.0),
+            ];
+            gfx.fill_polygon(&diamond, boost_color(power_up.boost));
+        }
+    }
+}
+
+const COLOR_STATION: Color = Color {
+    r: 1.0,
+    g: 0.8,
+    b: 0.2,
+    a: 1.0,
+};
+
+const STATION_SIZE: f32 = 8.0;
+/// Length of a dash of the fuel beam and of the gap after it.
+const BEAM_DASH: f32 = 6.0;
+/// How fast the dashes of the fuel beam flow to the ship, in pixels per second.
+const BEAM_SPEED: f32 = 30.0;
+
+/// The fuel stations, with a beam flowing to the ships taking fuel.
+struct DrawFuelStations<'a> {
+    gfx: &'a RefCell<Graphics>,
+}
+
+impl<'a> System<'a> for DrawFuelStations<'_> {
+    type SystemData = (
+        Read<'a, LevelTime>,
+        ReadStorage<'a, FuelStation>,
+        ReadStorage<'a, Refueling>,
+        ReadStorage<'a, Position>,
+    );
+
+    fn run(&mut self, (time, stations, refueling, positions): Self::SystemData) {
+        let mut gfx = self.gfx.borrow_mut();
+        for (station, pos) in (&stations, &positions).join() {
+            let half = Vector::ONE * STATION_SIZE / 2.0;
+            gfx.fill_rect(&Rectangle::new(pos.0 - half, half * 2.0), COLOR_STATION);
+            let range = COLOR_STATION.with_alpha(0.3);
+            gfx.stroke_circle(&Circle::new(pos.0, station.range), range);
+        }
+        for (refueling, ship_pos) in (&refueling, &positions).join() {
+            let station_pos = match positions.get(refueling.station) {
+                Some(pos) => pos.0,
+                None => continue,
+            };
+            let beam = ship_pos.0 - station_pos;
+            let len = beam.len();
+            if len == 0.0 {
+                continue;
+            }
+            let dir = beam / len;
+            let mut start = (time.0.as_secs_f32() * BEAM_SPEED) % (BEAM_DASH * 2.0);
+            while start < len {
+                let end = (start + BEAM_DASH).min(len);
+                let dash = [station_pos + dir * start, station_pos + dir * end];
+                gfx.stroke_path(&dash, COLOR_STATION);
+                start += BEAM_DASH * 2.0;
+            }
+        }
+    }
+}
+
+const COLOR_G_METER: Color = Color {
+    r: 0.8,
+    g: 0.8,
+    b: 0.8,
+    a: 0.8,
+};
+
+const G_METER_WIDTH: f32 = 150.0;
+const G_METER_HEIGHT: f32 = 8.0;
+/// How far past the limit the meter goes.
+const G_METER_RANGE: f32 = 1.5;
+
+/// Meters of the G-forces the ships feel, in the bottom right corner.
+///
+/// The mark shows the limit, the bar turns red over it.
+struct DrawGMeter<'a> {
+    gfx: &'a RefCell<Graphics>,
+    renderer: TextRenderer<'a>,
+}
+
+impl<'a> System<'a> for DrawGMeter<'_> {
+    type SystemData = (
+        Read<'a, Rules>,
+        ReadExpect<'a, Viewport>,
+        ReadStorage<'a, Ship>,
+        ReadStorage<'a, Strain>,
+    );
+
+    fn run(&mut self, (rules, viewport, ships, strains): Self::SystemData) {
+        let mut gfx = self.gfx.borrow_mut();
+        let zoom = viewport.zoom;
+        let corner = viewport.rect.pos + viewport.rect.size;
+        let mut pos = corner - Vector::new(G_METER_WIDTH + 20.0, 40.0) / zoom;
+        for (ship, strain) in (&ships, &strains).join() {
+            let g_limit = rules.g_limit * ship.strength;
+            let full = g_limit * G_METER_RANGE;
+            let color = if strain.g > g_limit {
+                Color::RED
+            } else {
+                COLOR_G_METER
+            };
+            let size = Vector::new(G_METER_WIDTH * (strain.g / full).min(1.0), G_METER_HEIGHT);
+            gfx.fill_rect(&Rectangle::new(pos, size / zoom), color);
+            let outline = Vector::new(G_METER_WIDTH, G_METER_HEIGHT);
+            gfx.stroke_rect(&Rectangle::new(pos, outline / zoom), COLOR_G_METER);
+            let limit = pos + Vector::new(G_METER_WIDTH / G_METER_RANGE, 0.0) / zoom;
+            let mark = limit + Vector::new(0.0, G_METER_HEIGHT * 2.0) / zoom;
+            gfx.stroke_path(&[limit - Vector::new(0.0, G_METER_HEIGHT) / zoom, mark], color);
+            let text = format!("{:.1} G", strain.g);
+            let text_pos = pos + Vector::new(0.0, -G_METER_HEIGHT * 2.0) / zoom;
+            if let Err(e) = self.renderer.draw(&mut gfx, &viewport, &text, color, text_pos) {
+                error!("Can't write text: {}", e);
+            }
+            pos.y -= 40.0 / zoom;
+        }
+    }
+}
+
+/// How much bigger the ship is on the designer screen.
+const DESIGNER_SCALE: f32 = 8.0;
+
+const COLOR_DESIGNER_HULL: Color = Color {
+    r: 0.4,
+    g: 0.4,
+    b: 0.4,
+    a: 1.0,
+};
+
+/// The designer screen, the loadout of the ships blown up in the middle of the screen.
+struct DrawDesigner<'a> {
+    gfx: &'a RefCell<Graphics>,
+    renderer: TextRenderer<'a>,
+}
+
+impl<'a> System<'a> for DrawDesigner<'_> {
+    type SystemData = (
+        Read<'a, Designer>,
+        Read<'a, Loadout>,
+        ReadExpect<'a, Viewport>,
+    );
+
+    fn run(&mut self, (designer, loadout, viewport): Self::SystemData) {
+        if !designer.open {
+            return;
+        }
+        let design = loadout.current();
+        let mut gfx = self.gfx.borrow_mut();
+        let center = viewport.rect.pos + viewport.rect.size / 2.0;
+        let scale = Vector::ONE * DESIGNER_SCALE;
+        let transform = Transform::translate(center) * Transform::scale(scale);
+        gfx.set_transform(transform);
+        gfx.stroke_rect(&design.bounds(), COLOR_DESIGNER_HULL);
+        gfx.stroke_path(&design.hull, Color::WHITE);
+        for (i, thruster) in design.thrusters.iter().enumerate() {
+            let color = if i == designer.selected {
+                COLOR_THRUSTER_ON
+            } else {
+                COLOR_THRUSTER_OFF
+            };
+            gfx.set_transform(
+                transform
+                    * Transform::translate(thruster.position)
+                    * Transform::rotate(thruster.angle),
+            );
+            gfx.stroke_path(&[Vector::ZERO, Vector::new(thruster.len, 0.0)], color);
+        }
+        gfx.set_transform(Transform::default());
+
+        let selected = &design.thrusters[designer.selected];
+        let text = format!(
+            concat!(
+                "Ship designer: {}\n",
+                "Push used: {:.1} / {:.1}\n",
+                "Thruster {} of {}: {}, {:.0} degrees, push {:.1}\n\n",
+                "Up/Down to select a thruster\n",
+                "Left/Right to turn it\n",
+                "WASD to move it\n",
+                "PageUp/PageDown to change its push\n",
+                "C to change the control firing it\n",
+                "Insert to copy it, Delete to remove it\n",
+                "Enter or 5 when done\n",
+            ),
+            design.name,
+            design.cost(),
+            BUDGET,
+            designer.selected + 1,
+            design.thrusters.len(),
+            selected.control,
+            selected.angle,
+            selected.push,
+        );
+        let pos = viewport.rect.pos + Vector::new(200, 200);
+        if let Err(e) = self.renderer.draw(&mut gfx, &viewport, &text, Color::WHITE, pos) {
+            error!("Can't write text: {}", e);
+        }
+    }
+}
+
+const COLOR_MENU_BACKGROUND: Color = Color {
+    r: 0.0,
+    g: 0.0,
+    b: 0.0,
+    a: 0.85,
+};
+
+/// The options menu, covering everything else.
+struct DrawOptions<'a> {
+    gfx: &'a RefCell<Graphics>,
+    renderer: TextRenderer<'a>,
+}
+
+impl<'a> System<'a> for DrawOptions<'_> {
+    type SystemData = (
+        Read<'a, OptionsMenu>,
+        Read<'a, Options>,
+        ReadExpect<'a, Viewport>,
+    );
+
+    fn run(&mut self, (menu, options, viewport): Self::SystemData) {
+        if !menu.open {
+            return;
+        }
+        let settings = Setting::all();
+        let lines = settings
+            .iter()
+            .enumerate()
+            .map(|(i, setting)| {
+                let marker = if i == menu.selected { ">" } else { " " };
+                let (name, value) = options.describe(*setting);
+                format!("{} {}: {}\n", marker, name, value)
+            })
+            .collect::<String>();
+        let help = if menu.capturing {
+            let setting = settings[menu.selected];
+            let (name, _) = options.describe(setting);
+            let what = match setting {
+                Setting::Button { .. } => "button",
+                _ => "key",
+            };
+            format!("Press the {} for {} (Backspace to cancel)\n", what, name)
+        } else {
+            concat!(
+                "Up/Down to select\n",
+                "Left/Right or Enter to change, Enter and a key to change a key\n",
+                "Backspace or F2 to close\n",
+            )
+            .to_owned()
+        };
+        let text = format!("Options\n\n{}\n{}", lines, help);
+        let mut gfx = self.gfx.borrow_mut();
+        gfx.fill_rect(&viewport.rect, COLOR_MENU_BACKGROUND);
+        let pos = viewport.rect.pos + Vector::new(200, 50);
+        if let Err(e) = self.renderer.draw(&mut gfx, &viewport, &text, Color::WHITE, pos) {
+            error!("Can't write text: {}", e);
+        }
+    }
+}
+
+/// How long ago something happened, roughly.
+#[cfg(all(feature = "serialize", not(target_arch = "wasm32")))]
+fn ago(time: SystemTime) -> String {
+    let secs = SystemTime::now().duration_since(time).unwrap_or_default().as_secs();
+    match secs {
+        0..=59 => "just now".to_owned(),
+        60..=3_599 => format!("{} min ago", secs / 60),
+        3_600..=86_399 => format!("{} h ago", secs / 3_600),
+        _ => format!("{} days ago", secs / 86_400),
+    }
+}
+
+/// The menu of the save slots, covering everything else.
+#[cfg(all(feature = "serialize", not(target_arch = "wasm32")))]
+struct DrawSaveMenu<'a> {
+    gfx: &'a RefCell<Graphics>,
+    renderer: TextRenderer<'a>,
+}
+
+#[cfg(all(feature = "serialize", not(target_arch = "wasm32")))]
+impl<'a> System<'a> for DrawSaveMenu<'_> {
+    type SystemData = (Read<'a, SaveMenu>, ReadExpect<'a, Viewport>);
+
+    fn run(&mut self, (menu, viewport): Self::SystemData) {
+        let (title, action) = match menu.mode {
+            Some(MenuMode::Save) => ("Save the level", "save into the slot"),
+            Some(MenuMode::Load) => ("Load a saved level", "load it"),
+            None => return,
+        };
+        let slots = (0..SLOTS)
+            .map(|slot| {
+                let marker = if slot == menu.selected { ">" } else { " " };
+                let content = match menu.slots.get(slot).and_then(Option::as_ref) {
+                    Some(info) => format!(
+                        "{}, {}, {} ({}), {:.1}s played, saved {}",
+                        info.level,
+                        info.player,
+                        info.players,
+                        info.difficulty,
+                        info.play_time.as_secs_f32(),
+                        ago(info.saved),
+                    ),
+                    None => "Empty".to_owned(),
+                };
+                format!("{} {}. {}\n", marker, slot + 1, content)
+            })
+            .collect::<String>();
+        let text = format!(
+            "{}\n\n{}\nUp/Down to select a slot\nEnter to {}, Backspace to close\n",
+            title, slots, action,
+        );
+        let mut gfx = self.gfx.borrow_mut();
+        gfx.fill_rect(&viewport.rect, COLOR_MENU_BACKGROUND);
+        let pos = viewport.rect.pos + Vector::new(200, 200);
+        if let Err(e) = self.renderer.draw(&mut gfx, &viewport, &text, Color::WHITE, pos) {
+            error!("Can't write text: {}", e);
+        }
+    }
+}
+
+/// Counts down the boosts of the ships, in the top right corner.
+struct DrawEffects<'a> {
+    gfx: &'a RefCell<Graphics>,
+    renderer: TextRenderer<'a>,
+}
+
+impl<'a> System<'a> for DrawEffects<'_> {
+    type SystemData = (
+        ReadExpect<'a, Viewport>,
+        ReadStorage<'a, Ship>,
+        ReadStorage<'a, Effects>,
+    );
+
+    fn run(&mut self, (viewport, ships, effects): Self::SystemData) {
+        let mut gfx = self.gfx.borrow_mut();
+        let mut pos = viewport.rect.pos + Vector::new(viewport.rect.size.x - 250.0, 20.0);
+        for (_, effects) in (&ships, &effects).join() {
+            for (boost, left) in &effects.0 {
+                let text = format!("{}: {:.1}s", boost, left.as_secs_f32());
+                match self.renderer.draw(&mut gfx, &viewport, &text, boost_color(*boost), pos) {
+                    Ok(size) => pos.y += size.y,
+                    Err(e) => error!("Can't write text: {}", e),
+                }
+            }
+        }
+    }
+}
+
+struct DrawLandings<'a> {
+    gfx: &'a RefCell<Graphics>,
+}
+
+impl<'a> System<'a> for DrawLandings<'_> {
+    type SystemData = (
+        Read<'a, Rules>,
+        ReadStorage<'a, Landing>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, Capture>,
+    );
+
+    fn run(&mut self, (rules, landings, positions, captures): Self::SystemData) {
+        let mut gfx = self.gfx.borrow_mut();
+        for (landing, position) in (&landings, &positions).join() {
+            draw_landing(&mut gfx, position, landing, &rules);
+        }
+        for capture in captures.join() {
+            let pad = (landings.get(capture.pad), positions.get(capture.pad));
+            if let (Some(landing), Some(position)) = pad {
+                draw_capture(&mut gfx, position, landing, &rules, capture);
+            }
+        }
+    }
+}
+
+const COLOR_CAPTURE: Color = Color {
+    r: 0.3,
+    g: 1.0,
+    b: 0.3,
+    a: 1.0,
+};
+
+const CAPTURE_SEGMENTS: usize = 60;
+
+/// How far outside of the landing area the capture progress goes.
+const CAPTURE_GAP: f32 = 4.0;
+
+/// A ring around the landing area filling up as the ship holds still in it.
+fn draw_capture(
+    gfx: &mut Graphics,
+    position: &Position,
+    landing: &Landing,
+    rules: &Rules,
+    capture: &Capture,
+) {
+    let progress = if rules.capture_time > Duration::default() {
+        (capture.held.as_secs_f32() / rules.capture_time.as_secs_f32()).min(1.0)
+    } else {
+        1.0
+    };
+    let radius = landing.radius(rules.land_distance) + CAPTURE_GAP;
+    draw_progress(gfx, position.0, radius, progress, COLOR_CAPTURE);
+}
+
+/// A part of a circle, going clockwise from the top as the progress goes from 0 to 1.
+fn draw_progress(gfx: &mut Graphics, center: Vector, radius: f32, progress: f32, color: Color) {
+    let segments = (CAPTURE_SEGMENTS as f32 * progress).ceil() as usize;
+    let points = (0..=segments)
+        .map(|i| {
+            let angle = i as f32 * 360.0 / CAPTURE_SEGMENTS as f32 - 90.0;
+            center + Vector::from_angle(angle) * radius
+        })
+        .collect::<Vec<_>>();
+    gfx.stroke_path(&points, color);
+}
+
+const COLOR_REPAIR: Color = Color {
+    r: 0.4,
+    g: 0.8,
+    b: 1.0,
+    a: 1.0,
+};
+
+/// Radius of the ring showing the progress of a repair.
+const REPAIR_RING: f32 = 16.0;
+
+/// The repairs going on and the spare parts left for the damaged ships.
+struct DrawRepairs<'a> {
+    gfx: &'a RefCell<Graphics>,
+    renderer: TextRenderer<'a>,
+}
+
+impl<'a> System<'a> for DrawRepairs<'_> {
+    type SystemData = (
+        ReadExpect<'a, Viewport>,
+        ReadStorage<'a, Ship>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, Repair>,
+        ReadStorage<'a, Thruster>,
+    );
+
+    fn run(&mut self, (viewport, ships, positions, repairs, thrusters): Self::SystemData) {
+        let mut gfx = self.gfx.borrow_mut();
+        for (repair, pos) in (&repairs, &positions).join() {
+            let progress = repair.progress.as_secs_f32() / REPAIR_TIME.as_secs_f32();
+            draw_progress(&mut gfx, pos.0, REPAIR_RING, progress.min(1.0), COLOR_REPAIR);
+        }
+        let damaged = thrusters
+            .join()
+            .filter(|thruster| thruster.condition != Condition::Intact)
+            .map(|thruster| thruster.ship)
+            .collect::<HashSet<_>>();
+        for ent in damaged {
+            if let (Some(ship), Some(pos)) = (ships.get(ent), positions.get(ent)) {
+                let text = format!("Spare parts: {}", ship.spare_parts);
+                let pos = pos.0 + Vector::new(-FUEL_GAUGE_WIDTH / 2.0, FUEL_GAUGE_OFFSET + 4.0);
+                if let Err(e) = self.renderer.draw(&mut gfx, &viewport, &text, COLOR_REPAIR, pos) {
+                    error!("Can't write text: {}", e);
+                }
+            }
+        }
+    }
+}
+
+/// The outer circle is where the ships need to get.
+fn draw_landing(gfx: &mut Graphics, position: &Position, landing: &Landing, rules: &Rules) {
+    let radius = landing.radius(rules.land_distance);
+    gfx.stroke_circle(&Circle::new(position.0, radius * 0.6), Color::RED);
+    gfx.stroke_circle(&Circle::new(position.0, radius), Color::BLUE);
+}
+
+/// Size of the picture-in-picture view, in screen pixels.
+const PIP_SIZE: Vector = Vector { x: 240.0, y: 180.0 };
+/// Distance of the picture-in-picture view from the screen edges.
+const PIP_MARGIN: f32 = 10.0;
+/// Zoom of the picture-in-picture view.
+const PIP_ZOOM: f32 = 2.5;
+/// How many corners the planets in the picture-in-picture view have.
+const PIP_SEGMENTS: usize = 48;
+
+/// Cuts a convex polygon to the part inside the rectangle.
+fn clip_polygon(points: &[Vector], rect: &Rectangle) -> Vec<Vector> {
+    let end = rect.pos + rect.size;
+    // How far inside of each of the edges a point is, negative outside
+    let edges: [&dyn Fn(Vector) -> f32; 4] = [
+        &|p| p.x - rect.pos.x,
+        &|p| end.x - p.x,
+        &|p| p.y - rect.pos.y,
+        &|p| end.y - p.y,
+    ];
+    let mut points = points.to_vec();
+    for inside in &edges {
+        let input = std::mem::take(&mut points);
+        for (i, current) in input.iter().enumerate() {
+            let previous = input[(i + input.len() - 1) % input.len()];
+            let (before, now) = (inside(previous), inside(*current));
+            if (before >= 0.0) != (now >= 0.0) {
+                points.push(previous + (*current - previous) * (before / (before - now)));
+            }
+            if now >= 0.0 {
+                points.push(*current);
+            }
+        }
+    }
+    points
+}
+
+/// A small zoomed-in view of the target landing area in the bottom right corner.
+///
+/// There's no clipping, so the planets are cut to the view by hand and the other things are drawn
+/// only if they fit whole. Only the important things are drawn.
+struct DrawPip<'a> {
+    gfx: &'a RefCell<Graphics>,
+}
+
+impl<'a> System<'a> for DrawPip<'_> {
+    type SystemData = (
+        Read<'a, TargetPad>,
+        Read<'a, Rules>,
+        ReadExpect<'a, Viewport>,
+        ReadStorage<'a, Landing>,
+        ReadStorage<'a, Star>,
+        ReadStorage<'a, Planet>,
+        DrawShipData<'a>,
+    );
+
+    fn run(&mut self, d: Self::SystemData) {
+        let (target, rules, viewport, landings, stars, planets, ships) = d;
+        let pad = match target.0.and_then(|pad| ships.positions.get(pad)) {
+            Some(pad) => *pad,
+            None => return,
+        };
+        let mut gfx = self.gfx.borrow_mut();
+
+        trace!("Drawing picture in picture");
+        let screen = Rectangle::new(Vector::ZERO, viewport.rect.size * viewport.zoom);
+        let screen_proj = Transform::orthographic(screen);
+        let pip = Rectangle::new(screen.size - PIP_SIZE - Vector::ONE * PIP_MARGIN, PIP_SIZE);
+        let world_size = PIP_SIZE / PIP_ZOOM;
+        let world_pos = pad.0 - world_size / 2.0;
+        let view = Rectangle::new(world_pos, world_size);
+        let fits = |pos: &Position, radius: f32| {
+            let rel = pos.0 - world_pos;
+            rel.x >= radius
+                && rel.y >= radius
+                && rel.x + radius <= world_size.x
+                && rel.y + radius <= world_size.y
+        };
+
+        gfx.set_transform(Transform::default());
+        gfx.set_projection(screen_proj);
+        gfx.fill_rect(&pip, Color::BLACK);
+
+        gfx.set_projection(screen_proj
+            * Transform::translate(pip.pos)
+            * Transform::scale(Vector::ONE * PIP_ZOOM)
+            * Transform::translate(-world_pos));
+        for (star, pos) in (&stars, &ships.positions).join() {
+            if fits(pos, star.size) {
+                gfx.fill_circle(&Circle::new(pos.0, star.size), star.color);
+            }
+        }
+        for (planet, pos) in (&planets, &ships.positions).join() {
+            let outline = (0..PIP_SEGMENTS)
+                .map(|i| {
+                    let angle = i as f32 * 360.0 / PIP_SEGMENTS as f32;
+                    pos.0 + Vector::from_angle(angle) * planet.radius
+                })
+                .collect::<Vec<_>>();
+            let visible = clip_polygon(&outline, &view);
+            if visible.len() >= 3 {
+                gfx.fill_polygon(&visible, planet.color);
+            }
+        }
+        for (landing, pos) in (&landings, &ships.positions).join() {
+            if fits(pos, landing.radius(rules.land_distance)) {
+                draw_landing(&mut gfx, pos, landing, &rules);
+            }
+        }
+        let ship_parts = (&ships.ships, &ships.positions, &ships.rotations, &ships.entities);
+        for (ship, pos, rotation, ent) in ship_parts.join() {
+            if fits(pos, ships.extent(ent)) {
+                ships.draw(&mut gfx, ent, ship, pos, rotation);
+            }
+        }
+
+        gfx.set_transform(Transform::default());
+        gfx.set_projection(screen_proj);
+        gfx.stroke_rect(&pip, Color::WHITE);
+        gfx.set_projection(viewport.transform);
+    }
+}
+
+/// How long it takes the camera to fly somewhere.
+const CAMERA_FLIGHT_DURATION: f32 = 0.6;
+/// Space around the things when zooming to fit them, as a fraction of the size.
+const FIT_MARGIN: f32 = 0.1;
+
+/// Smooth camera movement from one place (and zoom) to another.
+#[derive(Copy, Clone, Debug)]
+struct CameraFlight {
+    from_center: Vector,
+    from_zoom: f32,
+    to_center: Vector,
+    to_zoom: f32,
+    /// From 0 to 1.
+    progress: f32,
+}
+
+impl CameraFlight {
+    fn new(viewport: &Viewport, to_center: Vector, to_zoom: f32) -> Self {
+        CameraFlight {
+            from_center: viewport.center(),
+            from_zoom: viewport.zoom,
+            to_center,
+            to_zoom,
+            progress: 0.0,
+        }
+    }
+
+    /// Moves the flight forward and updates the viewport.
+    ///
+    /// With reduced motion, it jumps straight to the end. Returns if the flight is finished.
+    fn step(&mut self, viewport: &mut Viewport, elapsed: f32, reduced_motion: bool) -> bool {
+        self.progress = if reduced_motion {
+            1.0
+        } else {
+            (self.progress + elapsed / CAMERA_FLIGHT_DURATION).min(1.0)
+        };
+        // Ease in & out
+        let t = self.progress * self.progress * (3.0 - 2.0 * self.progress);
+        let center = self.from_center + (self.to_center - self.from_center) * t;
+        // Zoom is multiplicative, so interpolate it that way to look uniform.
+        let zoom = self.from_zoom * (self.to_zoom / self.from_zoom).powf(t);
+        viewport.look_at(center, zoom);
+        self.progress >= 1.0
+    }
+}
+
+/// Request and state of the zoom-to-fit camera movement.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct FitView {
+    pub requested: bool,
+    flight: Option<CameraFlight>,
+}
+
+#[derive(SystemData)]
+pub struct FitCameraData<'a> {
+    frame_duration: Read<'a, FrameDuration>,
+    options: Read<'a, Options>,
+    fit: Write<'a, FitView>,
+    viewport: WriteExpect<'a, Viewport>,
+    positions: ReadStorage<'a, Position>,
+    ships: ReadStorage<'a, Ship>,
+    stars: ReadStorage<'a, Star>,
+    planets: ReadStorage<'a, Planet>,
+    landings: ReadStorage<'a, Landing>,
+}
+
+/// Flies the camera so all the important things are visible.
+pub struct FitCamera;
+
+impl<'a> System<'a> for FitCamera {
+    type SystemData = FitCameraData<'a>;
+
+    fn run(&mut self, mut d: Self::SystemData) {
+        if d.fit.requested {
+            d.fit.requested = false;
+            let important = d.ships.mask() | d.stars.mask() | d.planets.mask();
+            let important = &important | d.landings.mask();
+            let bounds = (&d.positions, &important)
+                .join()
+                .map(|(pos, _)| (pos.0, pos.0))
+                .fold(None, |acc: Option<(Vector, Vector)>, (min, max)| match acc {
+                    Some((amin, amax)) => Some((
+                        Vector::new(amin.x.min(min.x), amin.y.min(min.y)),
+                        Vector::new(amax.x.max(max.x), amax.y.max(max.y)),
+                    )),
+                    None => Some((min, max)),
+                });
+            if let Some((min, max)) = bounds {
+                let size = (max - min) * (1.0 + 2.0 * FIT_MARGIN);
+                let window_size = d.viewport.rect.size * d.viewport.zoom;
+                let zoom = (window_size.x / size.x.max(1.0)).min(window_size.y / size.y.max(1.0));
+                let center = (min + max) / 2.0;
+                d.fit.flight = Some(CameraFlight::new(&d.viewport, center, zoom));
+            }
+        }
+
+        if let Some(mut flight) = d.fit.flight {
+            let elapsed = d.frame_duration.0.as_secs_f32();
+            let done = flight.step(&mut d.viewport, elapsed, d.options.reduced_motion);
+            d.fit.flight = if done { None } else { Some(flight) };
+        }
+    }
+}
+
+/// The spectator camera, following one thing after another.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Spectator {
+    /// Switch to the next thing to follow.
+    pub next_requested: bool,
+    target: Option<Entity>,
+    flight: Option<CameraFlight>,
+}
+
+#[derive(SystemData)]
+pub struct SpectateData<'a> {
+    frame_duration: Read<'a, FrameDuration>,
+    options: Read<'a, Options>,
+    spectator: Write<'a, Spectator>,
+    viewport: WriteExpect<'a, Viewport>,
+    entities: Entities<'a>,
+    positions: ReadStorage<'a, Position>,
+    ships: ReadStorage<'a, Ship>,
+    stars: ReadStorage<'a, Star>,
+    planets: ReadStorage<'a, Planet>,
+    landings: ReadStorage<'a, Landing>,
+}
+
+/// Follows the spectator's target, cycling through ships, stars, planets and landing areas.
+///
+/// After the last one, it turns the spectator off.
+pub struct Spectate;
+
+impl<'a> System<'a> for Spectate {
+    type SystemData = SpectateData<'a>;
+
+    fn run(&mut self, mut d: Self::SystemData) {
+        if d.spectator.next_requested {
+            d.spectator.next_requested = false;
+            // Ships first, they are the most interesting.
+            let candidates = [d.ships.mask(), d.stars.mask(), d.planets.mask(), d.landings.mask()]
+                .iter()
+                .flat_map(|mask| (&d.entities, &d.positions, *mask).join().map(|(ent, ..)| ent))
+                .collect::<Vec<_>>();
+            let next = match d.spectator.target {
+                Some(current) => candidates
+                    .iter()
+                    .position(|ent| *ent == current)
+                    .and_then(|idx| candidates.get(idx + 1)),
+                None => candidates.first(),
+            };
+            d.spectator.target = next.copied();
+            d.spectator.flight = next
+                .and_then(|ent| d.positions.get(*ent))
+                .map(|pos| CameraFlight::new(&d.viewport, pos.0, d.viewport.zoom));
+            info!("Spectating {:?}", d.spectator.target);
+        }
+
+        let target = match d.spectator.target.and_then(|ent| d.positions.get(ent)) {
+            Some(pos) => pos.0,
+            None => {
+                // It disappeared (eg. the level got restarted)
+                d.spectator.target = None;
+                d.spectator.flight = None;
+                return;
+            }
+        };
+
+        if let Some(mut flight) = d.spectator.flight {
+            // The target keeps moving while we fly there.
+            flight.to_center = target;
+            let elapsed = d.frame_duration.0.as_secs_f32();
+            let done = flight.step(&mut d.viewport, elapsed, d.options.reduced_motion);
+            d.spectator.flight = if done { None } else { Some(flight) };
+        } else {
+            let zoom = d.viewport.zoom;
+            d.viewport.look_at(target, zoom);
+        }
+    }
+}
+
+/// The point of the world the player clicked on, to fly to.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Waypoint(pub Option<Vector>);
+
+/// Is the camera detached for free panning?
+#[derive(Copy, Clone, Debug, Default)]
+pub struct FreeCamera(pub bool);
+
+impl FreeCamera {
+    /// Can the player move the camera around?
+    ///
+    /// Either with the free camera, or anytime the game is paused.
+    pub fn active(self, state: GameState) -> bool {
+        self.0 || state == GameState::Paused
+    }
+}
+
+/// The save menu, in the builds that have one.
+#[cfg(all(feature = "serialize", not(target_arch = "wasm32")))]
+type SaveMenuData<'a> = Read<'a, SaveMenu>;
+#[cfg(not(all(feature = "serialize", not(target_arch = "wasm32"))))]
+type SaveMenuData<'a> = ();
+
+#[cfg(all(feature = "serialize", not(target_arch = "wasm32")))]
+fn save_menu_open(menu: &SaveMenuData) -> bool {
+    menu.mode.is_some()
+}
+
+#[cfg(not(all(feature = "serialize", not(target_arch = "wasm32"))))]
+fn save_menu_open(_: &SaveMenuData) -> bool {
+    false
+}
+
+#[derive(SystemData)]
+pub struct PanCameraData<'a> {
+    frame_duration: Read<'a, FrameDuration>,
+    free: Read<'a, FreeCamera>,
+    state: ReadExpect<'a, GameState>,
+    keys: ReadExpect<'a, Keys>,
+    options_menu: Read<'a, OptionsMenu>,
+    save_menu: SaveMenuData<'a>,
+    ships: ReadStorage<'a, Ship>,
+    viewport: WriteExpect<'a, Viewport>,
+}
+
+/// Pans the free camera with WASD or the arrows.
+///
+/// Unless the keys control a ship, but while paused nothing flies anyway. The menus use the same
+/// keys, so the camera stays while one is open.
+pub struct PanCamera;
+
+impl<'a> System<'a> for PanCamera {
+    type SystemData = PanCameraData<'a>;
+
+    fn run(&mut self, d: Self::SystemData) {
+        let PanCameraData {
+            frame_duration,
+            free,
+            state,
+            keys,
+            options_menu,
+            save_menu,
+            ships,
+            mut viewport,
+        } = d;
+        if !free.active(*state) || options_menu.open || save_menu_open(&save_menu) {
+            return;
+        }
+        let paused = *state == GameState::Paused;
+        let direction = [
+            (Key::W, Vector::new(0, -1)),
+            (Key::A, Vector::new(-1, 0)),
+            (Key::S, Vector::new(0, 1)),
+            (Key::D, Vector::new(1, 0)),
+            (Key::Up, Vector::new(0, -1)),
+            (Key::Left, Vector::new(-1, 0)),
+            (Key::Down, Vector::new(0, 1)),
+            (Key::Right, Vector::new(1, 0)),
+        ]
+            .iter()
+            .filter(|(key, _)| keys.contains(key))
+            .filter(|(key, _)| paused || !ships.join().any(|ship| ship.keys.contains(*key)))
+            .fold(Vector::ZERO, |a, (_, dir)| a + *dir);
+        if direction != Vector::ZERO {
+            let dist = PAN_SPEED * frame_duration.0.as_secs_f32() / viewport.zoom;
+            viewport.rect.pos += direction * dist;
+            viewport.update();
+        }
+    }
+}
+
+const COLOR_ASSISTS: Color = Color {
+    r: 1.0,
+    g: 0.9,
+    b: 0.4,
+    a: 0.8,
+};
+
+/// The results screen part of the text at the end of a level.
+fn results(
+    stats: &RunStats,
+    time: Duration,
+    difficulty: Difficulty,
+    names: &ReadStorage<Name>,
+) -> String {
+    let accuracy = match stats.accuracy {
+        Some(accuracy) => format!("{:.0}%", accuracy * 100.0),
+        None => "-".to_owned(),
+    };
+    let pads = stats
+        .pads
+        .iter()
+        .map(|pad| names.get(*pad).map(|name| name.0.as_str()).unwrap_or("unnamed"))
+        .collect::<Vec<_>>();
+    let landed = if pads.is_empty() {
+        String::new()
+    } else {
+        format!("Landed at: {} (+{} points)\n", pads.join(", "), stats.pad_points)
+    };
+    let unlocked = stats
+        .unlocked
+        .iter()
+        .map(|ship| format!("Unlocked the {} ship!\n", ship))
+        .collect::<String>();
+    format!(
+        concat!(
+            "{}",
+            "Time: {:.2}s\n",
+            "Fuel used: {:.1}s, left: {:.1}s of {:.1}s\n",
+            "Max speed: {:.1}\n",
+            "Landing accuracy: {}\n",
+            "Score: {}\n",
+            "{}",
+            "\n",
+            "R to retry, Enter to continue\n",
+        ),
+        landed,
+        time.as_secs_f32(),
+        stats.fuel_used,
+        stats.fuel_left,
+        stats.fuel_total,
+        stats.max_speed,
+        accuracy,
+        stats.score(time, difficulty),
+        unlocked,
+    )
+}
+
+struct DrawState<'a> {
+    gfx: &'a RefCell<Graphics>,
+    renderer: TextRenderer<'a>,
+}
+
+impl<'a> System<'a> for DrawState<'_> {
+    type SystemData = (
+        ReadExpect<'a, GameState>,
+        ReadExpect<'a, Viewport>,
+        Read<'a, Attract>,
+        Read<'a, Players>,
+        Read<'a, Difficulty>,
+        Read<'a, Assists>,
+        Read<'a, Gamepads>,
+        Read<'a, LevelTime>,
+        Read<'a, Leaderboard>,
+        Read<'a, Tutorial>,
+        Read<'a, RunStats>,
+        Read<'a, Designer>,
+        Read<'a, Loadout>,
+        Read<'a, Profiles>,
+        Read<'a, Options>,
+        ReadStorage<'a, Name>,
+    );
+
+    fn run(&mut self, d: Self::SystemData) {
+        let (
+            game_state,
+            viewport,
+            attract,
+            players,
+            difficulty,
+            assists,
+            gamepads,
+            time,
+            leaderboard,
+            tutorial,
+            stats,
+            designer,
+            loadout,
+            profiles,
+            options,
+            names,
+        ) = d;
+        if designer.open {
+            return;
+        }
+        let text = match *game_state {
+            _ if attract.0.is_some() => Cow::Borrowed("Demo flight\nPress any key to play"),
+            GameState::Started => {
+                let count = if *players == Players::Single { 1 } else { 2 };
+                let pairing = gamepads
+                    .players
+                    .iter()
+                    .take(count)
+                    .enumerate()
+                    .map(|(i, pad)| match pad {
+                        Some(_) => format!("Player {}: gamepad paired\n", i + 1),
+                        None => format!("Player {}: press Start on a gamepad to pair it\n", i + 1),
+                    })
+                    .collect::<String>();
+                let profile = profiles.current();
+                let locked = profile
+                    .progress
+                    .locked()
+                    .map(|(ship, needs)| format!("{} ship locked, {} to get it\n", ship, needs))
+                    .collect::<String>();
+                // The tutorial teaches the basic controls, so they are listed only once it's over
+                let basics = if tutorial.current().is_some() {
+                    "Backspace to skip the tutorial\n"
+                } else {
+                    concat!(
+                        "Get the ship into a landing area (red & blue circle), hold still\n",
+                        "The smaller ones are worth more points\n",
+                        "Use arrows to control the thrusters\n",
+                        "Home key to center view onto the ship\n",
+                        "Hold PageDown while still to repair damaged thrusters\n",
+                        "Spacebar to pause & unpause\n",
+                        "+/- to zoom\n",
+                    )
+                };
+                Cow::Owned(format!(
+                    concat!(
+                        "{}",
+                        "Second player uses WASD for thrusters, Q to center view, E to repair\n",
+                        "L to show Lagrange points\n",
+                        "G to show the gravity field\n",
+                        "C to show the coordinate grid\n",
+                        "T to switch the target landing area\n",
+                        "F for free camera (WASD or middle mouse to move)\n",
+                        "Z to zoom out to see everything\n",
+                        "Tab to follow other things with the camera\n",
+                        "Click to set a waypoint, right click to remove it\n",
+                        "F1 to restart level\n",
+                        "F11 or Alt+Enter to toggle fullscreen\n",
+                        "F12 to take a screenshot\n",
+                        "F2 for the options\n",
+                        "F8 to start or stop logging gameplay events\n",
+                        "{}",
+                        "2 to change the players (now {})\n",
+                        "3 to change the difficulty (now {})\n",
+                        "5 to design the ship\n",
+                        "6 to change the ship (now {})\n",
+                        "{}",
+                        "7 to switch the player (now {}, {} wins in {} flights)\n",
+                        "{}",
+                    ),
+                    basics,
+                    SAVE_HELP,
+                    *players,
+                    *difficulty,
+                    loadout.current().name,
+                    locked,
+                    profile.name,
+                    profile.stats.wins,
+                    profile.stats.flights,
+                    pairing,
+                ))
+            }
+            GameState::Paused => Cow::Owned(format!(
+                "Paused\n{}{}",
+                "Arrows, WASD or middle mouse to look around, the mouse wheel to zoom\n",
+                SAVE_HELP,
+            )),
+            GameState::Won => {
+                let scores = leaderboard
+                    .entries
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (name, time))| {
+                        format!("{:>2}. {:<20} {:.2}s\n", i + 1, name, time.as_secs_f32())
+                    })
+                    .collect::<String>();
+                let best = profiles
+                    .current()
+                    .best_times
+                    .get(LEVEL_ID)
+                    .map(|best| format!("Your best time: {:.2}s\n", best.as_secs_f32()))
+                    .unwrap_or_default();
+                Cow::Owned(format!(
+                    "Congratulations, you've won!\n\n{}{}\n{}",
+                    best,
+                    results(&stats, time.0, *difficulty, &names),
+                    scores,
+                ))
+            }
+            GameState::Lost(reason) => {
+                let assist = assists
+                    .offered()
+                    .filter(|_| options.assists)
+                    .map(|assist| format!("Having trouble? Press 4 for an assist: {}\n", assist))
+                    .unwrap_or_default();
+                Cow::Owned(format!(
+                    "You've lost ({})\n\n{}\n{}",
+                    reason,
+                    results(&stats, time.0, *difficulty, &names),
+                    assist,
+                ))
+            }
+            GameState::Running if assists.level > 0 => {
+                let text = format!("Assists: {}", *assists);
+                let pos = viewport.rect.pos + Vector::new(20, 20);
+                let mut gfx = self.gfx.borrow_mut();
+                if let Err(e) = self.renderer.draw(&mut gfx, &viewport, &text, COLOR_ASSISTS, pos) {
+                    error!("Can't write text: {}", e);
+                }
+                return;
+            }
+            GameState::Running => return,
+        };
+        let pos = viewport.rect.pos + Vector::new(200, 200);
+        let mut gfx = self.gfx.borrow_mut();
+        if let Err(e) = self.renderer.draw(&mut gfx, &viewport, &text, Color::WHITE, pos) {
+            error!("Can't write text: {}", e);
+        }
+    }
+}
+
+/// The save menu needs the files and the serialization.
+#[cfg(all(feature = "serialize", not(target_arch = "wasm32")))]
+const SAVE_HELP: &str = "F5 to save the level in the middle, F6 to load a saved one\n";
+#[cfg(not(all(feature = "serialize", not(target_arch = "wasm32"))))]
+const SAVE_HELP: &str = "";
+
+const ORBIT_SEGMENTS: usize = 90;
+/// Don't draw the open orbits all the way to infinity.
+const ORBIT_MAX_DIST: f32 = 5_000.0;
+
+const COLOR_ORBIT: Color = Color {
+    r: 0.3,
+    g: 0.8,
+    b: 0.3,
+    a: 0.5,
+};
+
+struct DrawOrbits<'a> {
+    gfx: &'a RefCell<Graphics>,
+}
+
+impl<'a> System<'a> for DrawOrbits<'_> {
+    type SystemData = (
+        ReadStorage<'a, Orbit>,
+        ReadStorage<'a, Position>,
+    );
+
+    fn run(&mut self, (orbits, positions): Self::SystemData) {
+        let mut gfx = self.gfx.borrow_mut();
+
+        trace!("Drawing orbits");
+        for orbit in orbits.join() {
+            let center = match positions.get(orbit.body) {
+                Some(pos) => pos.0,
+                None => continue,
+            };
+            let step = 360.0 / ORBIT_SEGMENTS as f32;
+            // Go from the apoapsis around, so the open orbits are split at the far end.
+            let points = (0..=ORBIT_SEGMENTS)
+                .map(|i| -180.0 + step * i as f32)
+                .filter_map(|angle| orbit.point(angle))
+                .filter(|point| point.len() <= ORBIT_MAX_DIST)
+                .map(|point| center + point)
+                .collect::<Vec<_>>();
+            if points.len() >= 2 {
+                gfx.stroke_path(&points, COLOR_ORBIT);
+            }
+        }
+    }
+}
+
+struct DrawOrbitInfo<'a> {
+    gfx: &'a RefCell<Graphics>,
+    renderer: TextRenderer<'a>,
+}
+
+impl<'a> System<'a> for DrawOrbitInfo<'_> {
+    type SystemData = (
+        ReadStorage<'a, Ship>,
+        ReadStorage<'a, Orbit>,
+        ReadExpect<'a, Viewport>,
+    );
+
+    fn run(&mut self, (ships, orbits, viewport): Self::SystemData) {
+        let mut gfx = self.gfx.borrow_mut();
+        let mut pos = viewport.rect.pos + Vector::new(20, 30);
+        for (_, orbit) in (&ships, &orbits).join() {
+            let apoapsis = orbit.apoapsis
+                .map(|a| format!("{:.0}", a))
+                .unwrap_or_else(|| "escape".to_owned());
+            let text = format!(
+                "Apoapsis: {}\nPeriapsis: {:.0}\nEccentricity: {:.2}\n",
+                apoapsis, orbit.periapsis, orbit.eccentricity,
+            );
+            match self.renderer.draw(&mut gfx, &viewport, &text, Color::WHITE, pos) {
+                Ok(size) => pos.y += size.y,
+                Err(e) => error!("Can't write text: {}", e),
+            }
+        }
+    }
+}
+
+const COLOR_TARGET: Color = Color {
+    r: 0.3,
+    g: 0.7,
+    b: 1.0,
+    a: 0.5,
+};
+
+/// Line from the ship to the target landing area, with distance and closing speed.
+struct DrawTarget<'a> {
+    gfx: &'a RefCell<Graphics>,
+    renderer: TextRenderer<'a>,
+}
+
+impl<'a> System<'a> for DrawTarget<'_> {
+    type SystemData = (
+        Read<'a, TargetPad>,
+        ReadExpect<'a, DifficultyTimeMod>,
+        ReadExpect<'a, Viewport>,
+        ReadStorage<'a, Ship>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, Speed>,
+    );
+
+    fn run(&mut self, (target, difficulty, viewport, ships, positions, speeds): Self::SystemData) {
+        let pad = match target.0 {
+            Some(pad) => pad,
+            None => return,
+        };
+        let pad_pos = match positions.get(pad) {
+            Some(pos) => pos.0,
+            None => return,
+        };
+        let pad_speed = speeds.get(pad).map(|s| s.0).unwrap_or(Vector::ZERO);
+        let mut gfx = self.gfx.borrow_mut();
+        let mut text_pos = viewport.rect.pos + Vector::new(20.0, viewport.rect.size.y - 40.0);
+
+        for (_, pos, speed) in (&ships, &positions, &speeds).join() {
+            gfx.stroke_path(&[pos.0, pad_pos], COLOR_TARGET);
+
+            let offset = pad_pos - pos.0;
+            let distance = offset.len();
+            let rel_speed = speed.0 - pad_speed;
+            // How fast the distance shrinks, per second of real time.
+            let closing = if distance > 0.0 {
+                (offset.x * rel_speed.x + offset.y * rel_speed.y) / distance * difficulty.0
+            } else {
+                0.0
+            };
+            let text = format!("Target: {:.0} away, closing at {:.1}\n", distance, closing);
+            match self.renderer.draw(&mut gfx, &viewport, &text, Color::WHITE, text_pos) {
+                Ok(size) => text_pos.y -= size.y,
+                Err(e) => error!("Can't write text: {}", e),
+            }
+        }
+    }
+}
+
+const COLOR_WAYPOINT: Color = Color {
+    r: 1.0,
+    g: 0.5,
+    b: 1.0,
+    a: 0.8,
+};
+
+/// Size of the waypoint cross and the arrows pointing to it, in screen pixels.
+const WAYPOINT_SIZE: f32 = 10.0;
+/// How far from the ships the arrows pointing to the waypoint are, in screen pixels.
+const WAYPOINT_ARROW_DISTANCE: f32 = 30.0;
+
+/// The waypoint, with an arrow by each ship pointing to it.
+struct DrawWaypoint<'a> {
+    gfx: &'a RefCell<Graphics>,
+    renderer: TextRenderer<'a>,
+}
+
+impl<'a> System<'a> for DrawWaypoint<'_> {
+    type SystemData = (
+        Read<'a, Waypoint>,
+        ReadExpect<'a, Viewport>,
+        ReadStorage<'a, Ship>,
+        ReadStorage<'a, Position>,
+    );
+
+    fn run(&mut self, (waypoint, viewport, ships, positions): Self::SystemData) {
+        let waypoint = match waypoint.0 {
+            Some(waypoint) => waypoint,
+            None => return,
+        };
+        let mut gfx = self.gfx.borrow_mut();
+        let size = WAYPOINT_SIZE / viewport.zoom;
+        let cross = Vector::new(size, size);
+        gfx.stroke_path(&[waypoint - cross, waypoint + cross], COLOR_WAYPOINT);
+        let cross = Vector::new(size, -size);
+        gfx.stroke_path(&[waypoint - cross, waypoint + cross], COLOR_WAYPOINT);
+        let mut closest = None::<f32>;
+        for (_, pos) in (&ships, &positions).join() {
+            let offset = waypoint - pos.0;
+            let distance = offset.len();
+            closest = Some(closest.map_or(distance, |closest| closest.min(distance)));
+            let arrow_distance = WAYPOINT_ARROW_DISTANCE / viewport.zoom;
+            if distance <= arrow_distance + size {
+                continue;
+            }
+            let dir = offset.normalize();
+            let tip = pos.0 + dir * (arrow_distance + size);
+            let base = pos.0 + dir * arrow_distance;
+            let side = Vector::new(-dir.y, dir.x) * (size / 2.0);
+            gfx.stroke_path(&[base + side, tip, base - side], COLOR_WAYPOINT);
+        }
+        if let Some(closest) = closest {
+            let text = format!("{:.0} away", closest);
+            let pos = waypoint + Vector::new(size * 1.5, -size);
+            if let Err(e) = self.renderer.draw(&mut gfx, &viewport, &text, COLOR_WAYPOINT, pos) {
+                error!("Can't write text: {}", e);
+            }
+        }
+    }
+}
+
+const COLOR_TUTORIAL: Color = Color {
+    r: 0.4,
+    g: 1.0,
+    b: 0.6,
+    a: 1.0,
+};
+
+const COLOR_HINT: Color = Color {
+    r: 1.0,
+    g: 0.8,
+    b: 0.3,
+    a: 1.0,
+};
+
+/// Radius of the circles around the things the tutorial talks about.
+const TUTORIAL_HIGHLIGHT: f32 = 40.0;
+
+/// Where the hints go, above the tutorial.
+const HINT_OFFSET: f32 = 120.0;
+
+/// The current step of the tutorial, with the things it talks about circled, and the current hint.
+struct DrawTutorial<'a> {
+    gfx: &'a RefCell<Graphics>,
+    renderer: TextRenderer<'a>,
+}
+
+impl<'a> System<'a> for DrawTutorial<'_> {
+    type SystemData = (
+        Read<'a, Tutorial>,
+        Read<'a, Hints>,
+        Read<'a, LevelTime>,
+        Read<'a, Attract>,
+        Read<'a, Replay>,
+        ReadExpect<'a, Viewport>,
+        ReadStorage<'a, Ship>,
+        ReadStorage<'a, Landing>,
+        ReadStorage<'a, Position>,
+    );
+
+    fn run(&mut self, d: Self::SystemData) {
+        let (tutorial, hints, time, attract, replay, viewport, ships, landings, positions) = d;
+        if attract.0.is_some() || replay.mode != ReplayMode::Recording {
+            return;
+        }
+        let mut gfx = self.gfx.borrow_mut();
+        let bottom = viewport.rect.pos + Vector::new(200.0, viewport.rect.size.y);
+        if let Some(hint) = hints.current(time.0) {
+            let pos = bottom - Vector::new(0.0, HINT_OFFSET);
+            if let Err(e) = self.renderer.draw(&mut gfx, &viewport, hint.text(), COLOR_HINT, pos) {
+                error!("Can't write text: {}", e);
+            }
+        }
+        let step = match tutorial.current() {
+            Some(step) => step,
+            None => return,
+        };
+        let highlighted = match step.highlight {
+            Highlight::Nothing => Vec::new(),
+            Highlight::Ships => (&ships, &positions).join().map(|(_, pos)| pos.0).collect(),
+            Highlight::Landings => (&landings, &positions).join().map(|(_, pos)| pos.0).collect(),
+        };
+        for pos in highlighted {
+            gfx.stroke_circle(&Circle::new(pos, TUTORIAL_HIGHLIGHT), COLOR_TUTORIAL);
+        }
+        // Room for all the lines and one empty below them, however big the text is
+        let lines = step.text.lines().count() + 1;
+        let pos = bottom - Vector::new(0.0, lines as f32 * self.renderer.line_height(&viewport));
+        if let Err(e) = self.renderer.draw(&mut gfx, &viewport, step.text, COLOR_TUTORIAL, pos) {
+            error!("Can't write text: {}", e);
+        }
+    }
+}
+
+const COLOR_TOUCH: Color = Color {
+    r: 1.0,
+    g: 1.0,
+    b: 1.0,
+    a: 0.3,
+};
+
+/// The on-screen stick and buttons, the pressed buttons filled.
+struct DrawTouchControls<'a> {
+    gfx: &'a RefCell<Graphics>,
+    renderer: TextRenderer<'a>,
+}
+
+impl<'a> System<'a> for DrawTouchControls<'_> {
+    type SystemData = (Read<'a, Touches>, Read<'a, Options>, ReadExpect<'a, Viewport>);
+
+    fn run(&mut self, (touches, options, viewport): Self::SystemData) {
+        if !options.touch {
+            return;
+        }
+        let mut gfx = self.gfx.borrow_mut();
+        let stick = stick_area(&viewport);
+        gfx.stroke_circle(&stick, COLOR_TOUCH);
+        let knob = stick.pos + touches.stick_push * stick.radius;
+        gfx.fill_circle(&Circle::new(knob, stick.radius / 3.0), COLOR_TOUCH);
+        let view = viewport.rect;
+        for (i, (label, _, button)) in TOUCH_BUTTONS.iter().enumerate() {
+            let pos = Vector::new(button.pos.x * view.size.x, button.pos.y * view.size.y);
+            let size = Vector::new(button.size.x * view.size.x, button.size.y * view.size.y);
+            let rect = Rectangle::new(view.pos + pos, size);
+            if touches.pressed.values().any(|pressed| *pressed == i) {
+                gfx.fill_rect(&rect, COLOR_TOUCH);
+            }
+            gfx.stroke_rect(&rect, COLOR_TOUCH);
+            let text_pos = rect.pos + Vector::new(10.0, 20.0) / viewport.zoom;
+            if let Err(e) = self.renderer.draw(&mut gfx, &viewport, label, Color::WHITE, text_pos) {
+                error!("Can't write text: {}", e);
+            }
+        }
+    }
+}
+
+/// Drawing the world into the window.
+pub struct RenderPlugin<'a> {
+    gfx: &'a RefCell<Graphics>,
+    font: &'a VectorFont,
+}
+
+impl<'a> RenderPlugin<'a> {
+    pub fn new(gfx: &'a RefCell<Graphics>, font: &'a VectorFont) -> Self {
+        RenderPlugin { gfx, font }
+    }
+}
+
+impl<'a> Plugin<'a, 'a> for RenderPlugin<'a> {
+    fn systems(&mut self, builder: DispatcherBuilder<'a, 'a>) -> DispatcherBuilder<'a, 'a> {
+        let gfx = self.gfx;
+        let font = self.font;
+        let builder = builder
+            .with_thread_local(SetViewport { gfx })
+            .with_thread_local(DrawGrid {
+                gfx,
+                renderer: TextRenderer::new(font, 12.0),
+            })
+            .with_thread_local(DrawGravityField { gfx })
+            .with_thread_local(DrawZones { gfx })
+            .with_thread_local(DrawStars { gfx })
+            .with_thread_local(DrawComets { gfx })
+            .with_thread_local(DrawPlanets { gfx })
+            .with_thread_local(DrawOrbits { gfx })
+            .with_thread_local(DrawLagrange {
+                gfx,
+                renderer: TextRenderer::new(font, 12.0),
+            })
+            .with_thread_local(DrawShips { gfx })
+            .with_thread_local(DrawLandings { gfx })
+            .with_thread_local(DrawRepairs {
+                gfx,
+                renderer: TextRenderer::new(font, 12.0),
+            })
+            .with_thread_local(DrawPowerUps { gfx })
+            .with_thread_local(DrawFuelStations { gfx })
+            .with_thread_local(DrawLabels {
+                gfx,
+                renderer: TextRenderer::new(font, 12.0),
+            })
+            .with_thread_local(DrawTarget {
+                gfx,
+                renderer: TextRenderer::new(font, 16.0),
+            })
+            .with_thread_local(DrawWaypoint {
+                gfx,
+                renderer: TextRenderer::new(font, 12.0),
+            })
+            .with_thread_local(DrawOrbitInfo {
+                gfx,
+                renderer: TextRenderer::new(font, 16.0),
+            })
+            .with_thread_local(DrawPip { gfx })
+            .with_thread_local(DrawInterference {
+                gfx,
+                renderer: TextRenderer::new(font, 24.0),
+            })
+            .with_thread_local(DrawGMeter {
+                gfx,
+                renderer: TextRenderer::new(font, 16.0),
+            })
+            .with_thread_local(DrawEffects {
+                gfx,
+                renderer: TextRenderer::new(font, 16.0),
+            })
+            .with_thread_local(DrawDesigner {
+                gfx,
+                renderer: TextRenderer::new(font, 24.0),
+            })
+            .with_thread_local(DrawState {
+                gfx,
+                renderer: TextRenderer::new(font, 24.0),
+            })
+            .with_thread_local(DrawTutorial {
+                gfx,
+                renderer: TextRenderer::new(font, 24.0),
+            })
+            .with_thread_local(DrawTouchControls {
+                gfx,
+                renderer: TextRenderer::new(font, 16.0),
+            })
+            .with_thread_local(DrawOptions {
+                gfx,
+                renderer: TextRenderer::new(font, 20.0),
+            });
+        #[cfg(all(feature = "serialize", not(target_arch = "wasm32")))]
+        let builder = builder.with_thread_local(DrawSaveMenu {
+            gfx,
+            renderer: TextRenderer::new(font, 24.0),
+        });
+        builder
+    }
+}
@@ -0,0 +1,190 @@
+//! Repairing the damaged thrusters in flight.
+
+use std::time::Duration;
+
+use specs::prelude::*;
+use specs_hierarchy::Hierarchy;
+
+use log::info;
+
+use crate::components::{Condition, Landed, Repair, Ship, Speed, Thruster};
+use crate::input::Keys;
+use crate::physics::{ship_thrusters, FrameDuration};
+use crate::plugin::Plugin;
+use crate::state::Rules;
+
+/// How long it takes to make a thruster one step better.
+pub const REPAIR_TIME: Duration = Duration::from_secs(3);
+
+#[derive(SystemData)]
+struct RepairThrustersData<'a> {
+    entities: Entities<'a>,
+    ships: WriteStorage<'a, Ship>,
+    speeds: ReadStorage<'a, Speed>,
+    landed: ReadStorage<'a, Landed>,
+    thrusters: WriteStorage<'a, Thruster>,
+    thruster_hierarchy: ReadExpect<'a, Hierarchy<Thruster>>,
+    repairs: WriteStorage<'a, Repair>,
+    keys: Read<'a, Keys>,
+    rules: Read<'a, Rules>,
+    frame_duration: Read<'a, FrameDuration>,
+}
+
+/// Repairs the ships whose pilots hold the repair key.
+///
+/// The ship needs to sit on a planet or hold still (as slow as it may touch down) and to have
+/// spare parts left. Each `REPAIR_TIME` of holding the key fixes the worst thruster one step,
+/// using a spare part.
+struct RepairThrusters;
+
+impl<'a> System<'a> for RepairThrusters {
+    type SystemData = RepairThrustersData<'a>;
+
+    fn run(&mut self, d: Self::SystemData) {
+        let RepairThrustersData {
+            entities,
+            mut ships,
+            speeds,
+            landed,
+            mut thrusters,
+            thruster_hierarchy,
+            mut repairs,
+            keys,
+            rules,
+            frame_duration,
+        } = d;
+        for (ent, ship) in (&entities, &mut ships).join() {
+            let speed = speeds
+                .get(ent)
+                .map(|speed| speed.0.len())
+                .unwrap_or_default();
+            let still = landed.contains(ent) || speed <= rules.touchdown_speed;
+            // Dead ones first
+            let worst = ship_thrusters(&thruster_hierarchy, &thrusters, ent)
+                .into_iter()
+                .map(|(id, thruster)| (id, thruster.condition))
+                .filter(|(_, condition)| *condition != Condition::Intact)
+                .max_by_key(|(_, condition)| *condition)
+                .map(|(id, _)| id);
+            let repairing = still && ship.spare_parts > 0 && keys.contains(&ship.keys.repair);
+            let worst = match worst {
+                Some(worst) if repairing => worst,
+                _ => {
+                    repairs.remove(ent);
+                    continue;
+                }
+            };
+            let repair = repairs
+                .entry(ent)
+                .expect("Repairing a dead ship")
+                .or_insert_with(Repair::default);
+            repair.progress += frame_duration.0;
+            if repair.progress >= REPAIR_TIME {
+                repair.progress = Duration::default();
+                ship.spare_parts -= 1;
+                let thruster = thrusters.get_mut(worst).expect("Missing thruster");
+                thruster.condition = thruster.condition.better();
+                info!(
+                    "Thruster {:?} of ship {:?} repaired to {:?}",
+                    thruster.key, ent, thruster.condition,
+                );
+            }
+        }
+    }
+}
+
+/// The repairs of damaged thrusters.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RepairPlugin;
+
+impl<'a, 'b> Plugin<'a, 'b> for RepairPlugin {
+    fn physics(&mut self, builder: DispatcherBuilder<'a, 'b>) -> DispatcherBuilder<'a, 'b> {
+        builder.with(RepairThrusters, "repair-thrusters", &[])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use quicksilver::geom::Vector;
+
+    use super::*;
+    use crate::input::PLAYER_KEYS;
+    use crate::test_support::{TestWorld, FRAME};
+
+    fn break_all(world: &mut TestWorld) {
+        for thruster in (&mut world.world.write_storage::<Thruster>()).join() {
+            thruster.condition = Condition::Dead;
+        }
+    }
+
+    fn conditions(world: &TestWorld) -> Vec<Condition> {
+        let thrusters = world.world.read_storage::<Thruster>();
+        thrusters
+            .join()
+            .map(|thruster| thruster.condition)
+            .collect()
+    }
+
+    fn spare_parts(world: &TestWorld, ship: Entity) -> u32 {
+        world
+            .world
+            .read_storage::<Ship>()
+            .get(ship)
+            .unwrap()
+            .spare_parts
+    }
+
+    #[test]
+    fn holding_still_repairs_one_step() {
+        let mut world = TestWorld::new();
+        let ship = world.ship(Vector::ZERO);
+        break_all(&mut world);
+        let parts = spare_parts(&world, ship);
+        world
+            .world
+            .fetch_mut::<Keys>()
+            .insert(PLAYER_KEYS[0].repair);
+        world.step((REPAIR_TIME.as_nanos() / FRAME.as_nanos()) as usize);
+        let conditions = conditions(&world);
+        let repaired = conditions
+            .iter()
+            .filter(|c| **c == Condition::Degraded)
+            .count();
+        assert_eq!(repaired, 1);
+        assert_eq!(spare_parts(&world, ship), parts - 1);
+    }
+
+    #[test]
+    fn no_repairs_in_flight_or_without_parts() {
+        let mut world = TestWorld::new();
+        let ship = world.ship(Vector::ZERO);
+        world
+            .world
+            .write_storage::<Speed>()
+            .insert(ship, Speed(Vector::new(100, 0)))
+            .unwrap();
+        break_all(&mut world);
+        world
+            .world
+            .fetch_mut::<Keys>()
+            .insert(PLAYER_KEYS[0].repair);
+        world.step(1);
+        assert!(!world.world.read_storage::<Repair>().contains(ship));
+
+        let mut world = TestWorld::new();
+        let ship = world.ship(Vector::ZERO);
+        world
+            .world
+            .write_storage::<Ship>()
+            .get_mut(ship)
+            .unwrap()
+            .spare_parts = 0;
+        break_all(&mut world);
+        world
+            .world
+            .fetch_mut::<Keys>()
+            .insert(PLAYER_KEYS[0].repair);
+        world.step((REPAIR_TIME.as_nanos() / FRAME.as_nanos()) as usize);
+        assert!(conditions(&world).iter().all(|c| *c == Condition::Dead));
+    }
+}
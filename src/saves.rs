@@ -0,0 +1,360 @@
+//! Save slots, for leaving a level in the middle and coming back to it later.
+//!
+//! Each slot is a file in the `saves` directory. A few lines tell what run it holds, the rest is
+//! the world, stored the same way as a level file:
+//!
+//! ```text
+//! thrust-save 1
+//! level default
+//! player Anna
+//! players Single
+//! difficulty Normal
+//! assists 0
+//! play-time 42.5
+//! saved 1760000000
+//! world
+//! thrust-level 9
+//! ship Courier
+//! ...
+//! ```
+
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::fs;
+use std::io::{ErrorKind, Write as _};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use quicksilver::lifecycle::Key;
+use specs::prelude::*;
+
+use log::{error, info};
+
+use crate::input::Replay;
+use crate::physics::DifficultyTimeMod;
+use crate::profile::Profiles;
+use crate::render::FitView;
+use crate::serialize::{read_level, write_level};
+use crate::state::{
+    Assists, Difficulty, GameState, Leaderboard, LevelTime, Players, RunStats, LEVEL_ID,
+};
+use crate::tutorial::Hints;
+
+const SAVE_HEADER: &str = "thrust-save 1";
+
+/// Separates the description of the run from the world.
+const WORLD_LINE: &str = "world";
+
+/// Where the save slots are kept.
+pub const SAVE_DIR: &str = "saves";
+
+/// How many save slots there are.
+pub const SLOTS: usize = 5;
+
+/// What run a slot holds.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SlotInfo {
+    /// The level id.
+    pub level: String,
+    /// The profile that saved it.
+    pub player: String,
+    pub players: Players,
+    pub difficulty: Difficulty,
+    pub assists: u32,
+    /// How long the level was played, pauses excluded.
+    pub play_time: Duration,
+    pub saved: SystemTime,
+}
+
+impl SlotInfo {
+    /// Describes the run in the world.
+    fn new(world: &World) -> Self {
+        SlotInfo {
+            level: LEVEL_ID.to_owned(),
+            player: world.fetch::<Profiles>().current().name.clone(),
+            players: *world.fetch::<Players>(),
+            difficulty: *world.fetch::<Difficulty>(),
+            assists: world.fetch::<Assists>().level,
+            play_time: world.fetch::<LevelTime>().0,
+            saved: SystemTime::now(),
+        }
+    }
+
+    /// Parses the beginning of a save.
+    ///
+    /// Returns the rest, the stored world.
+    fn parse(content: &str) -> Result<(Self, &str), Box<dyn Error>> {
+        let separator = format!("\n{}\n", WORLD_LINE);
+        let mut parts = content.splitn(2, separator.as_str());
+        let mut lines = parts.next().unwrap_or_default().lines();
+        let world = parts.next().ok_or("Missing world")?;
+        if lines.next() != Some(SAVE_HEADER) {
+            return Err("Not a save".into());
+        }
+        let mut info = SlotInfo {
+            level: String::new(),
+            player: String::new(),
+            players: Players::default(),
+            difficulty: Difficulty::default(),
+            assists: 0,
+            play_time: Duration::default(),
+            saved: UNIX_EPOCH,
+        };
+        for line in lines {
+            let (key, value) = line.split_at(line.find(' ').ok_or("Missing value")?);
+            let value = value.trim();
+            match key {
+                "level" => info.level = value.to_owned(),
+                "player" => info.player = value.to_owned(),
+                "players" => info.players = Players::parse(value).ok_or("Unknown players")?,
+                "difficulty" => {
+                    info.difficulty = Difficulty::parse(value).ok_or("Unknown difficulty")?;
+                }
+                "assists" => info.assists = value.parse()?,
+                "play-time" => info.play_time = Duration::try_from_secs_f32(value.parse()?)?,
+                "saved" => {
+                    let since = Duration::from_secs(value.parse()?);
+                    info.saved = UNIX_EPOCH.checked_add(since).ok_or("Saved too late")?;
+                }
+                _ => return Err(format!("Unknown line {}", line).into()),
+            }
+        }
+        if info.level.is_empty() {
+            return Err("Missing level".into());
+        }
+        Ok((info, world))
+    }
+}
+
+impl Display for SlotInfo {
+    /// Writes the beginning of a save file, up to the world.
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        let saved = self.saved.duration_since(UNIX_EPOCH).unwrap_or_default();
+        writeln!(fmt, "{}", SAVE_HEADER)?;
+        writeln!(fmt, "level {}", self.level)?;
+        writeln!(fmt, "player {}", self.player)?;
+        writeln!(fmt, "players {:?}", self.players)?;
+        writeln!(fmt, "difficulty {:?}", self.difficulty)?;
+        writeln!(fmt, "assists {}", self.assists)?;
+        writeln!(fmt, "play-time {}", self.play_time.as_secs_f32())?;
+        writeln!(fmt, "saved {}", saved.as_secs())?;
+        writeln!(fmt, "{}", WORLD_LINE)
+    }
+}
+
+/// Writes the running level as a save.
+fn write_save(world: &mut World) -> Result<(SlotInfo, Vec<u8>), Box<dyn Error>> {
+    let info = SlotInfo::new(world);
+    let mut out = Vec::new();
+    write!(out, "{}", info)?;
+    write_level(world, &mut out)?;
+    Ok((info, out))
+}
+
+/// Replaces the running level by the saved one.
+///
+/// The level is left paused, so the player has a moment to look around before going on.
+fn read_save(world: &mut World, content: &str) -> Result<SlotInfo, Box<dyn Error>> {
+    let (info, level) = SlotInfo::parse(content)?;
+    if info.level != LEVEL_ID {
+        return Err(format!("Unknown level {}", info.level).into());
+    }
+    read_level(world, level)?;
+    *world.fetch_mut::<Players>() = info.players;
+    world.insert(info.difficulty);
+    let assists = Assists {
+        level: info.assists,
+        ..Assists::default()
+    };
+    let rules = assists.apply(info.difficulty.rules());
+    world.insert(assists);
+    world.insert(rules);
+    world.insert(DifficultyTimeMod(rules.time_mod));
+    world.fetch_mut::<Replay>().resume();
+    *world.fetch_mut::<GameState>() = GameState::Paused;
+    world.fetch_mut::<FitView>().requested = true;
+    world.fetch_mut::<LevelTime>().0 = info.play_time;
+    world.fetch_mut::<Leaderboard>().entries.clear();
+    world.insert(RunStats::default());
+    world.insert(Hints::default());
+    Ok(info)
+}
+
+fn slot_path(dir: &Path, slot: usize) -> PathBuf {
+    dir.join(format!("slot-{}.save", slot + 1))
+}
+
+/// What each of the slots holds, `None` for the empty ones.
+pub fn slots(dir: &Path) -> Vec<Option<SlotInfo>> {
+    (0..SLOTS)
+        .map(|slot| {
+            let path = slot_path(dir, slot);
+            let info = match fs::read_to_string(&path) {
+                Ok(content) => SlotInfo::parse(&content).map(|(info, _)| info),
+                Err(e) if e.kind() == ErrorKind::NotFound => return None,
+                Err(e) => Err(e.into()),
+            };
+            info.map_err(|e| error!("Broken save {}: {}", path.display(), e))
+                .ok()
+        })
+        .collect()
+}
+
+/// Stores the running level into the slot, replacing whatever was there.
+pub fn save(world: &mut World, dir: &Path, slot: usize) -> Result<SlotInfo, Box<dyn Error>> {
+    let (info, content) = write_save(world)?;
+    fs::create_dir_all(dir)?;
+    fs::write(slot_path(dir, slot), content)?;
+    Ok(info)
+}
+
+/// Continues the level stored in the slot.
+pub fn load(world: &mut World, dir: &Path, slot: usize) -> Result<SlotInfo, Box<dyn Error>> {
+    let path = slot_path(dir, slot);
+    read_save(world, &fs::read_to_string(&path)?)
+        .map_err(|e| format!("{}: {}", path.display(), e).into())
+}
+
+/// What the save menu is for.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MenuMode {
+    Save,
+    Load,
+}
+
+/// What a key did to the save menu.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MenuKey {
+    /// Not a key of the menu, the caller may use it for something else.
+    Ignored,
+    Handled,
+    /// The slot to save into or load from, the menu is closed now.
+    Chosen(MenuMode, usize),
+}
+
+/// The menu listing the save slots, shown over the level.
+#[derive(Clone, Debug, Default)]
+pub struct SaveMenu {
+    pub mode: Option<MenuMode>,
+    pub selected: usize,
+    /// What the slots held when the menu opened.
+    pub slots: Vec<Option<SlotInfo>>,
+}
+
+impl SaveMenu {
+    pub fn open(&mut self, mode: MenuMode, dir: &Path) {
+        info!("Opening the {:?} menu", mode);
+        self.mode = Some(mode);
+        self.selected = 0;
+        self.slots = slots(dir);
+    }
+
+    /// Handles a released key.
+    pub fn key(&mut self, key: Key) -> MenuKey {
+        let mode = match self.mode {
+            Some(mode) => mode,
+            None => return MenuKey::Ignored,
+        };
+        match key {
+            Key::Up => self.selected = (self.selected + SLOTS - 1) % SLOTS,
+            Key::Down => self.selected = (self.selected + 1) % SLOTS,
+            Key::Return => {
+                let empty = self
+                    .slots
+                    .get(self.selected)
+                    .and_then(Option::as_ref)
+                    .is_none();
+                // Nothing to load from an empty slot
+                if mode == MenuMode::Save || !empty {
+                    self.mode = None;
+                    return MenuKey::Chosen(mode, self.selected);
+                }
+            }
+            Key::Back | Key::F5 | Key::F6 => self.mode = None,
+            // The level doesn't go on under the menu
+            Key::Space | Key::Pause => (),
+            _ => return MenuKey::Ignored,
+        }
+        MenuKey::Handled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::{Ship, Thruster};
+    use crate::Game;
+
+    fn count<C: Component>(world: &World) -> usize {
+        world.read_storage::<C>().join().count()
+    }
+
+    #[test]
+    fn save_round_trip() {
+        let mut original = Game::new();
+        original.world_mut().insert(Difficulty::Hard);
+        *original.world_mut().fetch_mut::<GameState>() = GameState::Running;
+        for _ in 0..10 {
+            original.step();
+        }
+        let (info, content) = write_save(original.world_mut()).unwrap();
+        assert_eq!(info.difficulty, Difficulty::Hard);
+        assert!(info.play_time > Duration::default());
+
+        let mut game = Game::new();
+        let loaded = read_save(game.world_mut(), std::str::from_utf8(&content).unwrap()).unwrap();
+        assert_eq!(loaded.level, LEVEL_ID);
+        assert_eq!(loaded.player, info.player);
+        assert!((loaded.play_time.as_secs_f32() - info.play_time.as_secs_f32()).abs() < 0.001);
+        let world = game.world();
+        assert_eq!(*world.fetch::<GameState>(), GameState::Paused);
+        assert_eq!(*world.fetch::<Difficulty>(), Difficulty::Hard);
+        assert_eq!(world.fetch::<LevelTime>().0, loaded.play_time);
+        assert_eq!(count::<Ship>(original.world()), count::<Ship>(world));
+        assert_eq!(
+            count::<Thruster>(original.world()),
+            count::<Thruster>(world)
+        );
+    }
+
+    #[test]
+    fn not_a_save() {
+        let mut game = Game::new();
+        assert!(read_save(game.world_mut(), "thrust-level 8\n").is_err());
+        assert!(read_save(game.world_mut(), "thrust-save 1\nlevel default\n").is_err());
+        for bad in &["play-time -1", "play-time NaN", "saved 18446744073709551615"] {
+            let content = format!("thrust-save 1\nlevel default\n{}\nworld\n", bad);
+            assert!(SlotInfo::parse(&content).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn menu_keys() {
+        let mut menu = SaveMenu::default();
+        assert_eq!(menu.key(Key::Return), MenuKey::Ignored);
+        menu.mode = Some(MenuMode::Load);
+        menu.slots = vec![None; SLOTS];
+        menu.slots[SLOTS - 1] = Some(
+            SlotInfo::parse("thrust-save 1\nlevel x\nworld\n")
+                .unwrap()
+                .0,
+        );
+        // Can't load an empty slot
+        assert_eq!(menu.key(Key::Return), MenuKey::Handled);
+        assert_eq!(menu.mode, Some(MenuMode::Load));
+        assert_eq!(menu.key(Key::A), MenuKey::Ignored);
+        assert_eq!(menu.key(Key::Up), MenuKey::Handled);
+        assert_eq!(
+            menu.key(Key::Return),
+            MenuKey::Chosen(MenuMode::Load, SLOTS - 1)
+        );
+        assert_eq!(menu.mode, None);
+
+        menu.mode = Some(MenuMode::Save);
+        assert_eq!(menu.key(Key::Down), MenuKey::Handled);
+        assert_eq!(menu.key(Key::Return), MenuKey::Chosen(MenuMode::Save, 0));
+        menu.mode = Some(MenuMode::Save);
+        assert_eq!(menu.key(Key::Back), MenuKey::Handled);
+        assert_eq!(menu.mode, None);
+    }
+}
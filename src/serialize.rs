@@ -0,0 +1,446 @@
+//! Serialization of the components and resources.
+//!
+//! Most of the types simply derive serde's traits (when the `serialize` feature is on). This holds
+//! the glue for the ones that can't: the types from quicksilver and the components pointing to
+//! other entities. The latter implement specs' `ConvertSaveload`, so the entities are replaced by
+//! markers.
+//!
+//! It also reads and writes the level files. These hold the entities of a level, so a level can be
+//! built by playing with the world and exporting it. After the header comes a line naming the ship
+//! design the level is flown in. As specs can (de)serialize at most 16 components at once, the
+//! rest of the file holds two JSON values, each with a part of the components.
+
+use std::error::Error;
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs::{self, File};
+#[cfg(not(target_arch = "wasm32"))]
+use std::io::BufWriter;
+use std::io::{self, Write as _};
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use quicksilver::geom::Vector;
+use quicksilver::graphics::Color;
+use quicksilver::lifecycle::Key;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use specs::error::NoError;
+use specs::prelude::*;
+use specs::saveload::{
+    ConvertSaveload, DeserializeComponents, MarkerAllocator, SerializeComponents, SimpleMarker,
+    SimpleMarkerAllocator,
+};
+
+use crate::components::{
+    AtLagrange, Comet, Condition, FuelStation, Hull, Lagrange, LagrangePoint, Landed, Landing,
+    Mass, Name, Orbit, Planet, Position, PowerUp, Rotation, RotationSpeed, Ship, Speed, Star,
+    Thruster, Zone, ZoneEffect,
+};
+use crate::state::LevelShip;
+
+/// The first line of every level file.
+const LEVEL_HEADER: &str = "thrust-level 9";
+
+/// Starts the line with the name of the `LevelShip`.
+const SHIP_PREFIX: &str = "ship";
+
+/// Where the exported levels go.
+#[cfg(not(target_arch = "wasm32"))]
+pub const LEVEL_DIR: &str = "levels";
+
+#[derive(Deserialize, Serialize)]
+#[serde(remote = "Vector")]
+pub struct VectorDef {
+    pub x: f32,
+    pub y: f32,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(remote = "Color")]
+pub struct ColorDef {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+/// Lists of vectors, as lists of `[x, y]` pairs.
+pub mod vectors {
+    use quicksilver::geom::Vector;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(points: &[Vector], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(points.iter().map(|p| [p.x, p.y]))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<Vector>, D::Error> {
+        let points = Vec::<[f32; 2]>::deserialize(deserializer)?;
+        Ok(points.into_iter().map(|[x, y]| Vector::new(x, y)).collect())
+    }
+}
+
+/// Keys go by their names.
+///
+/// Only the keys a replay can hold are supported.
+pub mod key {
+    use quicksilver::lifecycle::Key;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use crate::input::REPLAY_KEYS;
+
+    pub fn serialize<S: Serializer>(key: &Key, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{:?}", key))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Key, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        REPLAY_KEYS
+            .iter()
+            .find(|key| format!("{:?}", key) == name)
+            .copied()
+            .ok_or_else(|| D::Error::custom(format!("Unknown key {}", name)))
+    }
+}
+
+/// Implements `ConvertSaveload` for a component pointing to other entities.
+///
+/// The fields before the `;` are the entities, the rest is serialized as it is.
+macro_rules! saveload {
+    ($name: ident => $data: ident {
+        $($entity: ident,)*
+        ;
+        $($(#[$attr: meta])* $field: ident: $ty: ty,)*
+    }) => {
+        #[derive(Clone, Deserialize, Serialize)]
+        #[serde(bound = "M: Serialize + DeserializeOwned")]
+        pub struct $data<M> {
+            $(pub $entity: M,)*
+            $($(#[$attr])* pub $field: $ty,)*
+        }
+
+        impl<M: Serialize + DeserializeOwned> ConvertSaveload<M> for $name {
+            type Data = $data<M>;
+            type Error = NoError;
+
+            fn convert_into<F>(&self, mut ids: F) -> Result<Self::Data, NoError>
+            where
+                F: FnMut(Entity) -> Option<M>,
+            {
+                Ok($data {
+                    $($entity: self.$entity.convert_into(&mut ids)?,)*
+                    $($field: self.$field,)*
+                })
+            }
+
+            fn convert_from<F>(data: Self::Data, mut ids: F) -> Result<Self, NoError>
+            where
+                F: FnMut(M) -> Option<Entity>,
+            {
+                Ok($name {
+                    $($entity: Entity::convert_from(data.$entity, &mut ids)?,)*
+                    $($field: data.$field,)*
+                })
+            }
+        }
+    };
+}
+
+saveload!(Thruster => ThrusterData {
+    ship,
+    ;
+    #[serde(with = "VectorDef")]
+    position: Vector,
+    direction: f32,
+    len: f32,
+    #[serde(with = "key")]
+    key: Key,
+    push_direction: f32,
+    push: f32,
+    rotation: f32,
+    heating: f32,
+    condition: Condition,
+});
+
+saveload!(Landed => LandedData {
+    planet,
+    ;
+    angle: f32,
+    distance: f32,
+    rotation: f32,
+});
+
+saveload!(Orbit => OrbitData {
+    body,
+    ;
+    eccentricity: f32,
+    periapsis: f32,
+    apoapsis: Option<f32>,
+    periapsis_angle: f32,
+});
+
+saveload!(Lagrange => LagrangeData {
+    primary,
+    ;
+});
+
+saveload!(AtLagrange => AtLagrangeData {
+    secondary,
+    ;
+    point: LagrangePoint,
+});
+
+/// Marks the entities stored in a level file.
+pub struct LevelMarker;
+
+type LevelMarkers<'a> = WriteStorage<'a, SimpleMarker<LevelMarker>>;
+
+/// The components stored in a level file, behind the given kind of storage.
+///
+/// Orbits are left out, they get computed from the rest every frame. Anything over 16
+/// components goes to the `level_extras`.
+macro_rules! level_components {
+    ($storage: ident) => {
+        (
+            $storage<'a, Landing>,
+            $storage<'a, Ship>,
+            $storage<'a, Rotation>,
+            $storage<'a, RotationSpeed>,
+            $storage<'a, Thruster>,
+            $storage<'a, Star>,
+            $storage<'a, Comet>,
+            $storage<'a, Planet>,
+            $storage<'a, Landed>,
+            $storage<'a, Lagrange>,
+            $storage<'a, AtLagrange>,
+            $storage<'a, Name>,
+            $storage<'a, Position>,
+            $storage<'a, Speed>,
+            $storage<'a, Mass>,
+            $storage<'a, Zone>,
+        )
+    };
+}
+
+/// The components stored in the second part of a level file.
+macro_rules! level_extras {
+    ($storage: ident) => {
+        ($storage<'a, PowerUp>, $storage<'a, FuelStation>, $storage<'a, Hull>)
+    };
+}
+
+type LevelRead<'a> = level_components!(ReadStorage);
+type LevelWrite<'a> = level_components!(WriteStorage);
+type ExtrasRead<'a> = level_extras!(ReadStorage);
+type ExtrasWrite<'a> = level_extras!(WriteStorage);
+
+/// Starts marking the entities from scratch.
+fn reset_markers(world: &mut World) {
+    world.register::<SimpleMarker<LevelMarker>>();
+    world.write_storage::<SimpleMarker<LevelMarker>>().clear();
+    world.insert(SimpleMarkerAllocator::<LevelMarker>::new());
+}
+
+/// Writes all the entities of the world as a level file.
+pub fn write_level<W: io::Write>(world: &mut World, mut out: W) -> Result<(), Box<dyn Error>> {
+    reset_markers(world);
+    world.exec(
+        |(entities, mut markers, mut allocator): (
+            Entities,
+            LevelMarkers,
+            Write<SimpleMarkerAllocator<LevelMarker>>,
+        )| {
+            for entity in (&entities).join() {
+                allocator.mark(entity, &mut markers);
+            }
+        },
+    );
+    let (entities, markers, components, extras) = world.system_data::<(
+        Entities,
+        ReadStorage<SimpleMarker<LevelMarker>>,
+        LevelRead,
+        ExtrasRead,
+    )>();
+    writeln!(out, "{}", LEVEL_HEADER)?;
+    writeln!(out, "{} {}", SHIP_PREFIX, world.fetch::<LevelShip>().0)?;
+    SerializeComponents::<NoError, _>::serialize(
+        &components,
+        &entities,
+        &markers,
+        &mut serde_json::Serializer::pretty(&mut out),
+    )?;
+    writeln!(out)?;
+    SerializeComponents::<NoError, _>::serialize(
+        &extras,
+        &entities,
+        &markers,
+        &mut serde_json::Serializer::pretty(&mut out),
+    )?;
+    writeln!(out)?;
+    Ok(())
+}
+
+/// Replaces all the entities of the world by the ones from a level file.
+///
+/// Only the entities are touched, resetting the rest of the level is up to the caller.
+pub fn read_level(world: &mut World, content: &str) -> Result<(), Box<dyn Error>> {
+    let mut parts = content.splitn(3, '\n');
+    if parts.next().map(str::trim_end) != Some(LEVEL_HEADER) {
+        return Err("Not a level".into());
+    }
+    let ship = parts
+        .next()
+        .and_then(|line| line.strip_prefix(SHIP_PREFIX))
+        .ok_or("Missing ship")?
+        .trim();
+    let mut deserializer = serde_json::Deserializer::from_str(parts.next().unwrap_or_default());
+    world.delete_all();
+    reset_markers(world);
+    world.exec(
+        |(entities, mut markers, mut allocator, mut components, mut extras): (
+            Entities,
+            LevelMarkers,
+            Write<SimpleMarkerAllocator<LevelMarker>>,
+            LevelWrite,
+            ExtrasWrite,
+        )| {
+            DeserializeComponents::<NoError, _>::deserialize(
+                &mut components,
+                &entities,
+                &mut markers,
+                &mut allocator,
+                &mut deserializer,
+            )?;
+            // The markers from the first part find the same entities again
+            DeserializeComponents::<NoError, _>::deserialize(
+                &mut extras,
+                &entities,
+                &mut markers,
+                &mut allocator,
+                &mut deserializer,
+            )
+        },
+    )?;
+    deserializer.end()?;
+    world.maintain();
+    world.insert(LevelShip(ship.to_owned()));
+    check_level(world)
+}
+
+/// Rejects the values the file format allows but the game can't work with.
+fn check_level(world: &World) -> Result<(), Box<dyn Error>> {
+    for zone in world.read_storage::<Zone>().join() {
+        if let ZoneEffect::Force { period: Some(period), .. } = zone.effect {
+            if period.is_nan() || period <= 0.0 {
+                return Err(format!("Invalid zone period {}", period).into());
+            }
+        }
+    }
+    for power_up in world.read_storage::<PowerUp>().join() {
+        if Duration::try_from_secs_f32(power_up.duration).is_err() {
+            return Err(format!("Invalid power-up duration {}", power_up.duration).into());
+        }
+    }
+    Ok(())
+}
+
+/// Exports the current world into a new level file.
+///
+/// A developer command, for building levels by tweaking a running one.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn export_level(world: &mut World) -> Result<PathBuf, Box<dyn Error>> {
+    fs::create_dir_all(LEVEL_DIR)?;
+    let stamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+    let path = Path::new(LEVEL_DIR).join(format!("thrust-{}.level", stamp));
+    let mut file = BufWriter::new(File::create(&path)?);
+    write_level(world, &mut file)?;
+    file.flush()?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Game;
+
+    fn count<C: Component>(world: &World) -> usize {
+        world.read_storage::<C>().join().count()
+    }
+
+    #[test]
+    fn level_round_trip() {
+        let mut original = Game::new();
+        original.world_mut().insert(LevelShip("Dart".to_owned()));
+        let mut file = Vec::new();
+        write_level(original.world_mut(), &mut file).unwrap();
+
+        let mut game = Game::new();
+        game.world_mut().delete_all();
+        read_level(game.world_mut(), std::str::from_utf8(&file).unwrap()).unwrap();
+        let world = game.world();
+        assert_eq!(count::<Star>(original.world()), count::<Star>(world));
+        assert_eq!(
+            count::<Thruster>(original.world()),
+            count::<Thruster>(world)
+        );
+        assert_eq!(count::<Name>(original.world()), count::<Name>(world));
+        assert_eq!(
+            count::<PowerUp>(original.world()),
+            count::<PowerUp>(world)
+        );
+        assert_eq!(count::<Hull>(original.world()), count::<Hull>(world));
+
+        assert_eq!(world.fetch::<LevelShip>().0, "Dart");
+
+        let ships = world.read_storage::<Ship>();
+        for thruster in world.read_storage::<Thruster>().join() {
+            assert!(ships.contains(thruster.ship));
+        }
+        let planets = world.read_storage::<Lagrange>();
+        let stars = world.read_storage::<Star>();
+        for lagrange in planets.join() {
+            assert!(stars.contains(lagrange.primary));
+        }
+    }
+
+    #[test]
+    fn zone_without_period() {
+        let mut original = Game::new();
+        for zone in (&mut original.world_mut().write_storage::<Zone>()).join() {
+            if let ZoneEffect::Force { period, .. } = &mut zone.effect {
+                *period = Some(0.0);
+            }
+        }
+        let mut file = Vec::new();
+        write_level(original.world_mut(), &mut file).unwrap();
+
+        let mut game = Game::new();
+        let content = std::str::from_utf8(&file).unwrap();
+        assert!(read_level(game.world_mut(), content).is_err());
+    }
+
+    #[test]
+    fn power_up_lasting_negative_time() {
+        let mut original = Game::new();
+        for power_up in (&mut original.world_mut().write_storage::<PowerUp>()).join() {
+            power_up.duration = -1.0;
+        }
+        let mut file = Vec::new();
+        write_level(original.world_mut(), &mut file).unwrap();
+
+        let mut game = Game::new();
+        let content = std::str::from_utf8(&file).unwrap();
+        assert!(read_level(game.world_mut(), content).is_err());
+    }
+
+    #[test]
+    fn not_a_level() {
+        let mut game = Game::new();
+        assert!(read_level(game.world_mut(), "thrust-replay 1\n").is_err());
+    }
+}
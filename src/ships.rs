@@ -0,0 +1,475 @@
+//! Ship designs and the data files describing them.
+//!
+//! A design is the shape of the hull and the thrusters on it. The designs live in text files, one
+//! per file:
+//!
+//! ```text
+//! thrust-ship 1
+//! name Courier
+//! # Optional, these are the defaults
+//! mass 50
+//! fuel 1
+//! strength 1
+//! # Points of the hull outline, x and y of each
+//! hull -10 0 10 0
+//! # control x y angle length push rotation heating
+//! thruster forward 10 0 0 15 8 0 10
+//! ```
+//!
+//! The pushes and rotations of the thrusters are for a ship of `SHIP_MASS`, heavier ships get
+//! pushed less by the same thrusters. Empty lines and lines starting with `#` are skipped.
+//!
+//! The built-in designs are compiled into the game, more can be put into the `ships` directory
+//! next to it.
+
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+
+use quicksilver::geom::{Rectangle, Vector};
+use quicksilver::lifecycle::Key;
+use specs::Entity;
+
+#[cfg(not(target_arch = "wasm32"))]
+use log::{error, info};
+
+use crate::components::{Condition, Thruster};
+use crate::input::KeyMap;
+
+const SHIP_HEADER: &str = "thrust-ship 1";
+
+/// Where the designs besides the built-in ones come from.
+#[cfg(not(target_arch = "wasm32"))]
+pub const SHIP_DIR: &str = "ships";
+
+/// The designs compiled into the game.
+///
+/// The first one is the one the ships get when nothing else is said.
+const BUILTIN_SHIPS: &[&str] = &[
+    include_str!("../static/ships/courier.ship"),
+    include_str!("../static/ships/dart.ship"),
+    include_str!("../static/ships/hauler.ship"),
+];
+
+/// The mass the pushes of the thrusters are given for.
+pub const SHIP_MASS: f32 = 50.0;
+
+/// Rotation force of a thruster, per its push and distance from the center.
+const TORQUE: f32 = 0.6;
+/// Heating of a thruster, per its push.
+const HEAT_PER_PUSH: f32 = 1.25;
+
+/// Which of the pilot's controls fires a thruster.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Control {
+    Forward,
+    Back,
+    Left,
+    Right,
+}
+
+impl Control {
+    pub fn key(self, keys: &KeyMap) -> Key {
+        match self {
+            Control::Forward => keys.forward,
+            Control::Back => keys.back,
+            Control::Left => keys.left,
+            Control::Right => keys.right,
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            Control::Forward => Control::Back,
+            Control::Back => Control::Left,
+            Control::Left => Control::Right,
+            Control::Right => Control::Forward,
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        [
+            Control::Forward,
+            Control::Back,
+            Control::Left,
+            Control::Right,
+        ]
+        .iter()
+        .copied()
+        .find(|control| control.to_string() == name)
+    }
+}
+
+impl Display for Control {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match self {
+            Control::Forward => write!(fmt, "forward"),
+            Control::Back => write!(fmt, "back"),
+            Control::Left => write!(fmt, "left"),
+            Control::Right => write!(fmt, "right"),
+        }
+    }
+}
+
+/// A thruster of a design, not yet on any ship.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ThrusterDesign {
+    pub control: Control,
+    /// Relative to the center of the ship.
+    pub position: Vector,
+    /// Both where the nozzle points and where it pushes, in degrees.
+    pub angle: f32,
+    pub len: f32,
+    pub push: f32,
+    pub rotation: f32,
+    pub heating: f32,
+}
+
+impl ThrusterDesign {
+    /// Recomputes the rotation, heating and size after the placement or push changed.
+    pub fn retune(&mut self) {
+        let torque = self.position.cross(Vector::from_angle(self.angle));
+        self.rotation = torque * self.push * TORQUE;
+        self.heating = self.push * HEAT_PER_PUSH;
+        self.len = 3.0 + self.push * 1.5;
+    }
+
+    /// The thruster for the given ship of the given mass, fired by the given keys.
+    pub fn thruster(&self, ship: Entity, mass: f32, keys: &KeyMap) -> Thruster {
+        let inertia = SHIP_MASS / mass;
+        Thruster {
+            ship,
+            position: self.position,
+            direction: self.angle,
+            len: self.len,
+            key: self.control.key(keys),
+            push_direction: self.angle,
+            push: self.push * inertia,
+            rotation: self.rotation * inertia,
+            heating: self.heating,
+            condition: Condition::Intact,
+        }
+    }
+
+    fn parse(fields: &[&str]) -> Result<Self, Box<dyn Error>> {
+        if fields.len() != 8 {
+            return Err(format!("Thruster needs 8 fields, has {}", fields.len()).into());
+        }
+        let control =
+            Control::parse(fields[0]).ok_or_else(|| format!("Unknown control {}", fields[0]))?;
+        let numbers = fields[1..]
+            .iter()
+            .map(|field| field.parse())
+            .collect::<Result<Vec<f32>, _>>()?;
+        if numbers.iter().any(|number| !number.is_finite()) {
+            return Err("Thruster needs finite numbers".into());
+        }
+        if numbers[3] < 0.0 {
+            return Err("Thruster length can't be negative".into());
+        }
+        if numbers[4] <= 0.0 {
+            return Err("Thruster push needs to be positive".into());
+        }
+        Ok(ThrusterDesign {
+            control,
+            position: Vector::new(numbers[0], numbers[1]),
+            angle: numbers[2],
+            len: numbers[3],
+            push: numbers[4],
+            rotation: numbers[5],
+            heating: numbers[6],
+        })
+    }
+}
+
+/// A kind of ship, the hull and the thrusters on it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShipDesign {
+    pub name: String,
+    pub mass: f32,
+    /// Multiplies the fuel of the rules.
+    pub fuel: f32,
+    /// Multiplies the G limit of the rules.
+    pub strength: f32,
+    /// The outline of the hull, relative to the center of the ship.
+    pub hull: Vec<Vector>,
+    pub thrusters: Vec<ThrusterDesign>,
+}
+
+impl Default for ShipDesign {
+    /// The first of the built-in designs.
+    fn default() -> Self {
+        Shipyard::default().designs.swap_remove(0)
+    }
+}
+
+impl ShipDesign {
+    /// The push of all the thrusters together.
+    pub fn cost(&self) -> f32 {
+        self.thrusters.iter().map(|t| t.push).sum()
+    }
+
+    /// The smallest rectangle around the hull, where the thrusters may sit.
+    pub fn bounds(&self) -> Rectangle {
+        let (mut min, mut max) = (Vector::new(f32::MAX, f32::MAX), Vector::new(f32::MIN, f32::MIN));
+        for point in &self.hull {
+            min = Vector::new(min.x.min(point.x), min.y.min(point.y));
+            max = Vector::new(max.x.max(point.x), max.y.max(point.y));
+        }
+        Rectangle::new(min, max - min)
+    }
+
+    /// Reads a design from the content of a ship file.
+    pub fn parse(content: &str) -> Result<Self, Box<dyn Error>> {
+        let mut lines = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+        if lines.next() != Some(SHIP_HEADER) {
+            return Err("Not a ship".into());
+        }
+        let mut name = None;
+        let (mut mass, mut fuel, mut strength) = (SHIP_MASS, 1.0, 1.0);
+        let mut hull = Vec::new();
+        let mut thrusters = Vec::new();
+        for line in lines {
+            let mut fields = line.split_whitespace();
+            match fields.next() {
+                Some("name") => name = Some(fields.collect::<Vec<_>>().join(" ")),
+                Some("mass") => mass = single(fields)?,
+                Some("fuel") => fuel = single(fields)?,
+                Some("strength") => strength = single(fields)?,
+                Some("hull") => {
+                    let coords = fields.map(str::parse).collect::<Result<Vec<f32>, _>>()?;
+                    if coords.len() < 4 || coords.len() % 2 != 0 {
+                        return Err("Hull needs at least two whole points".into());
+                    }
+                    hull = coords.chunks(2).map(|c| Vector::new(c[0], c[1])).collect();
+                }
+                Some("thruster") => {
+                    let fields = fields.collect::<Vec<_>>();
+                    thrusters.push(ThrusterDesign::parse(&fields)?);
+                }
+                _ => return Err(format!("Unknown line {}", line).into()),
+            }
+        }
+        if hull.is_empty() {
+            return Err("Missing hull".into());
+        }
+        if thrusters.is_empty() {
+            return Err("No thrusters".into());
+        }
+        for (value, what) in &[(mass, "Mass"), (fuel, "Fuel"), (strength, "Strength")] {
+            if !value.is_finite() || *value <= 0.0 {
+                return Err(format!("{} needs to be positive", what).into());
+            }
+        }
+        Ok(ShipDesign {
+            name: name.ok_or("Missing name")?,
+            mass,
+            fuel,
+            strength,
+            hull,
+            thrusters,
+        })
+    }
+}
+
+/// The only number on a line.
+fn single<'a>(mut fields: impl Iterator<Item = &'a str>) -> Result<f32, Box<dyn Error>> {
+    let value = fields.next().ok_or("Missing value")?.parse()?;
+    if fields.next().is_some() {
+        return Err("Too many values".into());
+    }
+    Ok(value)
+}
+
+impl Display for ShipDesign {
+    /// Writes the design as the content of a ship file.
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        writeln!(fmt, "{}", SHIP_HEADER)?;
+        writeln!(fmt, "name {}", self.name)?;
+        writeln!(fmt, "mass {}", self.mass)?;
+        writeln!(fmt, "fuel {}", self.fuel)?;
+        writeln!(fmt, "strength {}", self.strength)?;
+        write!(fmt, "hull")?;
+        for point in &self.hull {
+            write!(fmt, " {} {}", point.x, point.y)?;
+        }
+        writeln!(fmt)?;
+        for t in &self.thrusters {
+            writeln!(
+                fmt,
+                "thruster {} {} {} {} {} {} {} {}",
+                t.control,
+                t.position.x,
+                t.position.y,
+                t.angle,
+                t.len,
+                t.push,
+                t.rotation,
+                t.heating,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// All the ship designs the levels can refer to, by their names.
+#[derive(Clone, Debug)]
+pub struct Shipyard {
+    pub designs: Vec<ShipDesign>,
+}
+
+impl Default for Shipyard {
+    fn default() -> Self {
+        let designs = BUILTIN_SHIPS
+            .iter()
+            .map(|content| ShipDesign::parse(content).expect("Broken built-in ship"))
+            .collect();
+        Shipyard { designs }
+    }
+}
+
+impl Shipyard {
+    pub fn design(&self, name: &str) -> Option<&ShipDesign> {
+        self.designs.iter().find(|design| design.name == name)
+    }
+
+    /// The available design after the named one, going around.
+    pub fn next<F: Fn(&str) -> bool>(&self, name: &str, available: F) -> &ShipDesign {
+        let count = self.designs.len();
+        let start = self.designs.iter().position(|design| design.name == name).map_or(0, |i| i + 1);
+        (0..count)
+            .map(|i| &self.designs[(start + i) % count])
+            .find(|design| available(&design.name))
+            .unwrap_or(&self.designs[0])
+    }
+
+    /// Adds a design, replacing one of the same name.
+    pub fn add(&mut self, design: ShipDesign) {
+        match self.designs.iter_mut().find(|d| d.name == design.name) {
+            Some(old) => *old = design,
+            None => self.designs.push(design),
+        }
+    }
+
+    /// Adds all the designs from the ship files in the directory.
+    ///
+    /// Broken files are skipped, the rest still gets loaded.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_dir(&mut self, dir: &Path) {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                info!("No ship designs in {}: {}", dir.display(), e);
+                return;
+            }
+        };
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            if path.extension() == Some("ship".as_ref()) {
+                let design = fs::read_to_string(&path)
+                    .map_err(Box::<dyn Error>::from)
+                    .and_then(|content| ShipDesign::parse(&content));
+                match design {
+                    Ok(design) => {
+                        info!("Loaded ship {} from {}", design.name, path.display());
+                        self.add(design);
+                    }
+                    Err(e) => error!("Broken ship {}: {}", path.display(), e),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use specs::{Builder, World, WorldExt};
+
+    use super::*;
+    use crate::input::PLAYER_KEYS;
+
+    #[test]
+    fn builtin_ships_load() {
+        let shipyard = Shipyard::default();
+        assert!(shipyard.design("Courier").is_some());
+        assert_eq!(ShipDesign::default().thrusters.len(), 4);
+        assert_eq!(ShipDesign::default().mass, SHIP_MASS);
+    }
+
+    #[test]
+    fn classes_handle_differently() {
+        let shipyard = Shipyard::default();
+        let dart = shipyard.design("Dart").unwrap();
+        let hauler = shipyard.design("Hauler").unwrap();
+        assert!(dart.mass < SHIP_MASS && hauler.mass > SHIP_MASS);
+        assert!(dart.fuel < hauler.fuel);
+        assert!(dart.strength < hauler.strength);
+        let ship = World::new().create_entity().build();
+        let keys = PLAYER_KEYS[0];
+        let main = |design: &ShipDesign| {
+            design
+                .thrusters
+                .iter()
+                .map(|t| t.thruster(ship, design.mass, &keys))
+                .find(|t| t.key == keys.forward)
+                .unwrap()
+                .push
+        };
+        assert!(main(dart) > main(hauler));
+    }
+
+    #[test]
+    fn cycling_through_classes() {
+        let shipyard = Shipyard::default();
+        let mut name = "Courier".to_owned();
+        for _ in 0..shipyard.designs.len() {
+            name = shipyard.next(&name, |_| true).name.clone();
+        }
+        assert_eq!(name, "Courier");
+        assert_eq!(shipyard.next("Unknown", |_| true).name, "Courier");
+        assert_eq!(shipyard.next("Courier", |ship| ship != "Dart").name, "Hauler");
+        assert_eq!(shipyard.next("Courier", |ship| ship == "Courier").name, "Courier");
+    }
+
+    #[test]
+    fn ship_round_trip() {
+        let design = ShipDesign::default();
+        assert_eq!(ShipDesign::parse(&design.to_string()).unwrap(), design);
+    }
+
+    #[test]
+    fn broken_ships() {
+        assert!(ShipDesign::parse("thrust-level 7\n").is_err());
+        let no_thrusters = "thrust-ship 1\nname Brick\nhull -1 0 1 0\n";
+        assert!(ShipDesign::parse(no_thrusters).is_err());
+        let bad_control = format!("{}thruster up 0 0 0 1 1 0 1\n", no_thrusters);
+        assert!(ShipDesign::parse(&bad_control).is_err());
+        let design = ShipDesign::default().to_string();
+        let bad_values = [
+            ("fuel 1", "fuel 0"),
+            ("strength 1", "strength -1"),
+            ("strength 1", "strength NaN"),
+        ];
+        for (good, bad) in &bad_values {
+            assert!(ShipDesign::parse(&design.replace(good, bad)).is_err());
+        }
+        let thruster = format!("{}thruster left 10 0 20 10 3 6 5\n", no_thrusters);
+        assert!(ShipDesign::parse(&thruster).is_ok());
+        let bad_numbers = [
+            "10 0 20 10 NaN 6 5",
+            "10 0 20 10 0 6 5",
+            "10 0 20 -1 3 6 5",
+            "10 0 inf 10 3 6 5",
+        ];
+        for bad in &bad_numbers {
+            let broken = thruster.replace("10 0 20 10 3 6 5", bad);
+            assert!(ShipDesign::parse(&broken).is_err(), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,1005 @@
+//! State of the game and the level, from winning or losing to the scores and the event log.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs::{self, File};
+use std::io;
+#[cfg(not(target_arch = "wasm32"))]
+use std::io::BufWriter;
+use std::io::Write as _;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+#[cfg(all(feature = "leaderboard", not(target_arch = "wasm32")))]
+use std::sync::mpsc::Sender;
+#[cfg(all(feature = "leaderboard", not(target_arch = "wasm32")))]
+use std::thread;
+use std::time::Duration;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use quicksilver::geom::Vector;
+use quicksilver::graphics::Color;
+use quicksilver::lifecycle::Key;
+use specs::prelude::*;
+use specs::SystemData;
+
+use log::{error, info};
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
+
+use crate::components::{
+    Anomaly, AtLagrange, Boost, Capture, Comet, Condition, FuelStation, Hull, Lagrange,
+    LagrangePoint, Landing, Mass, Name, Planet, Position, PowerUp, Rotation, RotationSpeed, Ship,
+    Speed, Star, Zone, ZoneEffect, ZoneShape,
+};
+use crate::input::{KeyMap, Replay, ReplayMode, PLAYER_KEYS};
+use crate::loadout::Loadout;
+use crate::ships::{ShipDesign, Shipyard};
+use crate::physics::{DifficultyTimeMod, FrameDuration, LAND_DISTANCE, TOUCHDOWN_SPEED};
+use crate::render::FitView;
+use crate::tutorial::Hints;
+
+/// How many players there are and what they need to do to win.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Deserialize, Serialize))]
+pub enum Players {
+    #[default]
+    Single,
+    /// Two ships, both need to land.
+    Coop,
+    /// Two ships, the first one to land wins.
+    Race,
+}
+
+impl Players {
+    pub fn next(self) -> Self {
+        match self {
+            Players::Single => Players::Coop,
+            Players::Coop => Players::Race,
+            Players::Race => Players::Single,
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        [Players::Single, Players::Coop, Players::Race]
+            .iter()
+            .copied()
+            .find(|players| format!("{:?}", players) == name)
+    }
+}
+
+impl Display for Players {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match self {
+            Players::Single => write!(fmt, "single player"),
+            Players::Coop => write!(fmt, "two players, both need to land"),
+            Players::Race => write!(fmt, "two players, first to land wins"),
+        }
+    }
+}
+
+/// How forgiving the game is, chosen on the start screen.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Deserialize, Serialize))]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+}
+
+impl Difficulty {
+    pub fn next(self) -> Self {
+        match self {
+            Difficulty::Easy => Difficulty::Normal,
+            Difficulty::Normal => Difficulty::Hard,
+            Difficulty::Hard => Difficulty::Easy,
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        [Difficulty::Easy, Difficulty::Normal, Difficulty::Hard]
+            .iter()
+            .copied()
+            .find(|difficulty| format!("{:?}", difficulty) == name)
+    }
+
+    /// The rules of a level on this difficulty, before any assists.
+    pub fn rules(self) -> Rules {
+        match self {
+            Difficulty::Easy => Rules {
+                time_mod: 75.0,
+                gravity: 0.8,
+                closeness: 2.0,
+                fuel: 90.0,
+                land_distance: 35.0,
+                touchdown_speed: 5.0,
+                capture_time: Duration::from_secs(1),
+                spare_parts: 5,
+                g_limit: 6.0,
+                g_tolerance: Duration::from_secs(2),
+            },
+            Difficulty::Normal => Rules {
+                time_mod: 100.0,
+                gravity: 1.0,
+                closeness: 1.0,
+                fuel: 60.0,
+                land_distance: LAND_DISTANCE,
+                touchdown_speed: TOUCHDOWN_SPEED,
+                capture_time: Duration::from_secs(2),
+                spare_parts: 3,
+                g_limit: 4.0,
+                g_tolerance: Duration::from_secs(1),
+            },
+            Difficulty::Hard => Rules {
+                time_mod: 125.0,
+                gravity: 1.2,
+                closeness: 0.5,
+                fuel: 30.0,
+                land_distance: 18.0,
+                touchdown_speed: 2.0,
+                capture_time: Duration::from_secs(3),
+                spare_parts: 1,
+                g_limit: 3.5,
+                g_tolerance: Duration::from_millis(500),
+            },
+        }
+    }
+}
+
+impl Display for Difficulty {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match self {
+            Difficulty::Easy => write!(fmt, "easy"),
+            Difficulty::Normal => write!(fmt, "normal"),
+            Difficulty::Hard => write!(fmt, "hard"),
+        }
+    }
+}
+
+/// What the systems of the current level go by.
+///
+/// Given by the difficulty and adjusted by the assists, set up when the level starts.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Deserialize, Serialize))]
+pub struct Rules {
+    /// How many times faster than the real time the simulation runs.
+    pub time_mod: f32,
+    /// Multiplies the force of the gravity.
+    pub gravity: f32,
+    /// Multiplies the distance under which the gravity is disabled.
+    ///
+    /// A bigger one makes the close passes less violent.
+    pub closeness: f32,
+    /// Fuel the ships start with, in seconds of a single thruster firing.
+    pub fuel: f32,
+    /// How close to a landing area a ship needs to get to land there.
+    pub land_distance: f32,
+    /// The fastest a ship can touch a planet without crashing into it.
+    ///
+    /// Also the fastest a ship can move in a landing area and still land there.
+    pub touchdown_speed: f32,
+    /// How long a ship needs to hold still in a landing area to land there.
+    pub capture_time: Duration,
+    /// Spare parts the ships start with, each repairs a thruster once.
+    pub spare_parts: u32,
+    /// The most G the ships stand.
+    pub g_limit: f32,
+    /// How long the ships stand being over the G limit before they break apart.
+    pub g_tolerance: Duration,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Difficulty::default().rules()
+    }
+}
+
+/// How many levels need to be lost in a row before an assist is offered.
+const ASSIST_AFTER: u32 = 2;
+
+/// What the assists do, in the order they are offered.
+const ASSIST_NAMES: [&str; 3] = ["slower time", "larger landing areas", "extra fuel"];
+
+/// Help offered to players failing a level over and over.
+///
+/// Each accepted assist adds to the previous ones: first the time slows down, then the landing
+/// areas grow and last the ships get more fuel.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Assists {
+    /// Levels lost in a row since the last assist.
+    pub failures: u32,
+    /// How many assists were accepted.
+    pub level: u32,
+}
+
+impl Assists {
+    /// The assist on offer, if any.
+    pub fn offered(&self) -> Option<&'static str> {
+        if self.failures >= ASSIST_AFTER {
+            ASSIST_NAMES.get(self.level as usize).copied()
+        } else {
+            None
+        }
+    }
+
+    pub fn accept(&mut self) {
+        if self.offered().is_some() {
+            self.level += 1;
+            self.failures = 0;
+        }
+    }
+
+    /// Applies the accepted assists.
+    pub fn apply(&self, mut rules: Rules) -> Rules {
+        if self.level >= 1 {
+            rules.time_mod *= 0.8;
+        }
+        if self.level >= 2 {
+            rules.land_distance *= 1.4;
+        }
+        if self.level >= 3 {
+            rules.fuel *= 1.5;
+        }
+        rules
+    }
+}
+
+impl Display for Assists {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        let active = ASSIST_NAMES.iter().take(self.level as usize).copied().collect::<Vec<_>>();
+        write!(fmt, "{}", active.join(", "))
+    }
+}
+
+/// Counts the levels lost in a row, for the `Assists`.
+///
+/// Only the player's own runs count, not replays.
+pub struct CountFailures {
+    pub last: GameState,
+}
+
+impl<'a> System<'a> for CountFailures {
+    type SystemData = (ReadExpect<'a, GameState>, Read<'a, Replay>, Write<'a, Assists>);
+
+    fn run(&mut self, (state, replay, mut assists): Self::SystemData) {
+        if *state == self.last {
+            return;
+        }
+        self.last = *state;
+        if replay.mode != ReplayMode::Recording {
+            return;
+        }
+        match *state {
+            GameState::Lost(_) => assists.failures += 1,
+            GameState::Won => assists.failures = 0,
+            _ => (),
+        }
+    }
+}
+
+/// How long the current level has been running, pauses excluded.
+#[derive(Copy, Clone, Default, Debug)]
+#[cfg_attr(feature = "serialize", derive(Deserialize, Serialize))]
+pub struct LevelTime(pub Duration);
+
+/// Measures the `LevelTime`, part of the physics so it stops with it.
+pub struct LevelClock;
+
+impl<'a> System<'a> for LevelClock {
+    type SystemData = (Read<'a, FrameDuration>, Write<'a, LevelTime>);
+
+    fn run(&mut self, (fd, mut time): Self::SystemData) {
+        time.0 += fd.0;
+    }
+}
+
+/// How the current level went, for the results screen.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RunStats {
+    /// Fuel burned by all the ships together, in seconds of a single thruster firing.
+    ///
+    /// Refueling doesn't take it back.
+    pub fuel_used: f32,
+    /// Fuel the ships have left now.
+    pub fuel_left: f32,
+    /// Fuel all the ships started with.
+    pub fuel_total: f32,
+    /// The fuel of each ship in the last frame, to see how much it burned since.
+    pub fuel_seen: HashMap<Entity, f32>,
+    /// The fastest any of the ships flew.
+    pub max_speed: f32,
+    /// How close to the center of the landing areas the ships got, from 0 at the edge to 1.
+    ///
+    /// Known only once the level is won.
+    pub accuracy: Option<f32>,
+    /// The landing areas the ships landed in.
+    pub pads: Vec<Entity>,
+    /// The points of these landing areas.
+    pub pad_points: u32,
+    /// The ships the run unlocked.
+    pub unlocked: Vec<&'static str>,
+}
+
+/// The score a level on normal difficulty is worth for each part done perfectly.
+const SCORE_PART: f32 = 1_000.0;
+
+/// The time in which the level is worth half of the time part of the score.
+const SCORE_HALF_TIME: f32 = 60.0;
+
+impl RunStats {
+    /// The score of the level, finished in the given time.
+    ///
+    /// Made of the landing accuracy, the fuel left and the time, each worth the same, and the
+    /// points of the landing areas. Harder difficulties multiply it. Lost levels have no score.
+    pub fn score(&self, time: Duration, difficulty: Difficulty) -> u32 {
+        let accuracy = match self.accuracy {
+            Some(accuracy) => accuracy,
+            None => return 0,
+        };
+        // Not the fuel actually left, refueling on the way shouldn't pay off
+        let fuel_left = if self.fuel_total > 0.0 {
+            (1.0 - self.fuel_used / self.fuel_total).max(0.0)
+        } else {
+            0.0
+        };
+        let time = SCORE_HALF_TIME / (SCORE_HALF_TIME + time.as_secs_f32());
+        let multiplier = match difficulty {
+            Difficulty::Easy => 0.5,
+            Difficulty::Normal => 1.0,
+            Difficulty::Hard => 2.0,
+        };
+        let score = (accuracy + fuel_left + time) * SCORE_PART + self.pad_points as f32;
+        (score * multiplier).round() as u32
+    }
+}
+
+/// Keeps the `RunStats` up to date while the level runs.
+pub struct CollectStats;
+
+impl<'a> System<'a> for CollectStats {
+    type SystemData = (
+        ReadExpect<'a, GameState>,
+        Entities<'a>,
+        ReadStorage<'a, Ship>,
+        ReadStorage<'a, Speed>,
+        Write<'a, RunStats>,
+    );
+
+    fn run(&mut self, (state, entities, ships, speeds, mut stats): Self::SystemData) {
+        if *state != GameState::Running {
+            return;
+        }
+        for (ent, ship) in (&entities, &ships).join() {
+            // A ship seen for the first time burned what it's missing
+            let last = stats.fuel_seen.insert(ent, ship.fuel).unwrap_or(ship.max_fuel);
+            stats.fuel_used += (last - ship.fuel).max(0.0);
+        }
+        stats.fuel_left = ships.join().map(|ship| ship.fuel).sum();
+        stats.fuel_total = ships.join().map(|ship| ship.max_fuel).sum();
+        let fastest = (&ships, &speeds)
+            .join()
+            .map(|(_, speed)| speed.0.len())
+            .fold(0.0, f32::max);
+        stats.max_speed = stats.max_speed.max(fastest);
+    }
+}
+
+/// Something that happened in the game, for the structured event log.
+#[derive(Copy, Clone, Debug)]
+pub enum GameEvent {
+    ThrustStart {
+        ship: Entity,
+        key: Key,
+        /// The change of speed per second the thruster causes.
+        push: Vector,
+    },
+    ThrustStop {
+        ship: Entity,
+        key: Key,
+    },
+    /// A ship touched a planet too fast to land.
+    Collision {
+        ship: Entity,
+        planet: Entity,
+        speed: f32,
+    },
+    Touchdown {
+        ship: Entity,
+        planet: Entity,
+        speed: f32,
+    },
+    Takeoff {
+        ship: Entity,
+        planet: Entity,
+    },
+    State {
+        from: GameState,
+        to: GameState,
+    },
+    /// A thruster got worse, from an impact or overheating.
+    ThrusterDamaged {
+        ship: Entity,
+        key: Key,
+        condition: Condition,
+    },
+}
+
+/// Escapes a string to be put into JSON.
+fn json_str(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+impl GameEvent {
+    /// Formats the event as a single line JSON object.
+    fn to_json(self, time: Duration) -> String {
+        let time = time.as_secs_f64();
+        let dbg = |what: &dyn Debug| json_str(&format!("{:?}", what));
+        match self {
+            GameEvent::ThrustStart { ship, key, push } => format!(
+                r#"{{"time":{},"event":"thrust_start","ship":{},"key":{},"push":[{},{}]}}"#,
+                time,
+                ship.id(),
+                dbg(&key),
+                push.x,
+                push.y,
+            ),
+            GameEvent::ThrustStop { ship, key } => format!(
+                r#"{{"time":{},"event":"thrust_stop","ship":{},"key":{}}}"#,
+                time,
+                ship.id(),
+                dbg(&key),
+            ),
+            GameEvent::Collision { ship, planet, speed } => format!(
+                r#"{{"time":{},"event":"collision","ship":{},"planet":{},"speed":{}}}"#,
+                time,
+                ship.id(),
+                planet.id(),
+                speed,
+            ),
+            GameEvent::Touchdown { ship, planet, speed } => format!(
+                r#"{{"time":{},"event":"touchdown","ship":{},"planet":{},"speed":{}}}"#,
+                time,
+                ship.id(),
+                planet.id(),
+                speed,
+            ),
+            GameEvent::Takeoff { ship, planet } => format!(
+                r#"{{"time":{},"event":"takeoff","ship":{},"planet":{}}}"#,
+                time,
+                ship.id(),
+                planet.id(),
+            ),
+            GameEvent::State { from, to } => format!(
+                r#"{{"time":{},"event":"state","from":{},"to":{}}}"#,
+                time,
+                dbg(&from),
+                dbg(&to),
+            ),
+            GameEvent::ThrusterDamaged {
+                ship,
+                key,
+                condition,
+            } => format!(
+                r#"{{"time":{},"event":"thruster_damaged","ship":{},"key":{},"condition":{}}}"#,
+                time,
+                ship.id(),
+                dbg(&key),
+                dbg(&condition),
+            ),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+const EVENT_LOG_DIR: &str = "events";
+
+/// Structured log of gameplay events, as JSON lines.
+///
+/// Switched on and off at runtime, does nothing while off.
+#[derive(Default)]
+pub struct EventLog {
+    sink: Option<Box<dyn io::Write + Send + Sync>>,
+}
+
+impl EventLog {
+    pub fn record(&mut self, time: Duration, event: GameEvent) {
+        if let Some(sink) = &mut self.sink {
+            if let Err(e) = writeln!(sink, "{}", event.to_json(time)) {
+                error!("Can't write the event log, turning it off: {}", e);
+                self.sink = None;
+            }
+        }
+    }
+
+    /// Starts logging into a new file or stops the logging.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn toggle(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(mut sink) = self.sink.take() {
+            info!("Event log stopped");
+            sink.flush()?;
+            return Ok(());
+        }
+        fs::create_dir_all(EVENT_LOG_DIR)?;
+        let stamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        let path = Path::new(EVENT_LOG_DIR).join(format!("thrust-{}.jsonl", stamp));
+        self.sink = Some(Box::new(BufWriter::new(File::create(&path)?)));
+        info!("Logging events into {}", path.display());
+        Ok(())
+    }
+}
+
+/// Puts changes of the game state into the event log.
+pub struct LogStateChanges {
+    pub last: GameState,
+}
+
+impl<'a> System<'a> for LogStateChanges {
+    type SystemData = (ReadExpect<'a, GameState>, Read<'a, LevelTime>, Write<'a, EventLog>);
+
+    fn run(&mut self, (state, time, mut events): Self::SystemData) {
+        if *state != self.last {
+            events.record(time.0, GameEvent::State { from: self.last, to: *state });
+            self.last = *state;
+        }
+    }
+}
+
+/// The only level we have so far, for identifying the scores, progress and crash reports.
+pub const LEVEL_ID: &str = "default";
+
+/// The name of the ship design the players fly in the level, unless they made their own.
+///
+/// Part of the level, stored in the level files. Empty for the first of the built-in designs.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct LevelShip(pub String);
+
+/// How many of the best scores are shown.
+#[cfg(all(feature = "leaderboard", not(target_arch = "wasm32")))]
+const LEADERBOARD_LEN: usize = 10;
+
+/// Best times of the current level on the online leaderboard, once they arrive.
+#[derive(Clone, Debug, Default)]
+pub struct Leaderboard {
+    pub entries: Vec<(String, Duration)>,
+}
+
+/// Submits the time of a won level to the leaderboard and fetches the best ones.
+///
+/// Runs in a background thread, the best times are sent over the channel. The server is
+/// expected to accept `POST <url>/scores` with a JSON object of `level`, `players`,
+/// `difficulty`, `name` and `time_ms` and to answer
+/// `GET <url>/scores?level=<level>&players=<players>&difficulty=<difficulty>` with a JSON array
+/// of objects with `name` and `time_ms`, best first.
+#[cfg(all(feature = "leaderboard", not(target_arch = "wasm32")))]
+pub fn submit_score(
+    url: &str,
+    name: &str,
+    players: Players,
+    difficulty: Difficulty,
+    time: Duration,
+    results: Sender<Vec<(String, Duration)>>,
+) {
+    let scores = format!("{}/scores", url.trim_end_matches('/'));
+    let name = name.to_owned();
+    let players = format!("{:?}", players);
+    let difficulty = format!("{:?}", difficulty);
+    thread::spawn(move || {
+        let response = ureq::post(&scores)
+            .timeout(LEADERBOARD_TIMEOUT)
+            .send_json(ureq::json!({
+                "level": LEVEL_ID,
+                "players": players,
+                "difficulty": difficulty,
+                "name": name,
+                "time_ms": time.as_millis() as u64,
+            }));
+        if !response.ok() {
+            error!("Failed to submit score: {}", response.status_line());
+            return;
+        }
+        let response = ureq::get(&scores)
+            .query("level", LEVEL_ID)
+            .query("players", &players)
+            .query("difficulty", &difficulty)
+            .timeout(LEADERBOARD_TIMEOUT)
+            .call();
+        if !response.ok() {
+            error!("Failed to get scores: {}", response.status_line());
+            return;
+        }
+        let entries = match response.into_json() {
+            Ok(ureq::SerdeValue::Array(entries)) => entries,
+            Ok(other) => {
+                error!("Unexpected scores {}", other);
+                return;
+            }
+            Err(e) => {
+                error!("Failed to parse scores: {}", e);
+                return;
+            }
+        };
+        let entries = entries
+            .iter()
+            .filter_map(|entry| {
+                let name = entry.get("name")?.as_str()?;
+                let time = entry.get("time_ms")?.as_u64()?;
+                Some((name.to_owned(), Duration::from_millis(time)))
+            })
+            .take(LEADERBOARD_LEN)
+            .collect();
+        // The game may be gone by now, that's fine.
+        let _ = results.send(entries);
+    });
+}
+
+#[cfg(all(feature = "leaderboard", not(target_arch = "wasm32")))]
+const LEADERBOARD_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Deserialize, Serialize))]
+pub enum LostReason {
+    Overheated,
+    /// Over the G limit for too long.
+    TornApart,
+}
+
+impl Display for LostReason {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match *self {
+            LostReason::Overheated => write!(fmt, "Overheated"),
+            LostReason::TornApart => write!(fmt, "Torn apart by the G-forces"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Deserialize, Serialize))]
+pub enum GameState {
+    Started,
+    Running,
+    Paused,
+    Won,
+    Lost(LostReason),
+}
+
+impl GameState {
+    pub fn toggle(&mut self) {
+        use GameState::*;
+        *self = match *self {
+            Started | Paused => Running,
+            Running => Paused,
+            Won => Won,
+            Lost(reason) => Lost(reason),
+        };
+    }
+
+    /// Is the level over, won or lost?
+    pub fn finished(self) -> bool {
+        matches!(self, GameState::Won | GameState::Lost(_))
+    }
+}
+
+/// The landing area the target indicator points to.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TargetPad(pub Option<Entity>);
+
+impl TargetPad {
+    /// Switches to the next landing area (in arbitrary, but stable order).
+    pub fn cycle(&mut self, entities: &Entities, landings: &ReadStorage<Landing>) {
+        let pads = (entities, landings).join().map(|(ent, _)| ent).collect::<Vec<_>>();
+        let next = self.0
+            .and_then(|current| pads.iter().position(|pad| *pad == current))
+            .map(|idx| idx + 1)
+            .unwrap_or(0);
+        self.0 = pads.get(next).or_else(|| pads.first()).copied();
+    }
+}
+
+/// Makes sure the target points to an existing landing area, if there's any.
+pub struct TrackTarget;
+
+impl<'a> System<'a> for TrackTarget {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Landing>,
+        Write<'a, TargetPad>,
+    );
+
+    fn run(&mut self, (entities, landings, mut target): Self::SystemData) {
+        let valid = target.0
+            .map(|pad| entities.is_alive(pad) && landings.contains(pad))
+            .unwrap_or(false);
+        if !valid {
+            target.0 = None;
+            target.cycle(&entities, &landings);
+        }
+    }
+}
+
+#[derive(SystemData)]
+pub struct VictoryDetectorData<'a> {
+    entities: Entities<'a>,
+    positions: ReadStorage<'a, Position>,
+    speeds: ReadStorage<'a, Speed>,
+    ships: ReadStorage<'a, Ship>,
+    landings: ReadStorage<'a, Landing>,
+    captures: WriteStorage<'a, Capture>,
+    players: Read<'a, Players>,
+    rules: Read<'a, Rules>,
+    frame_duration: Read<'a, FrameDuration>,
+    state: WriteExpect<'a, GameState>,
+    stats: Write<'a, RunStats>,
+}
+
+/// Wins the level once the ships hold still in the landing areas for long enough.
+pub struct VictoryDetector;
+
+impl<'a> System<'a> for VictoryDetector {
+    type SystemData = VictoryDetectorData<'a>;
+
+    fn run(&mut self, mut d: Self::SystemData) {
+        if *d.state != GameState::Running {
+            return;
+        }
+        // Cache the landing areas, we'll need them all for each ship
+        let pads = (&d.entities, &d.landings, &d.positions, d.speeds.maybe())
+            .join()
+            .map(|(pad, landing, pos, speed)| {
+                (pad, landing, pos.0, speed.map(|s| s.0).unwrap_or(Vector::ZERO))
+            })
+            .collect::<Vec<_>>();
+
+        // Find the landing area each ship holds still in (the one it's relatively closest to the
+        // center of, if more overlap) and how close to the center it is, from 0 at the edge to 1.
+        // We don't really care if one ship shares it with another.
+        let rules = *d.rules;
+        let frame_duration = d.frame_duration.0;
+        let mut landed = Vec::new();
+        let ships = (&d.entities, &d.ships, &d.positions, &d.speeds).join();
+        for (ship, _, ship_pos, ship_speed) in ships {
+            let inside = pads
+                .iter()
+                .filter(|(_, _, _, pad_speed)| {
+                    (ship_speed.0 - *pad_speed).len() <= rules.touchdown_speed
+                })
+                .map(|(pad, landing, pad_pos, _)| {
+                    let distance = ship_pos.0.distance(*pad_pos);
+                    (*pad, landing.points, 1.0 - distance / landing.radius(rules.land_distance))
+                })
+                .filter(|(_, _, accuracy)| *accuracy >= 0.0)
+                .max_by(|(_, _, a), (_, _, b)| a.total_cmp(b));
+            let captured = match inside {
+                Some((pad, points, accuracy)) => {
+                    let held = d.captures
+                        .get(ship)
+                        .filter(|capture| capture.pad == pad)
+                        .map_or(Duration::default(), |capture| capture.held)
+                        + frame_duration;
+                    d.captures
+                        .insert(ship, Capture { pad, held })
+                        .expect("Capturing with a dead ship");
+                    Some((pad, points, accuracy)).filter(|_| held >= rules.capture_time)
+                }
+                None => {
+                    d.captures.remove(ship);
+                    None
+                }
+            };
+            landed.push(captured);
+        }
+        // Nobody wins a level without ships
+        let won = !landed.is_empty()
+            && match *d.players {
+                Players::Single | Players::Coop => landed.iter().all(Option::is_some),
+                Players::Race => landed.iter().any(Option::is_some),
+            };
+
+        if won {
+            let landed = landed.into_iter().flatten().collect::<Vec<_>>();
+            let accuracy = landed.iter().map(|(_, _, accuracy)| accuracy).sum::<f32>();
+            d.stats.accuracy = Some(accuracy / landed.len() as f32);
+            d.stats.pads = landed.iter().map(|(pad, _, _)| *pad).collect();
+            d.stats.pad_points = landed.iter().map(|(_, points, _)| points).sum();
+            *d.state = GameState::Won;
+        }
+    }
+}
+
+pub fn level(world: &mut World) {
+    // This deletes entities, but not resources.
+    world.delete_all();
+    world.insert(LevelShip("Courier".to_owned()));
+
+    world.create_entity()
+        .with(Star { color: Color::BLUE, size: 2.0 })
+        .with(Name("Rigel".to_owned()))
+        .with(Position(Vector::new(100.0, 250.0)))
+        .with(Speed(Vector::new(3.5, 3.2)))
+        .with(Mass(8.0))
+        .build();
+    world.create_entity()
+        .with(Star { color: Color::RED, size: 3.5 })
+        .with(Name("Antares".to_owned()))
+        .with(Position(Vector::new(400.0, 400.0)))
+        .with(Speed(Vector::new(-2, 1.2)))
+        .with(Mass(10.0))
+        .build();
+    let sun = world.create_entity()
+        .with(Star { color: Color::YELLOW, size: 3.5 })
+        .with(Name("Sol".to_owned()))
+        .with(Position(Vector::new(500.0, 500.0)))
+        .with(Mass(50.0))
+        .build();
+    world.create_entity()
+        .with(Comet { size: 1.5, tail: 4_000.0, max_tail: 60.0 })
+        .with(Name("Halley".to_owned()))
+        .with(Position(Vector::new(-200.0, 650.0)))
+        .with(Speed(Vector::new(4.0, -1.5)))
+        .with(Mass(5.0))
+        .build();
+    let verdant = world.create_entity()
+        .with(Planet { color: Color::GREEN, radius: 20.0 })
+        .with(Name("Verdant".to_owned()))
+        .with(Position(Vector::new(750.0, 500.0)))
+        .with(Speed(Vector::new(0.0, -2.0)))
+        .with(Mass(20.0))
+        .with(Lagrange { primary: sun })
+        .with(Rotation(0.0))
+        .with(RotationSpeed(0.3))
+        .build();
+    let design = {
+        let shipyard = world.fetch::<Shipyard>();
+        let ship = world.fetch::<LevelShip>();
+        let mut loadout = world.fetch_mut::<Loadout>();
+        loadout.level = shipyard.design(&ship.0).cloned().unwrap_or_default();
+        loadout.current().clone()
+    };
+    let (players, difficulty, assists, design) = {
+        let players = *world.fetch::<Players>();
+        let difficulty = *world.fetch::<Difficulty>();
+        let assists = world.fetch::<Assists>().level;
+        world.fetch_mut::<Replay>().restart(players, difficulty, assists, design)
+    };
+    *world.fetch_mut::<Players>() = players;
+    world.insert(difficulty);
+    let assisted = Assists { level: assists, ..Assists::default() };
+    let rules = assisted.apply(difficulty.rules());
+    world.insert(rules);
+    world.insert(DifficultyTimeMod(rules.time_mod));
+    if players == Players::Single {
+        create_ship(world, &design, PLAYER_KEYS[0], None, Vector::new(600.0, 650.0));
+    } else {
+        create_ship(world, &design, PLAYER_KEYS[0], Some("Player 1"), Vector::new(600.0, 650.0));
+        create_ship(world, &design, PLAYER_KEYS[1], Some("Player 2"), Vector::new(650.0, 700.0));
+    }
+    world.create_entity()
+        .with(Landing::default())
+        .with(Name("Outpost Beta".to_owned()))
+        .with(Position(Vector::new(600.0, 300.0)))
+        .build();
+    world.create_entity()
+        .with(Landing::new(0.6))
+        .with(Name("Outpost Gamma".to_owned()))
+        .with(Position(Vector::new(250.0, 100.0)))
+        .build();
+    // Parked in the leading Lagrange point of Verdant, moving along with it
+    let trojan = LagrangePoint::L4.position(
+        (Vector::new(500.0, 500.0), 50.0),
+        (Vector::new(750.0, 500.0), 20.0),
+        Vector::new(0.0, -2.0),
+    );
+    world.create_entity()
+        .with(Landing::new(0.8))
+        .with(Name("Trojan Station".to_owned()))
+        .with(AtLagrange { secondary: verdant, point: LagrangePoint::L4 })
+        .with(Position(trojan))
+        .with(Speed(Vector::ZERO))
+        .build();
+    // Guards the small landing area
+    world.create_entity()
+        .with(Zone {
+            shape: ZoneShape::Rect { size: Vector::new(300.0, 100.0) },
+            effect: ZoneEffect::Force { accel: Vector::new(0.0, 0.02), period: Some(8.0) },
+        })
+        .with(Name("Solar wind".to_owned()))
+        .with(Position(Vector::new(250.0, 170.0)))
+        .build();
+    world.create_entity()
+        .with(Zone {
+            shape: ZoneShape::Circle { radius: 120.0 },
+            effect: ZoneEffect::Force { accel: Vector::new(-0.01, 0.0), period: None },
+        })
+        .with(Name("Ion stream".to_owned()))
+        .with(Position(Vector::new(1000.0, 250.0)))
+        .build();
+    let anomalies = [
+        (Anomaly::Weak(0.5), 80.0, Vector::new(430.0, 170.0)),
+        (Anomaly::Reversed, 70.0, Vector::new(950.0, 550.0)),
+        (Anomaly::Drift(30.0), 70.0, Vector::new(200.0, 450.0)),
+    ];
+    for &(anomaly, radius, position) in &anomalies {
+        world.create_entity()
+            .with(Zone {
+                shape: ZoneShape::Circle { radius },
+                effect: ZoneEffect::Magnetic(anomaly),
+            })
+            .with(Name("Magnetic anomaly".to_owned()))
+            .with(Position(position))
+            .build();
+    }
+    world.create_entity()
+        .with(PowerUp { boost: Boost::Booster, duration: 10.0 })
+        .with(Position(Vector::new(700.0, 600.0)))
+        .build();
+    world.create_entity()
+        .with(PowerUp { boost: Boost::GravityDampener, duration: 15.0 })
+        .with(Position(Vector::new(350.0, 220.0)))
+        .build();
+    world.create_entity()
+        .with(FuelStation { range: 40.0, rate: 5.0 })
+        .with(Name("Fuel depot".to_owned()))
+        .with(Position(Vector::new(800.0, 450.0)))
+        .build();
+    world.create_entity()
+        .with(Landing::new(1.5))
+        .with(Name("Depot Alpha".to_owned()))
+        .with(Position(Vector::new(1000.0, 250.0)))
+        .build();
+
+    *world.fetch_mut::<GameState>() = GameState::Started;
+    world.fetch_mut::<FitView>().requested = true;
+    world.fetch_mut::<LevelTime>().0 = Duration::default();
+    world.fetch_mut::<Leaderboard>().entries.clear();
+    world.insert(RunStats::default());
+    world.insert(Hints::default());
+}
+
+/// Creates a ship with its thrusters.
+pub fn create_ship(
+    world: &mut World,
+    design: &ShipDesign,
+    keys: KeyMap,
+    name: Option<&str>,
+    position: Vector,
+) -> Entity {
+    let rules = *world.entry::<Rules>().or_insert_with(Rules::default);
+    let mut ship = world.create_entity()
+        .with(Ship {
+            keys,
+            max_temp: 500.0,
+            temperature: -20.0,
+            temp_dec: 0.1,
+            fuel: rules.fuel * design.fuel,
+            max_fuel: rules.fuel * design.fuel,
+            spare_parts: rules.spare_parts,
+            strength: design.strength,
+        })
+        .with(Hull(design.hull.clone()))
+        .with(Position(position))
+        .with(Mass(design.mass))
+        .with(Speed(Vector::new(5.0, 0.0)))
+        .with(Rotation(60.0))
+        .with(RotationSpeed(1.0));
+    if let Some(name) = name {
+        ship = ship.with(Name(name.to_owned()));
+    }
+    let ship = ship.build();
+    for thruster in &design.thrusters {
+        world.create_entity().with(thruster.thruster(ship, design.mass, &keys)).build();
+    }
+    ship
+}
@@ -0,0 +1,140 @@
+//! Fuel stations, refilling the ships hovering nearby.
+
+use quicksilver::geom::Vector;
+use specs::prelude::*;
+
+use log::info;
+
+use crate::components::{FuelStation, Position, Refueling, Ship, Speed};
+use crate::physics::FrameDuration;
+use crate::plugin::Plugin;
+use crate::state::Rules;
+
+#[derive(SystemData)]
+struct RefuelData<'a> {
+    entities: Entities<'a>,
+    stations: ReadStorage<'a, FuelStation>,
+    positions: ReadStorage<'a, Position>,
+    speeds: ReadStorage<'a, Speed>,
+    ships: WriteStorage<'a, Ship>,
+    refueling: WriteStorage<'a, Refueling>,
+    rules: Read<'a, Rules>,
+    frame_duration: Read<'a, FrameDuration>,
+}
+
+/// Pumps fuel into the ships hovering by a station.
+///
+/// Hovering is the same as holding still in a landing area, the ship must not move faster than it
+/// may touch down.
+struct Refuel;
+
+impl<'a> System<'a> for Refuel {
+    type SystemData = RefuelData<'a>;
+
+    fn run(&mut self, mut d: Self::SystemData) {
+        let stations = (&d.entities, &d.stations, &d.positions, d.speeds.maybe())
+            .join()
+            .map(|(ent, station, pos, speed)| {
+                (ent, *station, pos.0, speed.map_or(Vector::ZERO, |s| s.0))
+            })
+            .collect::<Vec<_>>();
+        let dur = d.frame_duration.0.as_secs_f32();
+        let touchdown_speed = d.rules.touchdown_speed;
+        let ships = (&d.entities, &mut d.ships, &d.positions, &d.speeds).join();
+        for (ent, ship, ship_pos, ship_speed) in ships {
+            let hovering = stations
+                .iter()
+                .filter(|(_, _, _, speed)| (ship_speed.0 - *speed).len() <= touchdown_speed)
+                .find(|(_, station, pos, _)| ship_pos.0.distance(*pos) <= station.range);
+            match hovering {
+                // A full tank doesn't take any more, so the beam goes off
+                Some((station_ent, station, _, _)) if ship.fuel < ship.max_fuel => {
+                    ship.fuel = (ship.fuel + station.rate * dur).min(ship.max_fuel);
+                    let refueling = Refueling {
+                        station: *station_ent,
+                    };
+                    let previous = d
+                        .refueling
+                        .insert(ent, refueling)
+                        .expect("Refueling a dead ship");
+                    if previous.is_none() {
+                        info!("Ship {:?} started refueling at {:?}", ent, station_ent);
+                    }
+                }
+                _ => {
+                    d.refueling.remove(ent);
+                }
+            }
+        }
+    }
+}
+
+/// The fuel stations, refilling the ships on long levels.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct StationPlugin;
+
+impl<'a, 'b> Plugin<'a, 'b> for StationPlugin {
+    fn physics(&mut self, builder: DispatcherBuilder<'a, 'b>) -> DispatcherBuilder<'a, 'b> {
+        builder.with(Refuel, "refuel", &[])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TestWorld;
+
+    fn station(world: &mut TestWorld) -> Entity {
+        world
+            .world
+            .create_entity()
+            .with(FuelStation {
+                range: 40.0,
+                rate: 10.0,
+            })
+            .with(Position(Vector::ZERO))
+            .build()
+    }
+
+    fn fuel(world: &TestWorld, ship: Entity) -> f32 {
+        world.world.read_storage::<Ship>().get(ship).unwrap().fuel
+    }
+
+    fn drain(world: &mut TestWorld, ship: Entity) {
+        world
+            .world
+            .write_storage::<Ship>()
+            .get_mut(ship)
+            .unwrap()
+            .fuel = 0.0;
+    }
+
+    #[test]
+    fn hovering_refuels() {
+        let mut world = TestWorld::new();
+        station(&mut world);
+        let near = world.ship(Vector::new(30, 0));
+        let far = world.ship(Vector::new(100, 0));
+        drain(&mut world, near);
+        drain(&mut world, far);
+        world.step(100);
+        assert!((fuel(&world, near) - 10.0).abs() < 0.01);
+        assert!(world.world.read_storage::<Refueling>().contains(near));
+        assert_eq!(fuel(&world, far), 0.0);
+    }
+
+    #[test]
+    fn no_refueling_in_flight() {
+        let mut world = TestWorld::new();
+        station(&mut world);
+        let ship = world.ship(Vector::ZERO);
+        drain(&mut world, ship);
+        world
+            .world
+            .write_storage::<Speed>()
+            .insert(ship, Speed(Vector::new(0.0, 10.0)))
+            .unwrap();
+        world.step(1);
+        assert_eq!(fuel(&world, ship), 0.0);
+    }
+}
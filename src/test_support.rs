@@ -0,0 +1,122 @@
+//! Running the simulation without any graphics, for tests.
+
+use std::time::Duration;
+
+use quicksilver::geom::Vector;
+use specs::prelude::*;
+
+use crate::components::{Landing, Mass, Position, Rotation, RotationSpeed, Speed};
+use crate::input::{Replay, ReplayMode, PLAYER_KEYS};
+use crate::physics::{DifficultyTimeMod, GravityPlugin, ThrusterPlugin};
+use crate::powerups::PowerUpPlugin;
+use crate::repair::RepairPlugin;
+use crate::ships::ShipDesign;
+use crate::state::{create_ship, GameState, Rules};
+use crate::stations::StationPlugin;
+use crate::zones::ZonePlugin;
+use crate::{Game, GameBuilder};
+
+/// How long each simulated frame takes.
+pub const FRAME: Duration = Duration::from_millis(10);
+
+/// A game with an empty world and nothing drawn.
+///
+/// The game is running from the start and the difficulty doesn't speed the time up, so a second
+/// of the simulation is a second of the physics. Nothing gets recorded into a replay.
+pub struct TestWorld {
+    pub world: World,
+    dispatcher: Dispatcher<'static, 'static>,
+}
+
+impl TestWorld {
+    pub fn new() -> Self {
+        let Game {
+            mut world,
+            dispatcher,
+        } = GameBuilder::new()
+            .with_plugin(GravityPlugin::default())
+            .with_plugin(ZonePlugin)
+            .with_plugin(PowerUpPlugin)
+            .with_plugin(StationPlugin)
+            .with_plugin(RepairPlugin)
+            .with_plugin(ThrusterPlugin)
+            .with_fixed_step(FRAME)
+            .build();
+        world.insert(DifficultyTimeMod(1.0));
+        world.insert(GameState::Running);
+        world.fetch_mut::<Replay>().mode = ReplayMode::Finished;
+        TestWorld { world, dispatcher }
+    }
+
+    /// A body without any special behaviour, just moving and pulling others.
+    pub fn body(&mut self, position: Vector, speed: Vector, mass: f32) -> Entity {
+        self.world
+            .create_entity()
+            .with(Position(position))
+            .with(Speed(speed))
+            .with(Mass(mass))
+            .build()
+    }
+
+    /// Something spinning in place.
+    pub fn spinner(&mut self, rotation: f32, speed: f32) -> Entity {
+        self.world
+            .create_entity()
+            .with(Rotation(rotation))
+            .with(RotationSpeed(speed))
+            .build()
+    }
+
+    /// The first player's ship, stopped.
+    pub fn ship(&mut self, position: Vector) -> Entity {
+        let design = ShipDesign::default();
+        let ship = create_ship(&mut self.world, &design, PLAYER_KEYS[0], None, position);
+        self.world
+            .write_storage::<Speed>()
+            .insert(ship, Speed(Vector::ZERO))
+            .expect("Fresh ship");
+        self.world
+            .write_storage::<RotationSpeed>()
+            .insert(ship, RotationSpeed(0.0))
+            .expect("Fresh ship");
+        ship
+    }
+
+    pub fn landing(&mut self, position: Vector) -> Entity {
+        self.world
+            .create_entity()
+            .with(Landing::default())
+            .with(Position(position))
+            .build()
+    }
+
+    /// Runs the given number of frames, each `FRAME` long.
+    pub fn step(&mut self, frames: usize) {
+        for _ in 0..frames {
+            self.dispatcher.dispatch(&self.world);
+            self.world.maintain();
+        }
+    }
+
+    /// Runs long enough for the ships holding still in a landing area to land there.
+    pub fn hold(&mut self) {
+        let capture_time = self.world.fetch::<Rules>().capture_time;
+        self.step((capture_time.as_nanos() / FRAME.as_nanos()) as usize + 1);
+    }
+
+    pub fn position(&self, ent: Entity) -> Vector {
+        self.world.read_storage::<Position>().get(ent).expect("No position").0
+    }
+
+    pub fn speed(&self, ent: Entity) -> Vector {
+        self.world.read_storage::<Speed>().get(ent).expect("No speed").0
+    }
+
+    pub fn rotation(&self, ent: Entity) -> f32 {
+        self.world.read_storage::<Rotation>().get(ent).expect("No rotation").0
+    }
+
+    pub fn state(&self) -> GameState {
+        *self.world.fetch::<GameState>()
+    }
+}
@@ -0,0 +1,226 @@
+//! On-screen controls, for playing on a touch screen.
+//!
+//! The controls stand for the keys of the first player, so the touches work just like the
+//! keyboard and end up in the replays too. Each finger presses the button under it, sliding to
+//! another button switches to it. The stick turns the ship, pushed to either side it fires the
+//! thrusters turning that way.
+
+use std::collections::HashMap;
+
+use quicksilver::geom::{Circle, Rectangle, Vector};
+use quicksilver::lifecycle::{Key, PointerId};
+
+use crate::input::PLAYER_KEYS;
+use crate::render::Viewport;
+
+/// The buttons, as parts of the view: the label, the action (in the order of `KeyMap::keys`)
+/// and where the button is.
+pub const TOUCH_BUTTONS: [(&str, usize, Rectangle); 4] = [
+    ("Repair", 5, button(0.02, 0.44)),
+    ("Center", 4, button(0.72, 0.78)),
+    ("Back", 1, button(0.86, 0.78)),
+    ("Thrust", 0, button(0.86, 0.56)),
+];
+
+const fn button(x: f32, y: f32) -> Rectangle {
+    Rectangle {
+        pos: Vector { x, y },
+        size: Vector { x: 0.12, y: 0.2 },
+    }
+}
+
+/// The middle of the stick, as a part of the view.
+const STICK_CENTER: Vector = Vector { x: 0.14, y: 0.8 };
+/// How far the stick reaches, as a part of the view height.
+const STICK_RADIUS: f32 = 0.13;
+/// How far the stick needs to be pushed to turn, as a part of its reach.
+const STICK_DEAD_ZONE: f32 = 0.3;
+const ACTION_LEFT: usize = 2;
+const ACTION_RIGHT: usize = 3;
+
+/// Where the stick is in the world, with the view around it.
+pub fn stick_area(viewport: &Viewport) -> Circle {
+    let rect = viewport.rect;
+    let center = Vector::new(STICK_CENTER.x * rect.size.x, STICK_CENTER.y * rect.size.y);
+    Circle::new(rect.pos + center, STICK_RADIUS * rect.size.y)
+}
+
+/// The fingers on the screen.
+#[derive(Clone, Debug, Default)]
+pub struct Touches {
+    /// The button each finger presses.
+    pub pressed: HashMap<PointerId, usize>,
+    /// The finger holding the stick.
+    stick: Option<PointerId>,
+    /// How far the stick is pushed, as a part of its reach.
+    pub stick_push: Vector,
+}
+
+impl Touches {
+    /// Turns a point of the window into a part of the view.
+    fn relative(viewport: &Viewport, location: Vector, window_size: Vector) -> Vector {
+        let rect = viewport.rect;
+        let offset = viewport.unproject(location, window_size) - rect.pos;
+        Vector::new(offset.x / rect.size.x, offset.y / rect.size.y)
+    }
+
+    /// The button under a point of the window.
+    fn button_at(viewport: &Viewport, location: Vector, window_size: Vector) -> Option<usize> {
+        let relative = Self::relative(viewport, location, window_size);
+        TOUCH_BUTTONS
+            .iter()
+            .position(|(_, _, button)| button.contains(relative))
+    }
+
+    /// How far a finger at the point of the window pushes the stick, as a part of its reach.
+    ///
+    /// Can be more than the reach, if the finger is outside of the stick.
+    fn stick_push(viewport: &Viewport, location: Vector, window_size: Vector) -> Vector {
+        let area = stick_area(viewport);
+        (viewport.unproject(location, window_size) - area.pos) / area.radius
+    }
+
+    /// The key of the action, `true` for pressing it.
+    fn key(action: usize, down: bool) -> (Key, bool) {
+        (PLAYER_KEYS[0].keys()[action], down)
+    }
+
+    /// The key of the button, `true` for pressing it.
+    fn button_key(button: usize, down: bool) -> (Key, bool) {
+        let (_, action, _) = TOUCH_BUTTONS[button];
+        Self::key(action, down)
+    }
+
+    /// The turning action the stick pushed this far does.
+    fn turn(push: Vector) -> Option<usize> {
+        if push.x < -STICK_DEAD_ZONE {
+            Some(ACTION_LEFT)
+        } else if push.x > STICK_DEAD_ZONE {
+            Some(ACTION_RIGHT)
+        } else {
+            None
+        }
+    }
+
+    /// Moves the stick.
+    ///
+    /// Returns the keys to press and release.
+    fn steer(&mut self, push: Vector) -> Vec<(Key, bool)> {
+        // The stick itself stays at its edge when the finger slips further
+        let push = if push.len() > 1.0 { push.normalize() } else { push };
+        let old = Self::turn(self.stick_push);
+        let new = Self::turn(push);
+        self.stick_push = push;
+        if old == new {
+            return Vec::new();
+        }
+        let released = old.map(|action| Self::key(action, false));
+        let pressed = new.map(|action| Self::key(action, true));
+        released.into_iter().chain(pressed).collect()
+    }
+
+    /// A finger (or the mouse) went down or up, at the location in the window.
+    ///
+    /// Returns the keys to press and release, or `None` if the finger isn't on any control (and
+    /// wasn't when it went down).
+    pub fn input(
+        &mut self,
+        viewport: &Viewport,
+        pointer: PointerId,
+        location: Vector,
+        down: bool,
+        window_size: Vector,
+    ) -> Option<Vec<(Key, bool)>> {
+        if !down {
+            if self.stick == Some(pointer) {
+                self.stick = None;
+                return Some(self.steer(Vector::ZERO));
+            }
+            let button = self.pressed.remove(&pointer)?;
+            return Some(vec![Self::button_key(button, false)]);
+        }
+        let push = Self::stick_push(viewport, location, window_size);
+        if self.stick.is_none() && push.len() <= 1.0 {
+            self.stick = Some(pointer);
+            return Some(self.steer(push));
+        }
+        let button = Self::button_at(viewport, location, window_size)?;
+        self.pressed.insert(pointer, button);
+        Some(vec![Self::button_key(button, true)])
+    }
+
+    /// Lets go of all the controls.
+    ///
+    /// Returns the keys to release.
+    pub fn release(&mut self) -> Vec<(Key, bool)> {
+        self.stick = None;
+        let mut released = self.steer(Vector::ZERO);
+        released.extend(
+            self.pressed
+                .drain()
+                .map(|(_, button)| Self::button_key(button, false)),
+        );
+        released
+    }
+
+    /// A finger (or the mouse) moved.
+    ///
+    /// Returns the keys to press and release.
+    pub fn moved(
+        &mut self,
+        viewport: &Viewport,
+        pointer: PointerId,
+        location: Vector,
+        window_size: Vector,
+    ) -> Vec<(Key, bool)> {
+        if self.stick == Some(pointer) {
+            return self.steer(Self::stick_push(viewport, location, window_size));
+        }
+        let old = match self.pressed.get(&pointer) {
+            Some(old) => *old,
+            None => return Vec::new(),
+        };
+        match Self::button_at(viewport, location, window_size) {
+            Some(new) if new != old => {
+                self.pressed.insert(pointer, new);
+                vec![Self::button_key(old, false), Self::button_key(new, true)]
+            }
+            // Slipping off the button doesn't let go, the finger is still on the screen
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buttons_follow_the_view() {
+        let mut viewport = Viewport::default();
+        let window = Vector::new(1000, 500);
+        let corner = Touches::button_at(&viewport, Vector::new(50, 250), window);
+        assert_eq!(corner, Some(0));
+        assert_eq!(Touches::button_key(0, true), (PLAYER_KEYS[0].keys()[5], true));
+        assert_eq!(Touches::button_at(&viewport, Vector::new(500, 250), window), None);
+        // Zooming and moving the camera doesn't move the buttons
+        viewport.zoom = 2.0;
+        viewport.rect = Rectangle::new(Vector::new(-300, 200), viewport.rect.size / 2.0);
+        let thrust = Touches::button_at(&viewport, Vector::new(950, 300), window);
+        assert_eq!(thrust, Some(3));
+        let push = Touches::stick_push(&viewport, Vector::new(140, 400), window);
+        assert!(push.len() < 0.1, "{:?}", push);
+    }
+
+    #[test]
+    fn stick_turns() {
+        let mut touches = Touches::default();
+        let [_, _, left, right, _, _] = PLAYER_KEYS[0].keys();
+        assert!(touches.steer(Vector::new(0.2, 0.5)).is_empty());
+        assert_eq!(touches.steer(Vector::new(-0.5, 0.0)), vec![(left, true)]);
+        assert_eq!(touches.steer(Vector::new(3.0, 0.0)), vec![(left, false), (right, true)]);
+        assert_eq!(touches.stick_push, Vector::new(1.0, 0.0));
+        assert_eq!(touches.release(), vec![(right, false)]);
+        assert_eq!(touches.stick_push, Vector::ZERO);
+    }
+}
@@ -0,0 +1,378 @@
+//! Teaching the controls while playing.
+//!
+//! The tutorial is a sequence of steps, each showing a hint until the player does what it asks.
+//! Later, the hints point out what the player does wrong.
+
+use std::time::Duration;
+
+use quicksilver::geom::Vector;
+use specs::prelude::*;
+
+use log::info;
+
+use crate::components::{Landing, Mass, Planet, Position, RotationSpeed, Ship, Speed};
+use crate::input::{Attract, KeyMap, Keys, Replay, ReplayMode};
+use crate::physics::DifficultyTimeMod;
+use crate::plugin::Plugin;
+use crate::render::Viewport;
+use crate::state::{GameState, LevelTime, Rules};
+
+/// What to draw attention to while a step is shown.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Highlight {
+    Nothing,
+    Ships,
+    Landings,
+}
+
+/// What the player needs to do to finish a step.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Trigger {
+    /// Start or unpause the game.
+    Run,
+    /// Fire the main or the back thruster of any ship.
+    Thrust,
+    /// Fire a side thruster of any ship.
+    Turn,
+    /// Center the view on a ship.
+    Center,
+    /// Change the zoom.
+    Zoom,
+    /// Pause the game.
+    Pause,
+    /// Win the level.
+    Land,
+}
+
+pub struct Step {
+    pub text: &'static str,
+    pub highlight: Highlight,
+    trigger: Trigger,
+}
+
+const STEPS: &[Step] = &[
+    Step {
+        text: "Press Space to start the level",
+        highlight: Highlight::Nothing,
+        trigger: Trigger::Run,
+    },
+    Step {
+        text: "Press Up to fire the main thruster",
+        highlight: Highlight::Ships,
+        trigger: Trigger::Thrust,
+    },
+    Step {
+        text: "Left and Right fire the side thrusters to turn",
+        highlight: Highlight::Ships,
+        trigger: Trigger::Turn,
+    },
+    Step {
+        text: "Press Home to center the view on your ship",
+        highlight: Highlight::Ships,
+        trigger: Trigger::Center,
+    },
+    Step {
+        text: "Use + and - to zoom",
+        highlight: Highlight::Nothing,
+        trigger: Trigger::Zoom,
+    },
+    Step {
+        text: "Space pauses the game when you need to think",
+        highlight: Highlight::Nothing,
+        trigger: Trigger::Pause,
+    },
+    Step {
+        text: "Hold still in the landing area to land. Mind the stars, they heat the ship up",
+        highlight: Highlight::Landings,
+        trigger: Trigger::Land,
+    },
+];
+
+/// Progress through the tutorial.
+#[derive(Clone, Debug, Default)]
+pub struct Tutorial {
+    step: usize,
+    /// The zoom when the current step started, to notice the player zooming.
+    zoom: Option<f32>,
+    skipped: bool,
+}
+
+impl Tutorial {
+    /// The step being taught now, if the tutorial is not over.
+    pub fn current(&self) -> Option<&'static Step> {
+        if self.skipped {
+            None
+        } else {
+            STEPS.get(self.step)
+        }
+    }
+
+    pub fn skip(&mut self) {
+        info!("Tutorial skipped");
+        self.skipped = true;
+    }
+}
+
+#[derive(SystemData)]
+struct AdvanceTutorialData<'a> {
+    tutorial: Write<'a, Tutorial>,
+    state: ReadExpect<'a, GameState>,
+    keys: Read<'a, Keys>,
+    viewport: ReadExpect<'a, Viewport>,
+    replay: Read<'a, Replay>,
+    attract: Read<'a, Attract>,
+    ships: ReadStorage<'a, Ship>,
+}
+
+/// Moves to the next step once the player did what the current one asks.
+struct AdvanceTutorial;
+
+impl<'a> System<'a> for AdvanceTutorial {
+    type SystemData = AdvanceTutorialData<'a>;
+
+    fn run(&mut self, mut d: Self::SystemData) {
+        // Only the player can learn something, not the replays
+        if d.replay.mode != ReplayMode::Recording || d.attract.0.is_some() {
+            return;
+        }
+        let step = match d.tutorial.current() {
+            Some(step) => step,
+            None => return,
+        };
+        let zoom = *d.tutorial.zoom.get_or_insert(d.viewport.zoom);
+        let keys = &d.keys;
+        let ships = &d.ships;
+        let pressed =
+            |wanted: &dyn Fn(&KeyMap) -> bool| ships.join().any(|ship| wanted(&ship.keys));
+        let done = match step.trigger {
+            Trigger::Run => *d.state == GameState::Running,
+            Trigger::Thrust => pressed(&|k| keys.contains(&k.forward) || keys.contains(&k.back)),
+            Trigger::Turn => pressed(&|k| keys.contains(&k.left) || keys.contains(&k.right)),
+            Trigger::Center => pressed(&|k| keys.contains(&k.homing)),
+            Trigger::Zoom => (d.viewport.zoom - zoom).abs() > f32::EPSILON,
+            Trigger::Pause => *d.state == GameState::Paused,
+            Trigger::Land => *d.state == GameState::Won,
+        };
+        if done {
+            info!("Tutorial step done: {}", step.text);
+            d.tutorial.step += 1;
+            d.tutorial.zoom = None;
+        }
+    }
+}
+
+/// A common mistake the player gets a hint about.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Hint {
+    /// Coming to a planet or a landing area too fast.
+    TooFast,
+    /// The ship spins out of control.
+    Spinning,
+    /// The ship is flying away from everything.
+    Drifting,
+}
+
+impl Hint {
+    pub fn text(self) -> &'static str {
+        match self {
+            Hint::TooFast => "Too fast! Fire the back thruster to slow down before you get there",
+            Hint::Spinning => "Spinning out of control? Tap the opposite side thruster to stop",
+            Hint::Drifting => "Drifting away? Press Z to see everything and head back",
+        }
+    }
+}
+
+/// How long a hint stays shown, in the level time.
+const HINT_DURATION: Duration = Duration::from_secs(5);
+/// A ship closer than this to a planet's surface or a landing area is approaching it.
+const HINT_APPROACH_DISTANCE: f32 = 100.0;
+/// Approaching faster than this many times the touchdown speed is too fast.
+const HINT_APPROACH_SPEED: f32 = 2.0;
+/// Turning faster than this is spinning out of control, in degrees per real second.
+const HINT_SPIN: f32 = 360.0;
+/// Farther than this from every body is drifting away.
+const HINT_DRIFT_DISTANCE: f32 = 1_500.0;
+
+/// The hints of the current level.
+///
+/// Each hint is shown only once per level.
+#[derive(Clone, Debug, Default)]
+pub struct Hints {
+    shown: Vec<Hint>,
+    /// The hint being shown and when it appeared.
+    current: Option<(Hint, Duration)>,
+}
+
+impl Hints {
+    /// The hint to show now.
+    pub fn current(&self, time: Duration) -> Option<Hint> {
+        self.current
+            .filter(|(_, since)| time < *since + HINT_DURATION)
+            .map(|(hint, _)| hint)
+    }
+}
+
+#[derive(SystemData)]
+struct DetectHintsData<'a> {
+    hints: Write<'a, Hints>,
+    state: ReadExpect<'a, GameState>,
+    time: Read<'a, LevelTime>,
+    time_mod: ReadExpect<'a, DifficultyTimeMod>,
+    rules: Read<'a, Rules>,
+    replay: Read<'a, Replay>,
+    attract: Read<'a, Attract>,
+    ships: ReadStorage<'a, Ship>,
+    planets: ReadStorage<'a, Planet>,
+    landings: ReadStorage<'a, Landing>,
+    masses: ReadStorage<'a, Mass>,
+    positions: ReadStorage<'a, Position>,
+    speeds: ReadStorage<'a, Speed>,
+    rotation_speeds: ReadStorage<'a, RotationSpeed>,
+}
+
+impl DetectHintsData<'_> {
+    /// Is the ship getting to a planet or a landing area too fast?
+    fn too_fast(&self, pos: Vector, speed: Vector) -> bool {
+        let limit = self.rules.touchdown_speed * HINT_APPROACH_SPEED;
+        let planets = (&self.planets, &self.positions, self.speeds.maybe())
+            .join()
+            .map(|(planet, p, s)| (p.0, s, planet.radius));
+        let landings = (&self.landings, &self.positions, self.speeds.maybe())
+            .join()
+            .map(|(landing, p, s)| (p.0, s, landing.radius(self.rules.land_distance)));
+        planets
+            .chain(landings)
+            .any(|(target, target_speed, radius)| {
+                let offset = target - pos;
+                let distance = offset.len();
+                let rel_speed = speed - target_speed.map(|s| s.0).unwrap_or(Vector::ZERO);
+                let closing = if distance > 0.0 {
+                    offset.dot(rel_speed) / distance
+                } else {
+                    0.0
+                };
+                distance - radius <= HINT_APPROACH_DISTANCE && closing > limit
+            })
+    }
+
+    /// Is the ship far from everything?
+    fn drifting(&self, pos: Vector) -> bool {
+        (&self.masses, &self.positions, !&self.ships)
+            .join()
+            .all(|(_, p, _)| p.0.distance(pos) > HINT_DRIFT_DISTANCE)
+    }
+}
+
+/// Notices the common mistakes and picks a hint about them.
+struct DetectHints;
+
+impl<'a> System<'a> for DetectHints {
+    type SystemData = DetectHintsData<'a>;
+
+    fn run(&mut self, mut d: Self::SystemData) {
+        let playing = *d.state == GameState::Running
+            && d.replay.mode == ReplayMode::Recording
+            && d.attract.0.is_none();
+        if !playing || d.hints.current(d.time.0).is_some() {
+            return;
+        }
+        let ships = (&d.ships, &d.positions, &d.speeds, d.rotation_speeds.maybe()).join();
+        let mut found = Vec::new();
+        for (_, pos, speed, spin) in ships {
+            if d.too_fast(pos.0, speed.0) {
+                found.push(Hint::TooFast);
+            }
+            let spin = spin.map(|s| s.0.abs()).unwrap_or_default() * d.time_mod.0;
+            if spin > HINT_SPIN {
+                found.push(Hint::Spinning);
+            }
+            if d.drifting(pos.0) {
+                found.push(Hint::Drifting);
+            }
+        }
+        let new = found.into_iter().find(|hint| !d.hints.shown.contains(hint));
+        if let Some(hint) = new {
+            info!("Hint: {:?}", hint);
+            let time = d.time.0;
+            d.hints.shown.push(hint);
+            d.hints.current = Some((hint, time));
+        }
+    }
+}
+
+/// Teaches the controls, step by step, and hints at what goes wrong.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TutorialPlugin;
+
+impl<'a, 'b> Plugin<'a, 'b> for TutorialPlugin {
+    fn systems(&mut self, builder: DispatcherBuilder<'a, 'b>) -> DispatcherBuilder<'a, 'b> {
+        builder
+            .with(AdvanceTutorial, "tutorial", &[])
+            .with(DetectHints, "hints", &[])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::PLAYER_KEYS;
+    use crate::Game;
+
+    fn current(game: &Game) -> Option<&'static str> {
+        game.world()
+            .fetch::<Tutorial>()
+            .current()
+            .map(|step| step.text)
+    }
+
+    #[test]
+    fn steps_advance_on_triggers() {
+        let mut game = Game::new();
+        game.step();
+        assert_eq!(current(&game), Some(STEPS[0].text));
+
+        game.world_mut().fetch_mut::<GameState>().toggle();
+        game.step();
+        assert_eq!(current(&game), Some(STEPS[1].text));
+        // Nothing pressed, nothing learned
+        game.step();
+        assert_eq!(current(&game), Some(STEPS[1].text));
+
+        game.world_mut()
+            .fetch_mut::<Keys>()
+            .insert(PLAYER_KEYS[0].forward);
+        game.step();
+        assert_eq!(current(&game), Some(STEPS[2].text));
+
+        game.world_mut().fetch_mut::<Tutorial>().skip();
+        assert_eq!(current(&game), None);
+    }
+
+    #[test]
+    fn spinning_hint_once() {
+        let mut game = Game::new();
+        game.world_mut().fetch_mut::<Tutorial>().skip();
+        game.world_mut().fetch_mut::<GameState>().toggle();
+        let spin = |game: &mut Game| {
+            let world = game.world_mut();
+            let (ships, mut speeds) =
+                world.system_data::<(ReadStorage<Ship>, WriteStorage<RotationSpeed>)>();
+            for (_, speed) in (&ships, &mut speeds).join() {
+                speed.0 = 10_000.0;
+            }
+        };
+        spin(&mut game);
+        game.step();
+        let time = game.world().fetch::<LevelTime>().0;
+        assert_eq!(
+            game.world().fetch::<Hints>().current(time),
+            Some(Hint::Spinning)
+        );
+
+        game.world_mut().fetch_mut::<Hints>().current = None;
+        spin(&mut game);
+        game.step();
+        let time = game.world().fetch::<LevelTime>().0;
+        assert_eq!(game.world().fetch::<Hints>().current(time), None);
+    }
+}
@@ -0,0 +1,192 @@
+//! Areas of the space affecting whatever flies through them.
+
+use quicksilver::geom::Vector;
+use specs::prelude::*;
+
+use crate::components::{Interference, Position, Ship, Speed, Zone, ZoneEffect};
+use crate::physics::{by_position, DifficultyTimeMod, FrameDuration};
+use crate::plugin::Plugin;
+use crate::state::LevelTime;
+
+#[derive(SystemData)]
+struct PushForceFieldsData<'a> {
+    frame_duration: Read<'a, FrameDuration>,
+    difficulty_mod: ReadExpect<'a, DifficultyTimeMod>,
+    time: Read<'a, LevelTime>,
+    zones: ReadStorage<'a, Zone>,
+    positions: ReadStorage<'a, Position>,
+    speeds: WriteStorage<'a, Speed>,
+}
+
+/// Accelerates everything inside the force fields, the same way the gravity does.
+struct PushForceFields;
+
+impl<'a> System<'a> for PushForceFields {
+    type SystemData = PushForceFieldsData<'a>;
+
+    fn run(&mut self, mut d: Self::SystemData) {
+        let time = d.time.0;
+        let mut zones = (&d.zones, &d.positions).join().collect::<Vec<_>>();
+        zones.sort_by(|(_, a), (_, b)| by_position(a, b));
+        let fields = zones
+            .into_iter()
+            .map(|(zone, pos)| (zone.shape, pos.0, zone.effect.force(time)))
+            .filter(|(_, _, force)| *force != Vector::ZERO)
+            .collect::<Vec<_>>();
+        if fields.is_empty() {
+            return;
+        }
+        let dur = d.frame_duration.0.as_secs_f32() * d.difficulty_mod.0;
+        // The zones themselves don't get pushed around
+        (&mut d.speeds, &d.positions, !&d.zones)
+            .par_join()
+            .for_each(|(speed, pos, _)| {
+                let accel = fields
+                    .iter()
+                    .filter(|(shape, center, _)| shape.contains(*center, pos.0))
+                    .map(|(_, _, force)| *force)
+                    .fold(Vector::ZERO, |a, b| a + b);
+                speed.0 += accel * dur;
+            });
+    }
+}
+
+#[derive(SystemData)]
+struct InterfereData<'a> {
+    entities: Entities<'a>,
+    zones: ReadStorage<'a, Zone>,
+    ships: ReadStorage<'a, Ship>,
+    positions: ReadStorage<'a, Position>,
+    interferences: WriteStorage<'a, Interference>,
+}
+
+/// Finds out how the magnetic anomalies disturb the controls of each ship.
+struct Interfere;
+
+impl<'a> System<'a> for Interfere {
+    type SystemData = InterfereData<'a>;
+
+    fn run(&mut self, mut d: Self::SystemData) {
+        let mut zones = (&d.zones, &d.positions).join().collect::<Vec<_>>();
+        zones.sort_by(|(_, a), (_, b)| by_position(a, b));
+        let anomalies = zones
+            .into_iter()
+            .filter_map(|(zone, pos)| match zone.effect {
+                ZoneEffect::Magnetic(anomaly) => Some((zone.shape, pos.0, anomaly)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        for (_, pos, ent) in (&d.ships, &d.positions, &d.entities).join() {
+            let mut interference = Interference::default();
+            let inside = anomalies
+                .iter()
+                .filter(|(shape, center, _)| shape.contains(*center, pos.0));
+            for (_, _, anomaly) in inside {
+                anomaly.disturb(&mut interference);
+            }
+            if interference == Interference::default() {
+                d.interferences.remove(ent);
+            } else {
+                d.interferences
+                    .insert(ent, interference)
+                    .expect("Disturbing a dead ship");
+            }
+        }
+    }
+}
+
+/// The zones in the level, like force fields and magnetic anomalies.
+///
+/// Needs to go before the `ThrusterPlugin`, its thrusters wait for the `interfere` system.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ZonePlugin;
+
+impl<'a, 'b> Plugin<'a, 'b> for ZonePlugin {
+    fn physics(&mut self, builder: DispatcherBuilder<'a, 'b>) -> DispatcherBuilder<'a, 'b> {
+        builder
+            .with(PushForceFields, "force-fields", &[])
+            .with(Interfere, "interfere", &[])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::{Anomaly, ZoneShape};
+    use crate::input::{Keys, PLAYER_KEYS};
+    use crate::test_support::TestWorld;
+
+    fn field(world: &mut TestWorld, shape: ZoneShape, period: Option<f32>) {
+        let effect = ZoneEffect::Force {
+            accel: Vector::new(0.0, 0.1),
+            period,
+        };
+        world
+            .world
+            .create_entity()
+            .with(Zone { shape, effect })
+            .with(Position(Vector::ZERO))
+            .build();
+    }
+
+    #[test]
+    fn force_field_pushes_only_inside() {
+        let mut world = TestWorld::new();
+        field(
+            &mut world,
+            ZoneShape::Rect {
+                size: Vector::new(100, 100),
+            },
+            None,
+        );
+        let inside = world.body(Vector::new(40, 40), Vector::ZERO, 0.0);
+        let outside = world.body(Vector::new(60, 0), Vector::ZERO, 0.0);
+        world.step(1);
+        assert!(world.speed(inside).y > 0.0);
+        assert_eq!(world.speed(inside).x, 0.0);
+        assert_eq!(world.speed(outside), Vector::ZERO);
+    }
+
+    #[test]
+    fn anomaly_reverses_controls() {
+        let mut world = TestWorld::new();
+        let effect = ZoneEffect::Magnetic(Anomaly::Reversed);
+        world
+            .world
+            .create_entity()
+            .with(Zone {
+                shape: ZoneShape::Circle { radius: 50.0 },
+                effect,
+            })
+            .with(Position(Vector::ZERO))
+            .build();
+        let ship = world.ship(Vector::ZERO);
+        // Pressing back fires the main thruster
+        world.world.fetch_mut::<Keys>().insert(PLAYER_KEYS[0].back);
+        world.step(1);
+        let reversed = world.speed(ship);
+        assert!(world.world.read_storage::<Interference>().contains(ship));
+
+        let mut world = TestWorld::new();
+        let ship = world.ship(Vector::ZERO);
+        world
+            .world
+            .fetch_mut::<Keys>()
+            .insert(PLAYER_KEYS[0].forward);
+        world.step(1);
+        assert_eq!(world.speed(ship), reversed);
+    }
+
+    #[test]
+    fn oscillating_field_swings() {
+        let mut world = TestWorld::new();
+        field(&mut world, ZoneShape::Circle { radius: 50.0 }, Some(1.0));
+        let body = world.body(Vector::ZERO, Vector::ZERO, 0.0);
+        // The first half of the period pushes one way, the second the other
+        world.step(50);
+        let half = world.speed(body).y;
+        assert!(half > 0.0);
+        world.step(50);
+        assert!(world.speed(body).y.abs() < half / 10.0);
+    }
+}